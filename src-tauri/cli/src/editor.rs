@@ -0,0 +1,54 @@
+use notura_core::error::{AppError, AppResult, Context};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Text written by the user's editor. The file is kept until `discard` so an
+// edit that could not be saved is not lost.
+pub struct EditedText {
+    pub text: String,
+    pub path: PathBuf,
+}
+
+impl EditedText {
+    pub fn discard(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn editor_command() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() })
+}
+
+// Open `text` in the user's editor and return what they saved. The editor may
+// carry arguments, e.g. `code --wait`.
+pub fn edit_text(text: &str, note_id: &str) -> AppResult<EditedText> {
+    let path = std::env::temp_dir().join(format!("notura-{}.md", note_id));
+    fs::write(&path, text)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    let editor = editor_command();
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+
+    let status = Command::new(program)
+        .args(parts)
+        .arg(&path)
+        .status()
+        .with_context(|| format!("Failed to start editor {}", program))?;
+    if !status.success() {
+        return Err(AppError::Internal(format!(
+            "Editor {} exited with {}, the note was not changed",
+            program, status
+        )));
+    }
+
+    let text = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Ok(EditedText { text, path })
+}
@@ -0,0 +1,409 @@
+// Command-line companion to the desktop app. It opens the same vault, so
+// notes can be captured and scripted while the app is closed or running;
+// SQLite's WAL mode and busy timeout keep the two from stepping on each other.
+mod editor;
+mod rpc;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use notura_core::error::{AppError, AppResult, Context};
+use notura_core::html;
+use notura_core::secrets::{NoteFindings, SecretPolicy};
+use notura_core::{Collection, Note, NoteStore};
+use serde::Serialize;
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "notura-cli", version, about = "Capture and script notes in your Notura vault")]
+struct Cli {
+    /// Directory holding notura.db, defaults to the desktop app's data directory
+    #[arg(long, env = "NOTURA_DATA_DIR", global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// Passphrase of an encrypted vault
+    #[arg(long, env = "NOTURA_VAULT_PASSPHRASE", hide_env_values = true, global = true)]
+    passphrase: Option<String>,
+
+    /// Print JSON instead of text, for piping into other tools
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a note from an argument or from stdin
+    New {
+        /// Defaults to the first line of the content
+        #[arg(short, long)]
+        title: Option<String>,
+        /// Collection id or name
+        #[arg(short, long)]
+        collection: Option<String>,
+        /// Note text; read from stdin when omitted or `-`
+        content: Option<String>,
+    },
+    /// List notes, most recently updated first
+    List {
+        /// Only notes in this collection (id or name)
+        #[arg(short, long)]
+        collection: Option<String>,
+        /// Only notes with this tag
+        #[arg(short, long)]
+        tag: Option<String>,
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Print a note's content
+    Show {
+        /// Note id or a unique prefix of it
+        note: String,
+    },
+    /// Edit a note in $VISUAL or $EDITOR
+    Edit {
+        note: String,
+    },
+    /// Full-text search over notes and their attachments
+    Search {
+        #[arg(required = true, num_args = 1..)]
+        query: Vec<String>,
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Show, add or remove tags
+    Tag {
+        note: String,
+        #[arg(short, long)]
+        add: Vec<String>,
+        #[arg(short, long)]
+        remove: Vec<String>,
+    },
+    /// Move a note into a collection, or out of any collection when omitted
+    Mv {
+        note: String,
+        collection: Option<String>,
+    },
+    /// Export notes as markdown, JSON or a zip archive with their files
+    Export {
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Markdown)]
+        format: ExportFormat,
+        /// Write here instead of stdout (or the exports directory for archives)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Export every note
+        #[arg(long, conflicts_with = "notes")]
+        all: bool,
+        /// What to do about API keys, passwords and other secrets in the notes
+        #[arg(long, value_enum, default_value_t = Secrets::Block)]
+        secrets: Secrets,
+        notes: Vec<String>,
+    },
+    /// Import notes from a JSON export or a markdown file
+    Import {
+        file: PathBuf,
+    },
+    /// Serve JSON-RPC on stdin/stdout for editor plugins and other tools
+    Rpc {
+        /// How often to check for changes once a client subscribes
+        #[arg(long, default_value_t = 1000)]
+        poll_ms: u64,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Markdown,
+    Json,
+    Archive,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Secrets {
+    /// Refuse to export keys and passwords, list emails and card numbers
+    Block,
+    /// Export anyway and list them
+    Warn,
+    /// Export with them replaced by a placeholder
+    Redact,
+}
+
+impl From<Secrets> for SecretPolicy {
+    fn from(secrets: Secrets) -> Self {
+        match secrets {
+            Secrets::Block => SecretPolicy::Block,
+            Secrets::Warn => SecretPolicy::Warn,
+            Secrets::Redact => SecretPolicy::Redact,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json = cli.json;
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if json {
+                eprintln!("{}", serde_json::to_string(&e).unwrap_or_else(|_| e.to_string()));
+            } else {
+                eprintln!("Error: {}", e);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> AppResult<()> {
+    let data_dir = cli.data_dir
+        .or_else(notura_core::default_data_dir)
+        .ok_or_else(|| AppError::Validation("Could not find the Notura data directory, pass --data-dir".to_string()))?;
+    let store = match cli.passphrase.as_deref() {
+        Some(passphrase) => NoteStore::open_encrypted(data_dir, passphrase).await?,
+        None => NoteStore::open(data_dir).await?,
+    };
+    let json = cli.json;
+
+    let result = match cli.command {
+        Command::New { title, collection, content } => {
+            let content = match content.as_deref() {
+                Some("-") | None => read_stdin()?,
+                Some(content) => content.to_string(),
+            };
+            let title = title.unwrap_or_else(|| title_from_content(&content));
+            let collection = match collection {
+                Some(collection) => Some(resolve_collection(&store, &collection).await?.id),
+                None => None,
+            };
+
+            let note = store.create_note(&title, &content, collection.as_deref()).await?;
+            print(json, &note, || println!("{}", note.id))
+        }
+        Command::List { collection, tag, limit } => {
+            let collection = match collection {
+                Some(collection) => Some(resolve_collection(&store, &collection).await?.id),
+                None => None,
+            };
+
+            let notes: Vec<Note> = store.get_all_notes().await?
+                .into_iter()
+                .filter(|note| collection.is_none() || note.collection_id == collection)
+                .filter(|note| tag.as_ref().is_none_or(|tag| tags_of(note).contains(tag)))
+                .take(limit.unwrap_or(usize::MAX))
+                .collect();
+            print(json, &notes, || notes.iter().for_each(print_note_line))
+        }
+        Command::Show { note } => {
+            let note = resolve_note(&store, &note).await?;
+            print(json, &note, || println!("{}", note.content))
+        }
+        Command::Edit { note } => {
+            let note = resolve_note(&store, &note).await?;
+            let edited = editor::edit_text(&note.content, &note.id)?;
+
+            // Editors usually add a final newline, which is not a change
+            if edited.text.trim_end() == note.content.trim_end() {
+                edited.discard();
+                print(json, &note, || eprintln!("No changes"))
+            } else {
+                // Fails rather than overwriting if the app saved the note meanwhile
+                match store.update_note_if_unchanged(&note.id, &edited.text, note.updated_at).await {
+                    Ok(updated) => {
+                        edited.discard();
+                        print(json, &updated, || println!("{}", updated.id))
+                    }
+                    Err(AppError::Conflict { message, id }) => Err(AppError::Conflict {
+                        message: format!("{}; your edit was kept in {}", message, edited.path.display()),
+                        id,
+                    }),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+        Command::Search { query, limit } => {
+            let mut results = store.search_notes(&query.join(" "), None).await?;
+            results.truncate(limit.unwrap_or(usize::MAX));
+            print(json, &results, || {
+                for result in &results {
+                    println!("{}  {}", short_id(&result.note_id), result.title);
+                    let excerpt = html::excerpt_text(&result.excerpt);
+                    match &result.matched_attachment {
+                        Some(name) => println!("    [{}] {}", name, excerpt.trim()),
+                        None => println!("    {}", excerpt.trim()),
+                    }
+                }
+            })
+        }
+        Command::Tag { note, add, remove } => {
+            let mut note = resolve_note(&store, &note).await?;
+
+            if !add.is_empty() || !remove.is_empty() {
+                let tags: Vec<String> = tags_of(&note)
+                    .into_iter()
+                    .chain(add)
+                    .filter(|tag| !remove.contains(tag))
+                    .collect();
+                note = store.set_tags(&note.id, &tags).await?;
+            }
+
+            let tags = tags_of(&note);
+            print(json, &tags, || println!("{}", tags.join(", ")))
+        }
+        Command::Mv { note, collection } => {
+            let note = resolve_note(&store, &note).await?;
+            let collection = match collection {
+                Some(collection) => Some(resolve_collection(&store, &collection).await?.id),
+                None => None,
+            };
+
+            store.move_note_to_collection(&note.id, collection.as_deref()).await?;
+            let note = store.get_note(&note.id).await?;
+            print(json, &note, || {})
+        }
+        Command::Export { format, output, all, secrets, notes } => {
+            let note_ids = if all {
+                store.get_all_notes().await?.into_iter().map(|note| note.id).collect()
+            } else {
+                let mut note_ids = Vec::new();
+                for note in &notes {
+                    note_ids.push(resolve_note(&store, note).await?.id);
+                }
+                note_ids
+            };
+
+            let format = match format {
+                ExportFormat::Markdown => "markdown",
+                ExportFormat::Json => "json",
+                ExportFormat::Archive => {
+                    let export = store.export_notes_archive(&note_ids, secrets.into()).await?;
+                    report_secrets(&export.findings);
+                    let mut path = export.output;
+                    if let Some(output) = output {
+                        std::fs::copy(&path, &output)
+                            .with_context(|| format!("Failed to write {}", output.display()))?;
+                        let _ = std::fs::remove_file(&path);
+                        path = output;
+                    }
+                    let path = path.to_string_lossy().to_string();
+                    return print(json, &path, || println!("{}", path));
+                }
+            };
+
+            let export = store.export_notes(format, &note_ids, secrets.into()).await?;
+            report_secrets(&export.findings);
+            let exported = export.output;
+            match output {
+                Some(output) => std::fs::write(&output, exported)
+                    .with_context(|| format!("Failed to write {}", output.display())),
+                None => {
+                    print!("{}", exported);
+                    Ok(())
+                }
+            }
+        }
+        Command::Import { file } => {
+            let notes = store.import_notes(&file).await?;
+            print(json, &notes, || notes.iter().for_each(print_note_line))
+        }
+        Command::Rpc { poll_ms } => rpc::serve(&store, Duration::from_millis(poll_ms.max(10))).await,
+    };
+
+    store.close().await;
+    result
+}
+
+// Print `value` as JSON, or run `text` for the human-readable form
+fn print<T: Serialize>(json: bool, value: &T, text: impl FnOnce()) -> AppResult<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value).context("Failed to serialize output")?);
+    } else {
+        text();
+    }
+    Ok(())
+}
+
+// On stderr, so it never ends up in the export
+fn report_secrets(found: &[NoteFindings]) {
+    for note in found {
+        for finding in &note.findings {
+            eprintln!(
+                "Warning: {} ({}) line {}: {} {}",
+                note.title,
+                short_id(&note.note_id),
+                finding.line,
+                finding.kind.label(),
+                finding.preview
+            );
+        }
+    }
+}
+
+fn print_note_line(note: &Note) {
+    let tags = tags_of(note);
+    let tags = if tags.is_empty() { String::new() } else { format!("  [{}]", tags.join(", ")) };
+    println!(
+        "{}  {}  {}{}",
+        short_id(&note.id),
+        note.updated_at.format("%Y-%m-%d %H:%M"),
+        note.title,
+        tags
+    );
+}
+
+fn short_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
+
+fn tags_of(note: &Note) -> Vec<String> {
+    serde_json::from_str(&note.tags).unwrap_or_default()
+}
+
+fn read_stdin() -> AppResult<String> {
+    if std::io::stdin().is_terminal() {
+        eprintln!("Reading note from stdin, end with Ctrl-D");
+    }
+    let mut content = String::new();
+    std::io::stdin()
+        .read_to_string(&mut content)
+        .context("Failed to read stdin")?;
+    Ok(content)
+}
+
+fn title_from_content(content: &str) -> String {
+    content
+        .lines()
+        .map(|line| line.trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .map(|line| line.chars().take(80).collect())
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+async fn resolve_note(store: &NoteStore, id: &str) -> AppResult<Note> {
+    let id = store.resolve_note_id(id).await?;
+    store.get_note(&id).await
+}
+
+// Collections are named by id, id prefix or name
+async fn resolve_collection(store: &NoteStore, name: &str) -> AppResult<Collection> {
+    let collections = store.get_all_collections().await?;
+
+    if let Some(collection) = collections.iter().find(|collection| collection.id == name) {
+        return Ok(collection.clone());
+    }
+
+    let matches: Vec<&Collection> = collections
+        .iter()
+        .filter(|collection| collection.name.eq_ignore_ascii_case(name) || collection.id.starts_with(name))
+        .collect();
+
+    match matches.as_slice() {
+        [collection] => Ok((*collection).clone()),
+        [] => Err(AppError::not_found("collection", name)),
+        _ => Err(AppError::Validation(format!("Collection {} is ambiguous", name))),
+    }
+}
@@ -0,0 +1,319 @@
+// JSON-RPC 2.0 on stdin/stdout for editor plugins and local tools. Messages
+// are either one JSON object per line or LSP-style `Content-Length` framed;
+// replies use whichever framing the client last used. Stdout carries protocol
+// messages only, anything else goes to stderr.
+use crate::{tags_of, title_from_content};
+use notura_core::changes::ChangeWatcher;
+use notura_core::error::{AppError, AppResult, Context};
+use notura_core::NoteStore;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+// JSON-RPC error codes; failures from the store use SERVER_ERROR with the
+// AppError (and its stable `code`) as `data`
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+// Larger `Content-Length` bodies are skipped and answered with PARSE_ERROR
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Framing {
+    Lines,
+    ContentLength,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into(), data: None }
+    }
+}
+
+impl From<AppError> for RpcError {
+    fn from(e: AppError) -> Self {
+        RpcError {
+            code: SERVER_ERROR,
+            message: e.to_string(),
+            data: serde_json::to_value(&e).ok(),
+        }
+    }
+}
+
+// Serve until stdin closes. With `notes/subscribe`, the database is checked
+// every `poll_interval` and changes are sent as `notes/changed` notifications.
+pub async fn serve(store: &NoteStore, poll_interval: Duration) -> AppResult<()> {
+    let (messages, mut incoming) = mpsc::channel(16);
+    tokio::spawn(read_messages(tokio::io::stdin(), messages));
+
+    let mut stdout = tokio::io::stdout();
+    let mut framing = Framing::Lines;
+    let mut watcher: Option<ChangeWatcher> = None;
+    let mut ticker = tokio::time::interval(poll_interval);
+
+    loop {
+        tokio::select! {
+            message = incoming.recv() => {
+                let Some((message, message_framing)) = message else { break };
+                framing = message_framing;
+
+                let reply = match message {
+                    Ok(message) => handle(store, &mut watcher, message).await,
+                    Err(e) => Some(error_reply(Value::Null, e)),
+                };
+                if let Some(reply) = reply {
+                    write_message(&mut stdout, framing, &reply).await?;
+                }
+            }
+            _ = ticker.tick(), if watcher.is_some() => {
+                let Some(watcher) = watcher.as_mut() else { continue };
+                match watcher.poll(store).await {
+                    Ok(changes) => {
+                        for change in changes {
+                            let notification = json!({
+                                "jsonrpc": "2.0",
+                                "method": "notes/changed",
+                                "params": change,
+                            });
+                            write_message(&mut stdout, framing, &notification).await?;
+                        }
+                    }
+                    Err(e) => eprintln!("Warning: {}", e),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Reads framed messages until EOF. Parse failures are passed on so the client
+// gets an error reply; read failures end the session.
+async fn read_messages(
+    input: impl AsyncRead + Unpin,
+    messages: mpsc::Sender<(Result<Value, RpcError>, Framing)>,
+) {
+    let mut input = BufReader::new(input);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        match input.read_line(&mut line).await {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Warning: Failed to read stdin: {}", e);
+                return;
+            }
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message = match content_length(&line) {
+            Some(length) => match read_framed_body(&mut input, length).await {
+                Ok(Some(body)) => (parse(&body), Framing::ContentLength),
+                Ok(None) => {
+                    let message = format!("Parse error: message is over the {} MiB limit", MAX_MESSAGE_LEN / 1024 / 1024);
+                    (Err(RpcError::new(PARSE_ERROR, message)), Framing::ContentLength)
+                }
+                Err(e) => {
+                    eprintln!("Warning: Failed to read stdin: {}", e);
+                    return;
+                }
+            },
+            None => (parse(line.as_bytes()), Framing::Lines),
+        };
+        if messages.send(message).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn content_length(header: &str) -> Option<usize> {
+    let (name, value) = header.split_once(':')?;
+    if name.trim().eq_ignore_ascii_case("content-length") {
+        value.trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+// Skip the remaining headers up to the blank line, then read the body. One
+// over MAX_MESSAGE_LEN is read past without being kept, giving None.
+async fn read_framed_body(
+    input: &mut BufReader<impl AsyncRead + Unpin>,
+    length: usize,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    if length > MAX_MESSAGE_LEN {
+        let skipped = tokio::io::copy(&mut input.take(length as u64), &mut tokio::io::sink()).await?;
+        if skipped < length as u64 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        return Ok(None);
+    }
+
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+fn parse(body: &[u8]) -> Result<Value, RpcError> {
+    serde_json::from_slice(body).map_err(|e| RpcError::new(PARSE_ERROR, format!("Parse error: {}", e)))
+}
+
+async fn write_message(
+    stdout: &mut tokio::io::Stdout,
+    framing: Framing,
+    message: &Value,
+) -> AppResult<()> {
+    let body = message.to_string();
+    let framed = match framing {
+        Framing::Lines => format!("{}\n", body),
+        Framing::ContentLength => format!("Content-Length: {}\r\n\r\n{}", body.len(), body),
+    };
+    stdout.write_all(framed.as_bytes()).await.context("Failed to write to stdout")?;
+    stdout.flush().await.context("Failed to write to stdout")
+}
+
+fn error_reply(id: Value, e: RpcError) -> Value {
+    let mut error = json!({ "code": e.code, "message": e.message });
+    if let Some(data) = e.data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+// Replies to requests; notifications (no `id`) get none
+async fn handle(store: &NoteStore, watcher: &mut Option<ChangeWatcher>, message: Value) -> Option<Value> {
+    let id = message.get("id").cloned();
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        return Some(error_reply(id.unwrap_or(Value::Null), RpcError::new(INVALID_REQUEST, "Invalid request")));
+    };
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = dispatch(store, watcher, method, params).await;
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error_reply(id, e),
+    })
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    query: String,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct IdParams {
+    // A full id or a unique prefix of it
+    id: String,
+}
+
+#[derive(Deserialize, Default)]
+struct ListParams {
+    collection_id: Option<String>,
+    tag: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct CreateParams {
+    title: Option<String>,
+    content: String,
+    collection_id: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct LinkParams {
+    target: String,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+fn to_value(value: impl serde::Serialize) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| AppError::from(e).into())
+}
+
+async fn dispatch(
+    store: &NoteStore,
+    watcher: &mut Option<ChangeWatcher>,
+    method: &str,
+    raw: Value,
+) -> Result<Value, RpcError> {
+    match method {
+        "notes/search" => {
+            let SearchParams { query, limit } = params(raw)?;
+            let mut results = store.search_notes(&query, None).await?;
+            results.truncate(limit.unwrap_or(usize::MAX));
+            to_value(results)
+        }
+        "notes/get" => {
+            let IdParams { id } = params(raw)?;
+            let id = store.resolve_note_id(&id).await?;
+            to_value(store.get_note(&id).await?)
+        }
+        "notes/list" => {
+            let ListParams { collection_id, tag, limit } = if raw.is_null() { ListParams::default() } else { params(raw)? };
+            let notes: Vec<_> = store.get_all_notes().await?
+                .into_iter()
+                .filter(|note| collection_id.is_none() || note.collection_id == collection_id)
+                .filter(|note| tag.as_ref().is_none_or(|tag| tags_of(note).contains(tag)))
+                .take(limit.unwrap_or(usize::MAX))
+                .collect();
+            to_value(notes)
+        }
+        "notes/create" => {
+            let CreateParams { title, content, collection_id, tags } = params(raw)?;
+            if let Some(collection_id) = &collection_id {
+                store.get_collection(collection_id).await?;
+            }
+            let title = title.unwrap_or_else(|| title_from_content(&content));
+            let mut note = store.create_note(&title, &content, collection_id.as_deref()).await?;
+            if let Some(tags) = tags {
+                note = store.set_tags(&note.id, &tags).await?;
+            }
+            to_value(note)
+        }
+        "notes/resolveLink" => {
+            let LinkParams { target } = params(raw)?;
+            to_value(store.resolve_link(&target).await?)
+        }
+        "collections/list" => to_value(store.get_all_collections().await?),
+        "notes/subscribe" => {
+            if watcher.is_none() {
+                *watcher = Some(ChangeWatcher::new(store).await?);
+            }
+            Ok(Value::Bool(true))
+        }
+        "notes/unsubscribe" => {
+            *watcher = None;
+            Ok(Value::Bool(true))
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    }
+}
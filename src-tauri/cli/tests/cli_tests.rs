@@ -0,0 +1,182 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+fn notura(dir: &TempDir, args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_notura-cli"))
+        .arg("--data-dir")
+        .arg(dir.path())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.unwrap_or("").as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn json(output: &Output) -> serde_json::Value {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_capture_tag_search_and_export() {
+    let dir = TempDir::new().unwrap();
+
+    let note = json(&notura(&dir, &["new", "--json"], Some("# Standup\nShip the release notes\n")));
+    assert_eq!(note["title"], "Standup");
+    let id = note["id"].as_str().unwrap();
+    let short_id = &id[..8];
+
+    notura(&dir, &["new", "Unrelated thought"], None);
+
+    let tags = json(&notura(&dir, &["tag", short_id, "--add", "work", "--add", "daily", "--json"], None));
+    assert_eq!(tags, serde_json::json!(["work", "daily"]));
+    let tags = json(&notura(&dir, &["tag", short_id, "--remove", "daily", "--json"], None));
+    assert_eq!(tags, serde_json::json!(["work"]));
+
+    let listed = json(&notura(&dir, &["list", "--tag", "work", "--json"], None));
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+
+    let results = json(&notura(&dir, &["search", "release", "--json"], None));
+    assert_eq!(results[0]["note_id"], id);
+
+    let shown = notura(&dir, &["show", short_id], None);
+    assert_eq!(String::from_utf8_lossy(&shown.stdout), "# Standup\nShip the release notes\n\n");
+
+    let exported = notura(&dir, &["export", "--format", "json", id], None);
+    let exported: serde_json::Value = serde_json::from_slice(&exported.stdout).unwrap();
+    assert_eq!(exported[0]["title"], "Standup");
+
+    // Errors come back as structured JSON on stderr with a failing exit code
+    let missing = notura(&dir, &["show", "ffffffff", "--json"], None);
+    assert!(!missing.status.success());
+    let error: serde_json::Value = serde_json::from_slice(&missing.stderr).unwrap();
+    assert_eq!(error["code"], "not_found");
+}
+
+#[test]
+fn test_edit_uses_editor_and_move_between_collections() {
+    let dir = TempDir::new().unwrap();
+    let note = json(&notura(&dir, &["new", "--json", "draft"], None));
+    let id = note["id"].as_str().unwrap();
+
+    // A stand-in editor that appends a line to the file it is given
+    let editor = dir.path().join("editor.sh");
+    std::fs::write(&editor, "#!/bin/sh\necho 'appended' >> \"$1\"\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let edited = Command::new(env!("CARGO_BIN_EXE_notura-cli"))
+        .arg("--data-dir")
+        .arg(dir.path())
+        .args(["edit", id, "--json"])
+        .env("VISUAL", &editor)
+        .output()
+        .unwrap();
+    assert_eq!(json(&edited)["content"], "draftappended\n");
+
+    let moved = notura(&dir, &["mv", id, "Inbox"], None);
+    assert!(!moved.status.success());
+    assert!(String::from_utf8_lossy(&moved.stderr).contains("Collection with id Inbox not found"));
+}
+
+#[test]
+fn test_rpc_over_stdio() {
+    use std::io::{BufRead, BufReader};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let dir = TempDir::new().unwrap();
+    let plan = json(&notura(&dir, &["new", "--title", "Release Plan", "--json"], Some("Cut the branch on Friday")));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_notura-cli"))
+        .arg("--data-dir")
+        .arg(dir.path())
+        .args(["rpc", "--poll-ms", "50"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+
+    // Read replies on a thread so a missing one fails the test instead of hanging it
+    let (lines, replies) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = lines.send(serde_json::from_str::<serde_json::Value>(&line.unwrap()).unwrap());
+        }
+    });
+    let mut call = |request: serde_json::Value| {
+        writeln!(stdin, "{}", request).unwrap();
+        replies.recv_timeout(Duration::from_secs(10)).unwrap()
+    };
+
+    let found = call(serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "notes/search", "params": {"query": "branch"}}));
+    assert_eq!(found["id"], 1);
+    assert_eq!(found["result"][0]["note_id"], plan["id"]);
+
+    let linked = call(serde_json::json!({"jsonrpc": "2.0", "id": 2, "method": "notes/resolveLink", "params": {"target": "[[release plan]]"}}));
+    assert_eq!(linked["result"]["id"], plan["id"]);
+
+    let missing = call(serde_json::json!({"jsonrpc": "2.0", "id": 3, "method": "notes/get", "params": {"id": "nope"}}));
+    assert_eq!(missing["error"]["code"], -32000);
+    assert_eq!(missing["error"]["data"]["code"], "not_found");
+    let unknown = call(serde_json::json!({"jsonrpc": "2.0", "id": 4, "method": "notes/explode"}));
+    assert_eq!(unknown["error"]["code"], -32601);
+
+    let subscribed = call(serde_json::json!({"jsonrpc": "2.0", "id": 5, "method": "notes/subscribe"}));
+    assert_eq!(subscribed["result"], true);
+
+    // A note created by another process shows up as a notification
+    let created = json(&notura(&dir, &["new", "--json"], Some("Written elsewhere")));
+    let changed = replies.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(changed["method"], "notes/changed");
+    assert_eq!(changed["params"]["id"], created["id"]);
+    assert_eq!(changed["params"]["kind"], "created");
+
+    let note = call(serde_json::json!({"jsonrpc": "2.0", "id": 6, "method": "notes/create", "params": {"content": "# From the editor\nbody", "tags": ["vim"]}}));
+    assert_eq!(note["result"]["title"], "From the editor");
+
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_rpc_skips_oversized_messages() {
+    let dir = TempDir::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_notura-cli"))
+        .arg("--data-dir")
+        .arg(dir.path())
+        .arg("rpc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // The body is read past, so the request after it is still answered
+    let length = 16 * 1024 * 1024 + 1;
+    let mut stdin = child.stdin.take().unwrap();
+    write!(stdin, "Content-Length: {}\r\n\r\n", length).unwrap();
+    stdin.write_all(&vec![b' '; length]).unwrap();
+    writeln!(stdin, "{}", serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "collections/list"})).unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let (header, rest) = stdout.split_once("\r\n\r\n").unwrap();
+    let body_length: usize = header.strip_prefix("Content-Length: ").unwrap().parse().unwrap();
+    let refused: serde_json::Value = serde_json::from_str(&rest[..body_length]).unwrap();
+    assert_eq!(refused["error"]["code"], -32700);
+    let answered: serde_json::Value = serde_json::from_str(&rest[body_length..]).unwrap();
+    assert_eq!(answered["id"], 1);
+}
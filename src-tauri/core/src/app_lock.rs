@@ -0,0 +1,259 @@
+// Optional master password. Once it is set the app starts locked and locks
+// again after a stretch without activity, and neither the shell nor the local
+// API or collaboration server serve notes until it is unlocked. Only Argon2
+// hashes of the password and of a recovery key are stored, in the settings
+// table. The lock keeps out other people at the same machine; it does not
+// encrypt anything on disk.
+use crate::crypto;
+use crate::error::{AppError, AppResult};
+use crate::settings::{self, LOCK_PASSWORD_HASH, LOCK_RECOVERY_HASH};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+// Wrong guesses are slowed down on top of the hashing itself
+const FAILED_ATTEMPT_DELAY: Duration = Duration::from_secs(1);
+// Crockford's base32, which leaves out letters easily mistaken for digits
+const RECOVERY_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const RECOVERY_KEY_BYTES: usize = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockStatus {
+    // A master password is set
+    pub enabled: bool,
+    pub locked: bool,
+}
+
+pub struct AppLock {
+    state: Mutex<LockState>,
+    // Woken whenever the app locks, to cut off connections that are open
+    locking: Notify,
+}
+
+struct LockState {
+    enabled: bool,
+    locked: bool,
+    timeout: Option<Duration>,
+    last_activity: Instant,
+}
+
+impl Default for AppLock {
+    fn default() -> Self {
+        AppLock {
+            state: Mutex::new(LockState {
+                enabled: false,
+                locked: false,
+                timeout: None,
+                last_activity: Instant::now(),
+            }),
+            locking: Notify::new(),
+        }
+    }
+}
+
+impl AppLock {
+    // Read whether a password is set, locking if so; for startup. Locked
+    // until the settings are read, and left locked if they can't be.
+    pub async fn load(&self, pool: &SqlitePool) -> AppResult<()> {
+        self.state().locked = true;
+        let enabled = settings::get_setting(pool, LOCK_PASSWORD_HASH).await?.is_some();
+        let timeout = settings::LockSettings::load(pool).await?.timeout();
+
+        let mut state = self.state();
+        state.enabled = enabled;
+        state.locked = enabled;
+        state.timeout = timeout;
+        Ok(())
+    }
+
+    pub fn status(&self) -> LockStatus {
+        self.lock_if_idle();
+        let state = self.state();
+        LockStatus {
+            enabled: state.enabled,
+            locked: state.locked,
+        }
+    }
+
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        self.state().timeout = timeout;
+    }
+
+    pub fn lock(&self) -> AppResult<()> {
+        let mut state = self.state();
+        if !state.enabled {
+            return Err(AppError::Validation("Set a master password to lock the app".to_string()));
+        }
+        state.locked = true;
+        drop(state);
+        self.locking.notify_waiters();
+        Ok(())
+    }
+
+    // Refuse while locked; anything else counts as activity
+    pub fn check(&self) -> AppResult<()> {
+        self.check_unlocked()?;
+        self.state().last_activity = Instant::now();
+        Ok(())
+    }
+
+    // Refuse while locked without counting as activity, for the local API and
+    // collaboration server, whose clients should not keep the app unlocked
+    pub fn check_unlocked(&self) -> AppResult<()> {
+        self.lock_if_idle();
+        if self.state().locked {
+            return Err(AppError::Locked("Notura is locked, enter the master password".to_string()));
+        }
+        Ok(())
+    }
+
+    // Completes once the app is locked
+    pub async fn until_locked(&self) {
+        let locking = self.locking.notified();
+        if self.state().locked {
+            return;
+        }
+        locking.await;
+    }
+
+    // Lock once the timeout passed without activity; true if this locked it
+    pub fn lock_if_idle(&self) -> bool {
+        let mut state = self.state();
+        let idle = state.timeout.is_some_and(|timeout| state.last_activity.elapsed() >= timeout);
+        if state.enabled && !state.locked && idle {
+            state.locked = true;
+            drop(state);
+            self.locking.notify_waiters();
+            return true;
+        }
+        false
+    }
+
+    pub async fn unlock(&self, pool: &SqlitePool, password: &str) -> AppResult<()> {
+        if !self.state().enabled {
+            return Ok(());
+        }
+        verify(pool, LOCK_PASSWORD_HASH, password, "Wrong master password").await?;
+        self.unlocked();
+        Ok(())
+    }
+
+    // Set the master password, or change it given the current one. Returns a
+    // new recovery key, to be shown to the user once.
+    pub async fn set_password(&self, pool: &SqlitePool, current: Option<&str>, password: &str) -> AppResult<String> {
+        self.check()?;
+        check_strength(password)?;
+        if self.state().enabled {
+            verify(pool, LOCK_PASSWORD_HASH, current.unwrap_or_default(), "Wrong master password").await?;
+        }
+
+        let recovery_key = save_password(pool, password).await?;
+        self.state().enabled = true;
+        Ok(recovery_key)
+    }
+
+    pub async fn remove_password(&self, pool: &SqlitePool, current: &str) -> AppResult<()> {
+        self.check()?;
+        verify(pool, LOCK_PASSWORD_HASH, current, "Wrong master password").await?;
+        settings::remove_setting(pool, LOCK_PASSWORD_HASH).await?;
+        settings::remove_setting(pool, LOCK_RECOVERY_HASH).await?;
+
+        let mut state = self.state();
+        state.enabled = false;
+        state.locked = false;
+        Ok(())
+    }
+
+    // For a forgotten password: the recovery key sets a new one and unlocks.
+    // The key is used up; the new one is returned.
+    pub async fn recover(&self, pool: &SqlitePool, recovery_key: &str, password: &str) -> AppResult<String> {
+        check_strength(password)?;
+        verify(pool, LOCK_RECOVERY_HASH, &normalize_recovery_key(recovery_key), "Wrong recovery key").await?;
+
+        let recovery_key = save_password(pool, password).await?;
+        self.state().enabled = true;
+        self.unlocked();
+        Ok(recovery_key)
+    }
+
+    fn unlocked(&self) {
+        let mut state = self.state();
+        state.locked = false;
+        state.last_activity = Instant::now();
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, LockState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn check_strength(password: &str) -> AppResult<()> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(AppError::Validation(format!(
+            "The master password needs at least {} characters",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+// Store the password's hash along with a new recovery key's, which is returned
+async fn save_password(pool: &SqlitePool, password: &str) -> AppResult<String> {
+    let recovery_key = new_recovery_key();
+    settings::set_setting(pool, LOCK_PASSWORD_HASH, &hash(password)?).await?;
+    settings::set_setting(pool, LOCK_RECOVERY_HASH, &hash(&normalize_recovery_key(&recovery_key))?).await?;
+    Ok(recovery_key)
+}
+
+async fn verify(pool: &SqlitePool, key: &str, secret: &str, refusal: &str) -> AppResult<()> {
+    let stored = settings::get_setting(pool, key).await?.unwrap_or_default();
+    let matches = PasswordHash::new(&stored)
+        .is_ok_and(|hash| Argon2::default().verify_password(secret.as_bytes(), &hash).is_ok());
+    if !matches {
+        tokio::time::sleep(FAILED_ATTEMPT_DELAY).await;
+        return Err(AppError::Unauthorized(refusal.to_string()));
+    }
+    Ok(())
+}
+
+fn hash(secret: &str) -> AppResult<String> {
+    let mut salt = [0u8; 16];
+    crypto::random_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| AppError::Internal(e.to_string()))?;
+    Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Internal(format!("Failed to hash the password: {}", e)))
+}
+
+// 32 base32 characters in groups of four, e.g. `7KQ2-M9XD-...`
+fn new_recovery_key() -> String {
+    let mut bytes = [0u8; RECOVERY_KEY_BYTES];
+    crypto::random_bytes(&mut bytes);
+
+    let mut characters = Vec::new();
+    for chunk in bytes.chunks(5) {
+        let bits = chunk.iter().fold(0u64, |bits, byte| bits << 8 | *byte as u64);
+        for shift in (0..8).rev() {
+            characters.push(RECOVERY_ALPHABET[(bits >> (shift * 5)) as usize & 31] as char);
+        }
+    }
+    characters
+        .chunks(4)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Keys are accepted in any case and with or without the dashes
+fn normalize_recovery_key(key: &str) -> String {
+    key.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
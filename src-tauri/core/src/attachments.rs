@@ -0,0 +1,288 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::settings::UploadSettings;
+use crate::{text_extraction, uploads, NoteStore};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AttachmentMetadata {
+    pub id: String,
+    pub filename: String,
+    pub original_name: String,
+    pub file_path: String,
+    pub size: i64,
+    pub mime_type: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl NoteStore {
+    // Store an arbitrary file under `attachments/` and record it, optionally
+    // linked to a note. The file is removed again if the database write
+    // fails. Its text is indexed in the background.
+    pub async fn save_attachment(
+        &self,
+        file_data: &[u8],
+        original_name: &str,
+        note_id: Option<&str>,
+    ) -> AppResult<AttachmentMetadata> {
+        let max_size = UploadSettings::load(self.pool()).await?.max_attachment_size;
+        let attachment = self.insert_attachment(file_data, original_name, note_id, max_size).await?;
+
+        // Text extraction can take a while for large PDFs
+        let store = self.clone();
+        let indexed = attachment.clone();
+        tokio::spawn(async move {
+            if let Err(e) = store.index_attachment_text(&indexed).await {
+                eprintln!("Warning: Failed to index attachment {}: {}", indexed.id, e);
+            }
+        });
+
+        Ok(attachment)
+    }
+
+    async fn insert_attachment(
+        &self,
+        file_data: &[u8],
+        original_name: &str,
+        note_id: Option<&str>,
+        max_size: u64,
+    ) -> AppResult<AttachmentMetadata> {
+        if file_data.len() as u64 > max_size {
+            return Err(AppError::Validation(format!(
+                "Attachment is {} bytes, which exceeds the {} byte limit",
+                file_data.len(),
+                max_size
+            )));
+        }
+
+        let original_name = Path::new(original_name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| !name.trim().is_empty())
+            .ok_or_else(|| AppError::Validation("Attachment name is required".to_string()))?;
+
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let mime_type = uploads::detect_mime_type(file_data, original_name);
+        let unique_filename = format!(
+            "{}_{}.{}",
+            id,
+            now.timestamp(),
+            uploads::storage_extension(file_data, original_name)
+        );
+
+        let attachments_dir = self.data_dir().join("attachments");
+        fs::create_dir_all(&attachments_dir)
+            .context("Failed to create attachments directory")?;
+        let file_path = uploads::write_file_atomically(&attachments_dir, &unique_filename, file_data)
+            .context("Failed to save attachment file")?;
+
+        let result: AppResult<AttachmentMetadata> = async {
+            let mut tx = self.pool().begin().await
+                .context("Failed to start transaction")?;
+
+            let attachment = sqlx::query_as::<_, AttachmentMetadata>(
+                r#"
+                INSERT INTO attachments (id, filename, original_name, file_path, size, mime_type, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                RETURNING *
+                "#,
+            )
+            .bind(&id)
+            .bind(&unique_filename)
+            .bind(original_name)
+            .bind(file_path.to_string_lossy().to_string())
+            .bind(file_data.len() as i64)
+            .bind(&mime_type)
+            .bind(now)
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to save attachment metadata")?;
+
+            if let Some(note_id) = note_id {
+                sqlx::query(
+                    "INSERT INTO note_attachments (note_id, attachment_id, created_at) VALUES (?1, ?2, ?3)"
+                )
+                .bind(note_id)
+                .bind(&id)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to associate attachment with note")?;
+            }
+
+            tx.commit().await
+                .context("Failed to commit attachment")?;
+
+            Ok(attachment)
+        }
+        .await;
+
+        if result.is_err() {
+            if let Err(e) = fs::remove_file(&file_path) {
+                eprintln!("Warning: Failed to clean up attachment file {}: {}", file_path.display(), e);
+            }
+        }
+
+        result
+    }
+
+    pub async fn get_attachment(&self, id: &str) -> AppResult<AttachmentMetadata> {
+        sqlx::query_as::<_, AttachmentMetadata>("SELECT * FROM attachments WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(self.pool())
+            .await
+            .context("Failed to get attachment")?
+            .ok_or_else(|| AppError::not_found("attachment", id))
+    }
+
+    pub async fn get_all_attachments(&self) -> AppResult<Vec<AttachmentMetadata>> {
+        sqlx::query_as::<_, AttachmentMetadata>("SELECT * FROM attachments ORDER BY created_at DESC")
+            .fetch_all(self.pool())
+            .await
+            .context("Failed to get attachments")
+    }
+
+    pub async fn get_attachments_for_note(&self, note_id: &str) -> AppResult<Vec<AttachmentMetadata>> {
+        sqlx::query_as::<_, AttachmentMetadata>(
+            r#"
+            SELECT a.* FROM attachments a
+            JOIN note_attachments na ON a.id = na.attachment_id
+            WHERE na.note_id = ?1
+            ORDER BY a.created_at
+            "#,
+        )
+        .bind(note_id)
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to get attachments for note")
+    }
+
+    // Attachments of several notes at once, keyed by note id (used by exports)
+    pub async fn get_attachments_for_notes(
+        &self,
+        note_ids: &[String],
+    ) -> AppResult<HashMap<String, Vec<AttachmentMetadata>>> {
+        let mut by_note = HashMap::new();
+
+        for note_id in note_ids {
+            let attachments = self.get_attachments_for_note(note_id).await?;
+            if !attachments.is_empty() {
+                by_note.insert(note_id.clone(), attachments);
+            }
+        }
+
+        Ok(by_note)
+    }
+
+    pub async fn delete_attachment(&self, id: &str) -> AppResult<()> {
+        let attachment = self.get_attachment(id).await?;
+
+        // Delete from database first (this cascades to note_attachments), so a
+        // failure never leaves a record pointing at a missing file
+        sqlx::query("DELETE FROM attachments WHERE id = ?1")
+            .bind(id)
+            .execute(self.pool())
+            .await
+            .context("Failed to delete attachment from database")?;
+
+        if let Err(e) = fs::remove_file(&attachment.file_path) {
+            eprintln!("Warning: Failed to delete attachment file {}: {}", attachment.file_path, e);
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_attachment_note_association(
+        &self,
+        attachment_id: &str,
+        note_id: &str,
+        is_used: bool,
+    ) -> AppResult<()> {
+        if is_used {
+            sqlx::query(
+                r#"
+                INSERT INTO note_attachments (note_id, attachment_id, created_at)
+                VALUES (?1, ?2, ?3)
+                ON CONFLICT (note_id, attachment_id) DO NOTHING
+                "#,
+            )
+            .bind(note_id)
+            .bind(attachment_id)
+            .bind(Utc::now())
+            .execute(self.pool())
+            .await
+            .context("Failed to add attachment association")?;
+        } else {
+            sqlx::query("DELETE FROM note_attachments WHERE note_id = ?1 AND attachment_id = ?2")
+                .bind(note_id)
+                .bind(attachment_id)
+                .execute(self.pool())
+                .await
+                .context("Failed to remove attachment association")?;
+        }
+
+        Ok(())
+    }
+
+    // Extract an attachment's text and replace its entry in attachments_fts. The
+    // name is always indexed; returns whether any content text was found. An
+    // attachment deleted in the meantime is left out.
+    pub async fn index_attachment_text(&self, attachment: &AttachmentMetadata) -> AppResult<bool> {
+        let path = PathBuf::from(&attachment.file_path);
+        let mime_type = attachment.mime_type.clone();
+
+        // PDF parsing is CPU bound, keep it off the async runtime
+        let text = tokio::task::spawn_blocking(move || text_extraction::extract_text(&path, &mime_type))
+            .await
+            .context("Text extraction task failed")??
+            .filter(|text| !text.trim().is_empty());
+
+        let mut tx = self.pool().begin().await
+            .context("Failed to start transaction")?;
+
+        sqlx::query("DELETE FROM attachments_fts WHERE attachment_id = ?1")
+            .bind(&attachment.id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to clear attachment index")?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO attachments_fts (attachment_id, name, content)
+            SELECT ?1, ?2, ?3 WHERE EXISTS (SELECT 1 FROM attachments WHERE id = ?1)
+            "#,
+        )
+            .bind(&attachment.id)
+            .bind(&attachment.original_name)
+            .bind(text.as_deref().unwrap_or(""))
+            .execute(&mut *tx)
+            .await
+            .context("Failed to index attachment")?;
+
+        tx.commit().await
+            .context("Failed to commit attachment index")?;
+
+        Ok(text.is_some())
+    }
+
+    // Rebuild the text index for every attachment, e.g. for files added before
+    // indexing existed. Unreadable files are logged and skipped.
+    pub async fn reindex_attachments(&self) -> AppResult<usize> {
+        let mut indexed = 0;
+
+        for attachment in self.get_all_attachments().await? {
+            match self.index_attachment_text(&attachment).await {
+                Ok(true) => indexed += 1,
+                Ok(false) => {}
+                Err(e) => eprintln!("Warning: Failed to index attachment {}: {}", attachment.id, e),
+            }
+        }
+
+        Ok(indexed)
+    }
+}
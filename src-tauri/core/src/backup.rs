@@ -0,0 +1,646 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::{recovery, s3_backup};
+use crate::settings::{BackupSettings, Retention, S3BackupSettings};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+pub const DATABASE_FILE: &str = "notura.db";
+// Directories next to the database that hold user files referenced by it
+pub const FILE_DIRECTORIES: &[&str] = &["images", "attachments"];
+
+const MANIFEST_FILE: &str = "manifest.json";
+const BACKUP_PREFIX: &str = "notura-backup-";
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+const FORMAT_VERSION: u32 = 1;
+const RESTORE_DIR: &str = "restore-pending";
+// Where a restore moves the data it replaces, followed by a timestamp
+pub(crate) const PRE_RESTORE_PREFIX: &str = "pre-restore-";
+// Written last when staging a restore, so a half-extracted backup is never applied
+const RESTORE_READY_MARKER: &str = ".ready";
+// Present when the restored database goes to the default location rather
+// than where the database currently lives
+const RESET_LOCATION_MARKER: &str = ".reset-location";
+const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub created_at: DateTime<Utc>,
+    pub app_version: String,
+    pub files: Vec<BackupFileEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFileEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub file_name: String,
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+    pub size: u64,
+}
+
+pub fn backups_dir(app_dir: &Path) -> PathBuf {
+    app_dir.join("backups")
+}
+
+// Create a timestamped archive of the database and the user file directories,
+// verified before it is reported as done.
+pub async fn create_backup(pool: &SqlitePool, app_dir: &Path) -> AppResult<BackupInfo> {
+    let backups_dir = backups_dir(app_dir);
+    fs::create_dir_all(&backups_dir)
+        .context("Failed to create backups directory")?;
+
+    let created_at = Utc::now();
+    let file_name = format!("{}{}.zip", BACKUP_PREFIX, created_at.format(TIMESTAMP_FORMAT));
+    let archive_path = backups_dir.join(&file_name);
+    if archive_path.exists() {
+        return Err(AppError::Conflict { message: format!("Backup {} already exists", file_name), id: Some(file_name) });
+    }
+
+    // VACUUM INTO copies the live database inside a single read transaction,
+    // so the snapshot is consistent even while the app keeps writing
+    let snapshot_path = backups_dir.join(format!(".{}.db.tmp", file_name));
+    let _ = fs::remove_file(&snapshot_path);
+    sqlx::query("VACUUM INTO ?1")
+        .bind(snapshot_path.to_string_lossy().to_string())
+        .execute(pool)
+        .await
+        .context("Failed to snapshot database")?;
+
+    let result = {
+        let archive_path = archive_path.clone();
+        let snapshot_path = snapshot_path.clone();
+        let app_dir = app_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || write_archive(&archive_path, &snapshot_path, &app_dir, created_at))
+            .await
+            .context("Backup task failed")
+            .and_then(|result| result)
+    };
+    let _ = fs::remove_file(&snapshot_path);
+    result?;
+
+    if let Err(e) = verify_backup(&archive_path).await {
+        let _ = fs::remove_file(&archive_path);
+        return Err(e).context("Backup failed verification");
+    }
+
+    backup_info(&archive_path).ok_or_else(|| AppError::Io(format!("Failed to read backup {}", file_name)))
+}
+
+fn write_archive(
+    archive_path: &Path,
+    snapshot_path: &Path,
+    app_dir: &Path,
+    created_at: DateTime<Utc>,
+) -> AppResult<()> {
+    let temp_path = archive_path.with_extension("zip.tmp");
+    let file = File::create(&temp_path)
+        .context("Failed to create backup archive")?;
+
+    let result = (|| -> AppResult<()> {
+        let mut zip = ZipWriter::new(file);
+        let mut files = vec![add_file(&mut zip, DATABASE_FILE, snapshot_path)?];
+
+        for directory in FILE_DIRECTORIES {
+            for (archive_name, source) in list_files(&app_dir.join(directory), directory)? {
+                files.push(add_file(&mut zip, &archive_name, &source)?);
+            }
+        }
+
+        let manifest = BackupManifest {
+            format_version: FORMAT_VERSION,
+            created_at,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            files,
+        };
+        let manifest = serde_json::to_vec_pretty(&manifest)
+            .context("Failed to serialize backup manifest")?;
+        zip.start_file(MANIFEST_FILE, SimpleFileOptions::default())
+            .context("Failed to write backup manifest")?;
+        zip.write_all(&manifest)
+            .context("Failed to write backup manifest")?;
+
+        let file = zip.finish()
+            .context("Failed to finish backup archive")?;
+        file.sync_all()
+            .context("Failed to flush backup archive")?;
+
+        fs::rename(&temp_path, archive_path)
+            .context("Failed to move backup archive into place")
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+// Files below `dir`, paired with their name inside the archive. Hidden files
+// are skipped: they are temporaries from in-progress atomic writes.
+fn list_files(dir: &Path, archive_prefix: &str) -> AppResult<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    if !dir.exists() {
+        return Ok(files);
+    }
+
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read {}", dir.display()))?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let archive_name = format!("{}/{}", archive_prefix, name);
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(list_files(&path, &archive_name)?);
+        } else {
+            files.push((archive_name, path));
+        }
+    }
+
+    Ok(files)
+}
+
+fn add_file(zip: &mut ZipWriter<File>, archive_name: &str, source: &Path) -> AppResult<BackupFileEntry> {
+    let mut input = File::open(source)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+    let size = input.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+    let options = SimpleFileOptions::default().large_file(size >= u32::MAX as u64);
+    zip.start_file(archive_name, options)
+        .with_context(|| format!("Failed to add {} to backup", archive_name))?;
+
+    let (size, sha256) = copy_with_checksum(&mut input, zip)
+        .with_context(|| format!("Failed to add {} to backup", archive_name))?;
+
+    Ok(BackupFileEntry {
+        path: archive_name.to_string(),
+        size,
+        sha256,
+    })
+}
+
+// Copy a stream while hashing it, returning the byte count and hex SHA-256
+fn copy_with_checksum(input: &mut impl Read, output: &mut impl Write) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        output.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+// Check every file against the manifest checksums and run SQLite's integrity
+// check on the archived database.
+pub async fn verify_backup(archive_path: &Path) -> AppResult<BackupManifest> {
+    let check_dir = archive_path.with_extension("verify");
+    let _ = fs::remove_dir_all(&check_dir);
+    fs::create_dir_all(&check_dir)
+        .context("Failed to create verification directory")?;
+
+    let result = verify_and_extract(archive_path, &check_dir, false).await;
+    let _ = fs::remove_dir_all(&check_dir);
+    result
+}
+
+// Verify a backup and extract all of it into `target_dir`, to be changed and
+// put back with `repack_backup`
+pub(crate) async fn unpack_backup(archive_path: &Path, target_dir: &Path) -> AppResult<BackupManifest> {
+    verify_and_extract(archive_path, target_dir, true).await
+}
+
+// Replace a backup with the contents of `source_dir`, laid out as
+// `unpack_backup` left them. The new archive is verified before the old one
+// is shredded and replaced.
+pub(crate) async fn repack_backup(archive_path: &Path, source_dir: &Path, created_at: DateTime<Utc>) -> AppResult<()> {
+    let repacked_path = archive_path.with_extension("repacked.zip");
+    let result = {
+        let repacked_path = repacked_path.clone();
+        let source_dir = source_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            write_archive(&repacked_path, &source_dir.join(DATABASE_FILE), &source_dir, created_at)
+        })
+        .await
+        .context("Backup task failed")
+        .and_then(|result| result)
+    };
+    let result = match result {
+        Ok(()) => verify_backup(&repacked_path).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        let _ = fs::remove_file(&repacked_path);
+        return Err(e).context("Rewritten backup failed verification");
+    }
+
+    crate::shred::shred_file(archive_path).context("Failed to shred the old backup")?;
+    fs::rename(&repacked_path, archive_path).context("Failed to move the rewritten backup into place")
+}
+
+// Verify an archive, extracting the database into `target_dir` (and the user
+// files too when `extract_files` is set)
+async fn verify_and_extract(
+    archive_path: &Path,
+    target_dir: &Path,
+    extract_files: bool,
+) -> AppResult<BackupManifest> {
+    let manifest = {
+        let archive_path = archive_path.to_path_buf();
+        let target_dir = target_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || verify_archive_contents(&archive_path, &target_dir, extract_files))
+            .await
+            .context("Backup verification task failed")??
+    };
+
+    check_database_integrity(&target_dir.join(DATABASE_FILE)).await?;
+
+    Ok(manifest)
+}
+
+fn verify_archive_contents(archive_path: &Path, target_dir: &Path, extract_files: bool) -> AppResult<BackupManifest> {
+    let file = File::open(archive_path)
+        .context("Failed to open backup")?;
+    let mut zip = ZipArchive::new(file)
+        .context("Backup is not a valid archive")?;
+
+    let manifest: BackupManifest = {
+        let entry = zip.by_name(MANIFEST_FILE)
+            .map_err(|_| AppError::Validation("Backup has no manifest".to_string()))?;
+        serde_json::from_reader(entry)
+            .context("Backup manifest is invalid")?
+    };
+
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(AppError::Validation(format!(
+            "Backup format version {} is newer than this app supports",
+            manifest.format_version
+        )));
+    }
+
+    if !manifest.files.iter().any(|entry| entry.path == DATABASE_FILE) {
+        return Err(AppError::Validation("Backup does not contain a database".to_string()));
+    }
+
+    for expected in &manifest.files {
+        let mut entry = zip.by_name(&expected.path)
+            .map_err(|_| AppError::Validation(format!("Backup is missing {}", expected.path)))?;
+
+        // Only extract names that stay inside the target directory
+        let relative = entry.enclosed_name()
+            .ok_or_else(|| AppError::Validation(format!("Backup contains an unsafe path: {}", expected.path)))?;
+
+        let (size, sha256) = if expected.path == DATABASE_FILE || extract_files {
+            let destination = target_dir.join(relative);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            let mut output = File::create(&destination)
+                .with_context(|| format!("Failed to extract {}", expected.path))?;
+            copy_with_checksum(&mut entry, &mut output)
+        } else {
+            copy_with_checksum(&mut entry, &mut io::sink())
+        }
+        .with_context(|| format!("Failed to read {} from backup", expected.path))?;
+
+        if size != expected.size || sha256 != expected.sha256 {
+            return Err(AppError::Validation(format!("Checksum mismatch for {}", expected.path)));
+        }
+    }
+
+    Ok(manifest)
+}
+
+async fn check_database_integrity(database_path: &Path) -> AppResult<()> {
+    // An encrypted vault's database can only be read with its passphrase,
+    // which need not be this vault's; the checksums still cover it
+    if crate::vault::is_encrypted_file(database_path) {
+        return Ok(());
+    }
+
+    let options = SqliteConnectOptions::new()
+        .filename(database_path)
+        .read_only(true);
+    let pool = SqlitePool::connect_with(options)
+        .await
+        .context("Failed to open backed up database")?;
+
+    let result: AppResult<()> = async {
+        let rows: Vec<(String,)> = sqlx::query_as("PRAGMA integrity_check")
+            .fetch_all(&pool)
+            .await
+            .context("Failed to check backed up database")?;
+        if rows.len() != 1 || rows[0].0 != "ok" {
+            let problems = rows.into_iter().map(|(row,)| row).collect::<Vec<_>>().join("; ");
+            return Err(AppError::Validation(format!("Backed up database is corrupt: {}", problems)));
+        }
+
+        sqlx::query("SELECT COUNT(*) FROM notes")
+            .fetch_one(&pool)
+            .await
+            .context("Backed up database has no notes table")?;
+
+        Ok(())
+    }
+    .await;
+
+    pool.close().await;
+    result
+}
+
+// When a backup was made, from its file name
+pub fn backup_time(file_name: &str) -> Option<DateTime<Utc>> {
+    let timestamp = file_name.strip_prefix(BACKUP_PREFIX)?.strip_suffix(".zip")?;
+    Some(NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?.and_utc())
+}
+
+pub(crate) fn backup_info(path: &Path) -> Option<BackupInfo> {
+    let file_name = path.file_name()?.to_str()?.to_string();
+    let created_at = backup_time(&file_name)?;
+    let size = fs::metadata(path).ok()?.len();
+
+    Some(BackupInfo {
+        file_name,
+        path: path.to_string_lossy().to_string(),
+        created_at,
+        size,
+    })
+}
+
+// Backups in the backups directory, newest first
+pub fn list_backups(app_dir: &Path) -> AppResult<Vec<BackupInfo>> {
+    let dir = backups_dir(app_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = fs::read_dir(&dir)
+        .context("Failed to read backups directory")?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| backup_info(&entry.path()))
+        .collect::<Vec<_>>();
+
+    backups.sort_by_key(|backup| Reverse(backup.created_at));
+    Ok(backups)
+}
+
+pub fn last_backup_time(app_dir: &Path) -> AppResult<Option<DateTime<Utc>>> {
+    Ok(list_backups(app_dir)?.first().map(|backup| backup.created_at))
+}
+
+// Refuse anything but a backup's plain file name, which could point outside
+// the backups directory
+pub fn check_backup_name(file_name: &str) -> AppResult<()> {
+    let is_plain_name = Path::new(file_name).file_name().and_then(|name| name.to_str()) == Some(file_name);
+    if !is_plain_name || !file_name.starts_with(BACKUP_PREFIX) || !file_name.ends_with(".zip") {
+        return Err(AppError::Validation(format!("Invalid backup name: {}", file_name)));
+    }
+    Ok(())
+}
+
+// Resolve a backup by file name, refusing anything outside the backups directory
+pub fn resolve_backup(app_dir: &Path, file_name: &str) -> AppResult<PathBuf> {
+    check_backup_name(file_name)?;
+
+    let path = backups_dir(app_dir).join(file_name);
+    if !path.is_file() {
+        return Err(AppError::not_found("backup", file_name));
+    }
+
+    Ok(path)
+}
+
+// Maps a backup time to its day, week or month for retention
+type RetentionBucket = fn(&DateTime<Utc>) -> (i32, u32, u32);
+
+// Pick the backups that fall outside the retention policy: the newest backup
+// of each of the last `keep_daily` days, `keep_weekly` ISO weeks and
+// `keep_monthly` months is kept, as is the newest backup overall.
+pub fn backups_to_prune(backups: &[BackupInfo], retention: &Retention) -> Vec<BackupInfo> {
+    let mut newest_first = backups.to_vec();
+    newest_first.sort_by_key(|backup| Reverse(backup.created_at));
+
+    let mut keep = HashSet::new();
+    if let Some(newest) = newest_first.first() {
+        keep.insert(newest.file_name.clone());
+    }
+
+    let buckets: [(u64, RetentionBucket); 3] = [
+        (retention.keep_daily, |date| (date.year(), date.month(), date.day())),
+        (retention.keep_weekly, |date| (date.iso_week().year(), date.iso_week().week(), 0)),
+        (retention.keep_monthly, |date| (date.year(), date.month(), 0)),
+    ];
+
+    for (count, bucket_of) in buckets {
+        let mut seen = HashSet::new();
+        for backup in &newest_first {
+            if seen.len() as u64 >= count {
+                break;
+            }
+            if seen.insert(bucket_of(&backup.created_at)) {
+                keep.insert(backup.file_name.clone());
+            }
+        }
+    }
+
+    newest_first
+        .into_iter()
+        .filter(|backup| !keep.contains(&backup.file_name))
+        .collect()
+}
+
+pub fn prune_backups(app_dir: &Path, settings: &BackupSettings) -> AppResult<Vec<BackupInfo>> {
+    let pruned = backups_to_prune(&list_backups(app_dir)?, &settings.retention());
+
+    for backup in &pruned {
+        fs::remove_file(&backup.path)
+            .with_context(|| format!("Failed to remove old backup {}", backup.file_name))?;
+    }
+
+    Ok(pruned)
+}
+
+// Periodically create a backup once the configured interval has passed since
+// the last one, then apply the retention policy. Runs for the app's lifetime.
+pub async fn run_backup_scheduler(pool: SqlitePool, app_dir: PathBuf) {
+    loop {
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+
+        if let Err(e) = run_scheduled_backup(&pool, &app_dir).await {
+            eprintln!("Scheduled backup failed: {}", e);
+        }
+    }
+}
+
+async fn run_scheduled_backup(pool: &SqlitePool, app_dir: &Path) -> AppResult<()> {
+    let settings = BackupSettings::load(pool).await?;
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    // Uploads cut short last time are finished first
+    let remote = S3BackupSettings::load(pool).await?;
+    if remote.enabled {
+        for uploaded in s3_backup::resume_uploads(app_dir, &remote).await? {
+            eprintln!("Uploaded backup {} to {}", uploaded.file_name, remote.bucket);
+        }
+    }
+
+    let interval = Duration::hours(settings.interval_hours as i64);
+    if let Some(last_backup) = last_backup_time(app_dir)? {
+        if Utc::now() - last_backup < interval {
+            return Ok(());
+        }
+    }
+
+    let backup = create_backup(pool, app_dir).await?;
+    eprintln!("Created scheduled backup {}", backup.file_name);
+
+    for pruned in prune_backups(app_dir, &settings)? {
+        eprintln!("Removed old backup {}", pruned.file_name);
+    }
+
+    if remote.enabled {
+        s3_backup::upload_backup(app_dir, &remote, &backup.file_name).await?;
+        eprintln!("Uploaded backup {} to {}", backup.file_name, remote.bucket);
+        for pruned in s3_backup::prune_remote_backups(&remote).await? {
+            eprintln!("Removed old backup {} from {}", pruned.file_name, remote.bucket);
+        }
+    }
+
+    Ok(())
+}
+
+// Verify a backup and extract it next to the live data. The swap happens on
+// the next start, before the database is opened (see `apply_pending_restore`).
+// The database is restored where it lives now, or to the default location
+// with `reset_location`.
+pub async fn stage_restore(app_dir: &Path, file_name: &str, reset_location: bool) -> AppResult<BackupManifest> {
+    let archive_path = resolve_backup(app_dir, file_name)?;
+    let staging_dir = app_dir.join(RESTORE_DIR);
+    let _ = fs::remove_dir_all(&staging_dir);
+    fs::create_dir_all(&staging_dir)
+        .context("Failed to create restore directory")?;
+
+    let result = async {
+        let manifest = verify_and_extract(&archive_path, &staging_dir, true).await?;
+        if reset_location {
+            fs::write(staging_dir.join(RESET_LOCATION_MARKER), b"")
+                .context("Failed to stage restore")?;
+        }
+        fs::write(staging_dir.join(RESTORE_READY_MARKER), file_name)
+            .context("Failed to mark restore as ready")?;
+        Ok(manifest)
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging_dir);
+    }
+
+    result
+}
+
+// Swap a staged restore into place, moving the current database and files
+// into a `pre-restore-<timestamp>` directory. The database goes wherever the
+// database location points. Must run before the database is opened. Returns
+// whether a restore was applied.
+pub fn apply_pending_restore(app_dir: &Path) -> AppResult<bool> {
+    let staging_dir = app_dir.join(RESTORE_DIR);
+    if !staging_dir.exists() {
+        return Ok(false);
+    }
+
+    if !staging_dir.join(RESTORE_READY_MARKER).exists() {
+        // Staging was interrupted, the backup was never fully verified
+        let _ = fs::remove_dir_all(&staging_dir);
+        return Ok(false);
+    }
+
+    // The database elsewhere is left where it is
+    if staging_dir.join(RESET_LOCATION_MARKER).exists() {
+        recovery::set_database_location(app_dir, None)?;
+    }
+    let database_path = recovery::database_path(app_dir);
+
+    let aside_dir = app_dir.join(format!("{}{}", PRE_RESTORE_PREFIX, Utc::now().format(TIMESTAMP_FORMAT)));
+    fs::create_dir_all(&aside_dir)
+        .with_context(|| format!("Failed to create {}", aside_dir.display()))?;
+
+    let current_files = recovery::database_files(&database_path)
+        .into_iter()
+        .chain(FILE_DIRECTORIES.iter().map(|name| app_dir.join(name)));
+    for path in current_files {
+        if let Some(name) = path.file_name().filter(|_| path.exists()) {
+            move_path(&path, &aside_dir.join(name))
+                .with_context(|| format!("Failed to move {} aside", path.display()))?;
+        }
+    }
+
+    let staged_files = std::iter::once((staging_dir.join(DATABASE_FILE), database_path))
+        .chain(FILE_DIRECTORIES.iter().map(|name| (staging_dir.join(name), app_dir.join(name))));
+    for (staged, target) in staged_files {
+        if staged.exists() {
+            move_path(&staged, &target)
+                .with_context(|| format!("Failed to restore {}", target.display()))?;
+        }
+    }
+
+    let _ = fs::remove_dir_all(&staging_dir);
+    Ok(true)
+}
+
+// Rename, or for a database on another drive, copy and remove the original
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices && from.is_file() => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+// Point image and attachment records at this machine's data directory, for
+// backups restored from another install or user account
+pub async fn relocate_file_paths(pool: &SqlitePool, app_dir: &Path) -> AppResult<()> {
+    for (table, directory) in [("images", "images"), ("attachments", "attachments")] {
+        let prefix = format!("{}{}", app_dir.join(directory).display(), std::path::MAIN_SEPARATOR);
+        sqlx::query(&format!("UPDATE {} SET file_path = ?1 || filename", table))
+            .bind(prefix)
+            .execute(pool)
+            .await
+            .with_context(|| format!("Failed to update {} paths", table))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+// Noticing note changes made by any process sharing the database, for front
+// ends that push updates to their clients. SQLite has no cross-process change
+// feed, so this compares snapshots of every note's `updated_at`.
+use crate::error::{AppResult, Context};
+use crate::NoteStore;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteChange {
+    pub id: String,
+    pub kind: ChangeKind,
+    // None for deleted notes
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+pub struct ChangeWatcher {
+    known: HashMap<String, DateTime<Utc>>,
+}
+
+impl ChangeWatcher {
+    // Start from the current state; only later changes are reported
+    pub async fn new(store: &NoteStore) -> AppResult<Self> {
+        Ok(ChangeWatcher {
+            known: snapshot(store).await?,
+        })
+    }
+
+    // Changes since the previous call, deletions last
+    pub async fn poll(&mut self, store: &NoteStore) -> AppResult<Vec<NoteChange>> {
+        let current = snapshot(store).await?;
+        let mut changes = Vec::new();
+
+        for (id, updated_at) in &current {
+            let kind = match self.known.get(id) {
+                None => ChangeKind::Created,
+                Some(known) if known != updated_at => ChangeKind::Updated,
+                Some(_) => continue,
+            };
+            changes.push(NoteChange { id: id.clone(), kind, updated_at: Some(*updated_at) });
+        }
+        changes.sort_by_key(|change| change.updated_at);
+
+        for id in self.known.keys().filter(|id| !current.contains_key(*id)) {
+            changes.push(NoteChange { id: id.clone(), kind: ChangeKind::Deleted, updated_at: None });
+        }
+
+        self.known = current;
+        Ok(changes)
+    }
+}
+
+async fn snapshot(store: &NoteStore) -> AppResult<HashMap<String, DateTime<Utc>>> {
+    let rows: Vec<(String, DateTime<Utc>)> = sqlx::query_as("SELECT id, updated_at FROM notes")
+        .fetch_all(store.pool())
+        .await
+        .context("Failed to check for note changes")?;
+    Ok(rows.into_iter().collect())
+}
@@ -0,0 +1,337 @@
+// Live co-editing of notes between Notura instances on a local network. One
+// instance runs the WebSocket server and others join a note on it with the
+// server's token. Both ends speak the y-websocket sync protocol: each sends
+// its state vector, answers the other's with whatever that side is missing,
+// and from then on passes every update along as it happens. The merging
+// itself is done by the notes' Yjs documents, see `documents.rs`.
+use crate::app_lock::AppLock;
+use crate::error::{AppError, AppResult, Context};
+use crate::http_api::constant_time_eq;
+use crate::settings::CollabSettings;
+use crate::NoteStore;
+use futures_util::{SinkExt, StreamExt};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, oneshot, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{header, HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+use yrs::sync::{Message as SyncEnvelope, SyncMessage};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::StateVector;
+
+// Notes are shared at ws://<host>:<port>/notes/<note id>
+const NOTES_PATH: &str = "/notes/";
+// Close code for a note the server does not have
+const CLOSE_NOT_FOUND: u16 = 4404;
+
+// Tells connections apart so nobody is sent their own updates back
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+// A running server; dropping it without `stop` leaves it running
+pub struct CollabServer {
+    addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl CollabServer {
+    // Listen on every interface so other machines can join, on the configured
+    // port or any free port for 0. Nothing is served while the app is locked,
+    // and locking it closes the connections.
+    pub async fn start(store: NoteStore, lock: Arc<AppLock>, settings: &CollabSettings) -> AppResult<CollabServer> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, settings.port))
+            .await
+            .with_context(|| format!("Failed to listen on port {}", settings.port))?;
+        let addr = listener.local_addr().context("Failed to read the collaboration address")?;
+        let token: Arc<str> = Arc::from(settings.token.as_str());
+
+        let (shutdown, mut stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let (closing, _) = watch::channel(false);
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => {
+                            connections.spawn(serve(store.clone(), lock.clone(), stream, token.clone(), closing.subscribe()));
+                        }
+                        Err(e) => eprintln!("Warning: Failed to accept a collaborator: {}", e),
+                    },
+                    Some(finished) = connections.join_next(), if !connections.is_empty() => {
+                        if let Ok(Err(e)) = finished {
+                            eprintln!("Warning: Collaboration connection ended: {}", e);
+                        }
+                    }
+                }
+            }
+
+            let _ = closing.send(true);
+            while connections.join_next().await.is_some() {}
+        });
+
+        Ok(CollabServer { addr, shutdown, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Stop listening and close every connection
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+async fn serve(
+    store: NoteStore,
+    lock: Arc<AppLock>,
+    stream: TcpStream,
+    token: Arc<str>,
+    mut closing: watch::Receiver<bool>,
+) -> AppResult<()> {
+    let mut note_id = String::new();
+    let authorize = Authorize { token: &token, lock: &lock, note_id: &mut note_id };
+    let mut socket = tokio_tungstenite::accept_hdr_async(stream, authorize).await?;
+
+    if let Err(AppError::NotFound { .. }) = store.get_note(&note_id).await {
+        let frame = CloseFrame {
+            code: CloseCode::from(CLOSE_NOT_FOUND),
+            reason: format!("Note {} not found", note_id).into(),
+        };
+        let _ = socket.close(Some(frame)).await;
+        return Ok(());
+    }
+
+    let stop = async move {
+        tokio::select! {
+            _ = closing.wait_for(|closing| *closing) => {}
+            _ = lock.until_locked() => {}
+        }
+    };
+    sync_note(store, socket, note_id, stop).await
+}
+
+// Checks the handshake for the token, either as `Authorization: Bearer
+// <token>` or, for browsers, as `?token=`, and notes which note it asks for
+struct Authorize<'a> {
+    token: &'a str,
+    lock: &'a AppLock,
+    note_id: &'a mut String,
+}
+
+impl Callback for Authorize<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let presented = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| {
+                request
+                    .uri()
+                    .query()
+                    .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("token=")))
+            })
+            .unwrap_or_default();
+        if !constant_time_eq(presented.trim().as_bytes(), self.token.as_bytes()) {
+            return Err(error_response(StatusCode::UNAUTHORIZED, "Missing or invalid collaboration token"));
+        }
+        if let Err(e) = self.lock.check_unlocked() {
+            return Err(error_response(StatusCode::LOCKED, &e.to_string()));
+        }
+
+        match request.uri().path().strip_prefix(NOTES_PATH) {
+            Some(id) if !id.is_empty() && !id.contains('/') => {
+                *self.note_id = id.to_string();
+                Ok(response)
+            }
+            _ => Err(error_response(StatusCode::NOT_FOUND, "Notes are shared at /notes/<id>")),
+        }
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(message.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+// A connection to another instance's server, syncing one note
+pub struct CollabSession {
+    note_id: String,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<AppResult<()>>,
+}
+
+impl CollabSession {
+    // Join the note on the server at `address`, `host:port` or a ws:// URL. A
+    // note this vault does not have yet is created from the shared copy.
+    pub async fn join(store: NoteStore, address: &str, token: &str, note_id: &str) -> AppResult<CollabSession> {
+        let address = address.trim().trim_end_matches('/');
+        let url = if address.contains("://") {
+            format!("{}{}{}", address, NOTES_PATH, note_id)
+        } else {
+            format!("ws://{}{}{}", address, NOTES_PATH, note_id)
+        };
+        let mut request = url
+            .into_client_request()
+            .map_err(|e| AppError::Validation(format!("Invalid collaboration address {}: {}", address, e)))?;
+        let authorization = HeaderValue::from_str(&format!("Bearer {}", token.trim()))
+            .map_err(|_| AppError::Validation("Invalid collaboration token".to_string()))?;
+        request.headers_mut().insert(header::AUTHORIZATION, authorization);
+
+        let socket = match tokio_tungstenite::connect_async(request).await {
+            Ok((socket, _)) => socket,
+            Err(WsError::Http(response)) if response.status() == StatusCode::UNAUTHORIZED => {
+                return Err(AppError::Unauthorized(format!("{} refused the collaboration token", address)));
+            }
+            Err(WsError::Http(response)) if response.status() == StatusCode::LOCKED => {
+                return Err(AppError::Locked(format!("{} is locked", address)));
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to connect to {}", address)),
+        };
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let stop = async move {
+            let _ = stopped.await;
+        };
+        let task = tokio::spawn(sync_note(store, socket, note_id.to_string(), stop));
+
+        Ok(CollabSession {
+            note_id: note_id.to_string(),
+            shutdown,
+            task,
+        })
+    }
+
+    pub fn note_id(&self) -> &str {
+        &self.note_id
+    }
+
+    // False once either side closed the connection or it failed
+    pub fn is_connected(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    // Close the connection; the error it ended with, if it already had
+    pub async fn leave(self) -> AppResult<()> {
+        let _ = self.shutdown.send(());
+        self.task.await?
+    }
+}
+
+// Keep the note in step with the other end until `stop` completes or the
+// connection ends
+async fn sync_note<S>(
+    store: NoteStore,
+    mut socket: WebSocketStream<S>,
+    note_id: String,
+    stop: impl Future<Output = ()>,
+) -> AppResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let origin = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    let mut updates = store.subscribe_document_updates();
+    tokio::pin!(stop);
+
+    // Only the joining side can lack the note; it is created from the first
+    // update the server sends
+    let mut missing = match store.get_note(&note_id).await {
+        Ok(_) => false,
+        Err(AppError::NotFound { .. }) => true,
+        Err(e) => return Err(e),
+    };
+    let state_vector = if missing {
+        StateVector::default()
+    } else {
+        store.document_state_vector(&note_id).await?
+    };
+    send(&mut socket, SyncMessage::SyncStep1(state_vector)).await?;
+
+    loop {
+        tokio::select! {
+            _ = &mut stop => {
+                let _ = socket.close(None).await;
+                return Ok(());
+            }
+            message = socket.next() => {
+                let data = match message {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(frame))) => return closed(frame, &note_id),
+                    // Pings are answered by the socket itself
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(AppError::from(e)),
+                    None => return Ok(()),
+                };
+
+                match SyncEnvelope::decode_v1(&data) {
+                    Ok(SyncEnvelope::Sync(SyncMessage::SyncStep1(state_vector))) => {
+                        let update = if missing {
+                            yrs::Update::new().encode_v1()
+                        } else {
+                            store.document_diff(&note_id, &state_vector).await?
+                        };
+                        send(&mut socket, SyncMessage::SyncStep2(update)).await?;
+                    }
+                    Ok(SyncEnvelope::Sync(SyncMessage::SyncStep2(update) | SyncMessage::Update(update))) => {
+                        if missing {
+                            store.create_note_from_document(&note_id, &update).await?;
+                            missing = false;
+                        } else {
+                            store.apply_document_update(&note_id, &update, origin).await?;
+                        }
+                    }
+                    // Awareness (who is here, where their cursor is) is not used
+                    Ok(_) => {}
+                    Err(e) => {
+                        return Err(AppError::Validation(format!("Malformed collaboration message: {}", e)));
+                    }
+                }
+            }
+            update = updates.recv() => match update {
+                Ok(update) if update.note_id == note_id && update.origin != Some(origin) && !missing => {
+                    send(&mut socket, SyncMessage::Update(update.update)).await?;
+                }
+                Ok(_) => {}
+                // Some updates were missed; the whole document covers them
+                Err(broadcast::error::RecvError::Lagged(_)) if !missing => {
+                    let update = store.document_diff(&note_id, &StateVector::default()).await?;
+                    send(&mut socket, SyncMessage::Update(update)).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn send<S>(socket: &mut WebSocketStream<S>, message: SyncMessage) -> AppResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    socket
+        .send(Message::binary(SyncEnvelope::Sync(message).encode_v1()))
+        .await
+        .context("Failed to send to collaborator")
+}
+
+fn closed(frame: Option<CloseFrame>, note_id: &str) -> AppResult<()> {
+    match frame {
+        Some(frame) if frame.code == CloseCode::from(CLOSE_NOT_FOUND) => Err(AppError::not_found("note", note_id)),
+        _ => Ok(()),
+    }
+}
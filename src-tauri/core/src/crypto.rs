@@ -0,0 +1,223 @@
+// Passphrase encryption for data that leaves this machine. Data is encrypted
+// in chunks with XChaCha20-Poly1305 in the STREAM construction, so files of
+// any size never have to fit in memory and a truncated, reordered or altered
+// chunk is detected. The key comes from the passphrase through Argon2id with
+// a random salt; salt and nonce are stored in the header. Data that stays on
+// this machine under a key kept elsewhere, like an encrypted vault's images,
+// uses the same format keyed directly.
+use crate::error::{AppError, AppResult, Context};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::XChaCha20Poly1305;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8; 8] = b"NTRENC01";
+const KEY_MAGIC: &[u8; 8] = b"NTRKEY01";
+pub const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+// The 24-byte XChaCha nonce minus the 5 bytes STREAM uses for its counter
+const NONCE_LEN: usize = 19;
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+fn cipher(passphrase: &str, salt: &[u8]) -> AppResult<XChaCha20Poly1305> {
+    if passphrase.is_empty() {
+        return Err(AppError::Validation("The encryption passphrase is empty".to_string()));
+    }
+
+    let key = derive_key(passphrase, salt)?;
+    Ok(XChaCha20Poly1305::new((&*key).into()))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> AppResult<Zeroizing<[u8; KEY_LEN]>> {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| AppError::Internal(format!("Failed to derive the encryption key: {}", e)))?;
+    Ok(key)
+}
+
+// The key `encrypt` derived for one piece of data, kept to read and rewrite
+// that data without holding on to the passphrase. What it writes has the
+// same salt and can still be decrypted with the passphrase. The key is wiped
+// from memory when dropped.
+pub struct PassphraseKey {
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; KEY_LEN]>,
+}
+
+impl PassphraseKey {
+    // The key `encrypt` used for `data` with `passphrase`. A wrong passphrase
+    // gives a key that fails to decrypt.
+    pub fn for_data(data: &[u8], passphrase: &str) -> AppResult<PassphraseKey> {
+        let salt = data
+            .strip_prefix(MAGIC)
+            .and_then(|rest| rest.get(..SALT_LEN))
+            .and_then(|salt| <[u8; SALT_LEN]>::try_from(salt).ok())
+            .ok_or_else(|| AppError::Validation("The data is not encrypted by Notura".to_string()))?;
+        if passphrase.is_empty() {
+            return Err(AppError::Validation("The encryption passphrase is empty".to_string()));
+        }
+        Ok(PassphraseKey { salt, key: derive_key(passphrase, &salt)? })
+    }
+
+    pub fn encrypt(&self, input: &mut impl Read, output: &mut impl Write) -> AppResult<()> {
+        output.write_all(MAGIC)?;
+        output.write_all(&self.salt)?;
+        encrypt_stream(input, output, XChaCha20Poly1305::new((&*self.key).into()))
+    }
+
+    pub fn decrypt(&self, input: &mut impl Read, output: &mut impl Write) -> AppResult<()> {
+        let mut header = [0u8; MAGIC.len() + SALT_LEN];
+        if read_full(input, &mut header)? < header.len() || &header[..MAGIC.len()] != MAGIC {
+            return Err(AppError::Validation("The data is not encrypted by Notura".to_string()));
+        }
+        if header[MAGIC.len()..] != self.salt {
+            return Err(decryption_failed());
+        }
+        decrypt_stream(input, output, XChaCha20Poly1305::new((&*self.key).into()))
+    }
+}
+
+pub fn encrypt(input: &mut impl Read, output: &mut impl Write, passphrase: &str) -> AppResult<()> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = cipher(passphrase, &salt)?;
+
+    output.write_all(MAGIC)?;
+    output.write_all(&salt)?;
+    encrypt_stream(input, output, cipher)
+}
+
+pub fn decrypt(input: &mut impl Read, output: &mut impl Write, passphrase: &str) -> AppResult<()> {
+    let mut header = [0u8; MAGIC.len() + SALT_LEN];
+    if read_full(input, &mut header)? < header.len() || &header[..MAGIC.len()] != MAGIC {
+        return Err(AppError::Validation("The data is not encrypted by Notura".to_string()));
+    }
+    decrypt_stream(input, output, cipher(passphrase, &header[MAGIC.len()..])?)
+}
+
+pub fn encrypt_with_key(input: &mut impl Read, output: &mut impl Write, key: &[u8; KEY_LEN]) -> AppResult<()> {
+    output.write_all(KEY_MAGIC)?;
+    encrypt_stream(input, output, XChaCha20Poly1305::new(key.into()))
+}
+
+pub fn decrypt_with_key(input: &mut impl Read, output: &mut impl Write, key: &[u8; KEY_LEN]) -> AppResult<()> {
+    let mut header = [0u8; KEY_MAGIC.len()];
+    if read_full(input, &mut header)? < header.len() || &header != KEY_MAGIC {
+        return Err(AppError::Validation("The data is not encrypted by Notura".to_string()));
+    }
+    decrypt_stream(input, output, XChaCha20Poly1305::new(key.into()))
+}
+
+// Whether `data` starts like something `encrypt_with_key` wrote
+pub fn is_key_encrypted(data: &[u8]) -> bool {
+    data.starts_with(KEY_MAGIC)
+}
+
+fn encrypt_stream(input: &mut impl Read, output: &mut impl Write, cipher: XChaCha20Poly1305) -> AppResult<()> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let mut encryptor = EncryptorBE32::from_aead(cipher, &nonce.into());
+    output.write_all(&nonce)?;
+
+    // Every chunk but the last is full, so a short one marks the end; data
+    // that fills its last chunk exactly is followed by an empty one
+    let mut chunk = vec![0u8; CHUNK_LEN];
+    loop {
+        let read = read_full(input, &mut chunk)?;
+        if read < CHUNK_LEN {
+            let sealed = encryptor.encrypt_last(&chunk[..read]).map_err(|_| encryption_failed())?;
+            output.write_all(&sealed)?;
+            return Ok(());
+        }
+        let sealed = encryptor.encrypt_next(&chunk[..]).map_err(|_| encryption_failed())?;
+        output.write_all(&sealed)?;
+    }
+}
+
+fn decrypt_stream(input: &mut impl Read, output: &mut impl Write, cipher: XChaCha20Poly1305) -> AppResult<()> {
+    let mut nonce = [0u8; NONCE_LEN];
+    if read_full(input, &mut nonce)? < nonce.len() {
+        return Err(decryption_failed());
+    }
+    let mut decryptor = DecryptorBE32::from_aead(cipher, &nonce.into());
+
+    let mut chunk = vec![0u8; CHUNK_LEN + TAG_LEN];
+    loop {
+        let read = read_full(input, &mut chunk)?;
+        if read < chunk.len() {
+            let plain = decryptor.decrypt_last(&chunk[..read]).map_err(|_| decryption_failed())?;
+            output.write_all(&plain)?;
+            return Ok(());
+        }
+        let plain = decryptor.decrypt_next(&chunk[..]).map_err(|_| decryption_failed())?;
+        output.write_all(&plain)?;
+    }
+}
+
+// Encrypt or decrypt `source` into `destination`, which only appears once
+// it is complete
+pub fn encrypt_file(source: &Path, destination: &Path, passphrase: &str) -> AppResult<()> {
+    transform_file(source, destination, |input, output| encrypt(input, output, passphrase))
+}
+
+pub fn decrypt_file(source: &Path, destination: &Path, passphrase: &str) -> AppResult<()> {
+    transform_file(source, destination, |input, output| decrypt(input, output, passphrase))
+}
+
+fn transform_file(
+    source: &Path,
+    destination: &Path,
+    transform: impl FnOnce(&mut BufReader<File>, &mut BufWriter<File>) -> AppResult<()>,
+) -> AppResult<()> {
+    let temp_path = destination.with_extension("crypt.tmp");
+    let result = (|| -> AppResult<()> {
+        let mut input = BufReader::new(File::open(source).with_context(|| format!("Failed to read {}", source.display()))?);
+        let mut output = BufWriter::new(File::create(&temp_path).with_context(|| format!("Failed to create {}", temp_path.display()))?);
+        transform(&mut input, &mut output)?;
+        output
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+        fs::rename(&temp_path, destination).with_context(|| format!("Failed to move {} into place", destination.display()))
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+pub fn random_bytes(buffer: &mut [u8]) {
+    OsRng.fill_bytes(buffer);
+}
+
+// Like `read_exact`, but a short read at the end is fine; returns how much
+// was read
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn encryption_failed() -> AppError {
+    AppError::Internal("Failed to encrypt".to_string())
+}
+
+fn decryption_failed() -> AppError {
+    AppError::Unauthorized("Wrong passphrase, or the encrypted data is damaged".to_string())
+}
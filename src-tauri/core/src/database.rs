@@ -0,0 +1,498 @@
+use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DatabaseError {
+    #[error("Database connection error: {0}")]
+    Connection(#[from] sqlx::Error),
+    #[error("Migration error: {0}")]
+    Migration(String),
+    #[error("Query error: {0}")]
+    Query(String),
+    #[error("Restore error: {0}")]
+    Restore(String),
+    #[error("The vault is encrypted, enter its passphrase")]
+    Locked,
+    #[error("Wrong vault passphrase")]
+    WrongPassphrase,
+    #[error("The vault is not encrypted")]
+    NotEncrypted,
+    #[error("The database is damaged: {0}")]
+    Corrupt(String),
+}
+
+pub type DatabaseResult<T> = Result<T, DatabaseError>;
+
+const BUSY_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Open the database in `app_dir`, applying a staged restore first, and bring
+// its schema up to date. Anything printed goes to stderr, stdout belongs to
+// whichever front end is driving the store.
+pub async fn open_database(app_dir: &Path) -> DatabaseResult<SqlitePool> {
+    open_database_with_key(app_dir, None).await
+}
+
+// Like `open_database`, for an encrypted vault when given its passphrase
+pub async fn open_database_with_key(app_dir: &Path, passphrase: Option<&str>) -> DatabaseResult<SqlitePool> {
+    std::fs::create_dir_all(app_dir)
+        .map_err(|e| {
+            eprintln!("Failed to create app directory: {}", e);
+            DatabaseError::Connection(sqlx::Error::Io(e))
+        })?;
+    
+    // A restore staged by the previous run is swapped in before anything opens the database
+    let restored = crate::backup::apply_pending_restore(app_dir)
+        .map_err(|e| DatabaseError::Restore(e.to_string()))?;
+    if restored {
+        // The backup passed its checksums, so a database in it without the
+        // SQLite header is an encrypted one
+        let encrypted = crate::vault::is_encrypted_file(&crate::recovery::database_path(app_dir));
+        crate::vault::set_marked(app_dir, encrypted)
+            .map_err(|e| DatabaseError::Restore(e.to_string()))?;
+        eprintln!("Restored database from backup");
+    }
+    
+    let database_path = crate::recovery::database_path(app_dir);
+    // Without the marker a file lacking the SQLite header is damaged rather
+    // than encrypted. Given the passphrase it is still tried, and marked if
+    // the passphrase opens it.
+    match (passphrase, crate::vault::is_encrypted_file(&database_path), crate::vault::is_marked(app_dir)) {
+        (None, true, true) => return Err(DatabaseError::Locked),
+        (None, true, false) => {
+            return Err(DatabaseError::Corrupt(format!("{} is not a SQLite database", database_path.display())));
+        }
+        (Some(_), false, _) if database_path.exists() => return Err(DatabaseError::NotEncrypted),
+        _ => {}
+    }
+    
+    // Use connection options to ensure the database file is created properly.
+    // The app and the command-line tool may have the database open at the same
+    // time; WAL lets readers run alongside a writer and the busy timeout makes a
+    // second writer wait for the lock instead of failing straight away.
+    let connection_options = SqliteConnectOptions::from_str(&format!("sqlite:{}", database_path.display()))?
+        .create_if_missing(true)
+        .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
+        .synchronous(sqlx::sqlite::SqliteSynchronous::Normal)
+        .busy_timeout(BUSY_TIMEOUT)
+        // Deleted content is overwritten rather than left in free space; see
+        // shred.rs
+        .pragma("secure_delete", "ON");
+    // SQLCipher needs the key before anything else touches the file
+    let connection_options = match passphrase {
+        Some(passphrase) => connection_options.pragma("key", crate::vault::key_pragma(passphrase)),
+        None => connection_options,
+    };
+    
+    let pool = SqlitePool::connect_with(connection_options).await
+        .map_err(|e| {
+            if passphrase.is_some() && crate::vault::is_not_a_database(&e) {
+                return DatabaseError::WrongPassphrase;
+            }
+            eprintln!("Failed to connect to database: {}", e);
+            DatabaseError::Connection(e)
+        })?;
+    
+    run_migrations(&pool).await?;
+    if passphrase.is_some() {
+        crate::vault::set_marked(app_dir, true).map_err(|e| DatabaseError::Query(e.to_string()))?;
+    }
+    
+    if restored {
+        crate::backup::relocate_file_paths(&pool, app_dir)
+            .await
+            .map_err(|e| DatabaseError::Restore(e.to_string()))?;
+    }
+    
+    Ok(pool)
+}
+
+pub async fn run_migrations(pool: &SqlitePool) -> DatabaseResult<()> {
+    // Create notes table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS notes (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            content TEXT NOT NULL,
+            collection_id TEXT,
+            tags TEXT, -- JSON array
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            word_count INTEGER DEFAULT 0,
+            character_count INTEGER DEFAULT 0,
+            is_archived BOOLEAN DEFAULT FALSE,
+            is_encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+            FOREIGN KEY (collection_id) REFERENCES collections(id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    add_column_if_missing(pool, "notes", "is_encrypted", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    
+    // Create collections table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS collections (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT,
+            parent_id TEXT,
+            color TEXT,
+            icon TEXT,
+            sort_order INTEGER DEFAULT 0,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (parent_id) REFERENCES collections(id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // The search index mirrors this view rather than the notes table, so
+    // encrypted notes are never indexed
+    sqlx::query(
+        r#"
+        CREATE VIEW IF NOT EXISTS notes_searchable AS
+        SELECT rowid AS note_rowid, title, content, tags FROM notes WHERE NOT is_encrypted
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // An index over the whole notes table, from before notes could be
+    // encrypted, is replaced along with its triggers
+    let outdated_index: Option<(String,)> = sqlx::query_as(
+        "SELECT name FROM sqlite_master WHERE name = 'notes_fts' AND sql LIKE '%content=''notes''%'"
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    if outdated_index.is_some() {
+        for statement in [
+            "DROP TRIGGER IF EXISTS notes_fts_insert",
+            "DROP TRIGGER IF EXISTS notes_fts_delete",
+            "DROP TRIGGER IF EXISTS notes_fts_update",
+            "DROP TABLE notes_fts",
+        ] {
+            sqlx::query(statement)
+                .execute(pool)
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+        }
+    }
+    
+    // Create FTS5 virtual table for full-text search
+    sqlx::query(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(
+            title, content, tags,
+            content='notes_searchable',
+            content_rowid='note_rowid'
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // Create triggers to keep FTS table in sync
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS notes_fts_insert AFTER INSERT ON notes WHEN NOT new.is_encrypted BEGIN
+            INSERT INTO notes_fts(rowid, title, content, tags) 
+            VALUES (new.rowid, new.title, new.content, new.tags);
+        END
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS notes_fts_delete AFTER DELETE ON notes WHEN NOT old.is_encrypted BEGIN
+            INSERT INTO notes_fts(notes_fts, rowid, title, content, tags) 
+            VALUES('delete', old.rowid, old.title, old.content, old.tags);
+        END
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // A note that gets encrypted or decrypted leaves or joins the index
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS notes_fts_update AFTER UPDATE ON notes BEGIN
+            INSERT INTO notes_fts(notes_fts, rowid, title, content, tags) 
+            SELECT 'delete', old.rowid, old.title, old.content, old.tags WHERE NOT old.is_encrypted;
+            INSERT INTO notes_fts(rowid, title, content, tags) 
+            SELECT new.rowid, new.title, new.content, new.tags WHERE NOT new.is_encrypted;
+        END
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    if outdated_index.is_some() {
+        sqlx::query("INSERT INTO notes_fts(notes_fts) VALUES ('rebuild')")
+            .execute(pool)
+            .await
+            .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    }
+    
+    // Create images table
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS images (
+            id TEXT PRIMARY KEY,
+            filename TEXT NOT NULL,
+            original_name TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            mime_type TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // Create note_images junction table for many-to-many relationship
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS note_images (
+            note_id TEXT NOT NULL,
+            image_id TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (note_id, image_id),
+            FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE,
+            FOREIGN KEY (image_id) REFERENCES images(id) ON DELETE CASCADE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // Create attachments table for arbitrary files linked to notes
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS attachments (
+            id TEXT PRIMARY KEY,
+            filename TEXT NOT NULL,
+            original_name TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            mime_type TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS note_attachments (
+            note_id TEXT NOT NULL,
+            attachment_id TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (note_id, attachment_id),
+            FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE,
+            FOREIGN KEY (attachment_id) REFERENCES attachments(id) ON DELETE CASCADE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // Create FTS5 table for text extracted from attachments
+    sqlx::query(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS attachments_fts USING fts5(
+            attachment_id UNINDEXED, name, content
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS attachments_fts_delete AFTER DELETE ON attachments BEGIN
+            DELETE FROM attachments_fts WHERE attachment_id = old.id;
+        END
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // Create settings table for user-configurable options
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // Every insert, update and delete of a synced record is logged, and
+    // deletions leave a tombstone, so sync can find what changed since it
+    // last ran. Image links belong to their note's record.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS change_log (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            entity TEXT NOT NULL,
+            entity_id TEXT NOT NULL,
+            changed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tombstones (
+            entity TEXT NOT NULL,
+            entity_id TEXT NOT NULL,
+            deleted_at DATETIME NOT NULL,
+            PRIMARY KEY (entity, entity_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    for (table, entity) in [("notes", "note"), ("collections", "collection"), ("images", "image")] {
+        let triggers = [
+            format!(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS {table}_changes_insert AFTER INSERT ON {table} BEGIN
+                    INSERT INTO change_log (entity, entity_id) VALUES ('{entity}', new.id);
+                    DELETE FROM tombstones WHERE entity = '{entity}' AND entity_id = new.id;
+                END
+                "#
+            ),
+            format!(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS {table}_changes_update AFTER UPDATE ON {table} BEGIN
+                    INSERT INTO change_log (entity, entity_id) VALUES ('{entity}', new.id);
+                END
+                "#
+            ),
+            format!(
+                r#"
+                CREATE TRIGGER IF NOT EXISTS {table}_changes_delete AFTER DELETE ON {table} BEGIN
+                    INSERT INTO change_log (entity, entity_id) VALUES ('{entity}', old.id);
+                    INSERT OR REPLACE INTO tombstones (entity, entity_id, deleted_at)
+                    VALUES ('{entity}', old.id, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+                END
+                "#
+            ),
+        ];
+        for trigger in triggers {
+            sqlx::query(&trigger)
+                .execute(pool)
+                .await
+                .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+        }
+    }
+    
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS note_images_changes_insert AFTER INSERT ON note_images BEGIN
+            INSERT INTO change_log (entity, entity_id) VALUES ('note', new.note_id);
+        END
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    sqlx::query(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS note_images_changes_delete AFTER DELETE ON note_images BEGIN
+            INSERT INTO change_log (entity, entity_id) VALUES ('note', old.note_id);
+        END
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // The version of each record this device last agreed on with the WebDAV
+    // folder; a NULL hash means both sides had it deleted
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webdav_records (
+            entity TEXT NOT NULL,
+            entity_id TEXT NOT NULL,
+            hash TEXT,
+            PRIMARY KEY (entity, entity_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    // Each note's Yjs document, the mergeable form of `notes.content` that
+    // collaborative editing works on
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS note_documents (
+            note_id TEXT PRIMARY KEY,
+            state BLOB NOT NULL,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY (note_id) REFERENCES notes(id) ON DELETE CASCADE
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    
+    Ok(())
+}
+
+// For columns added to a table after it was first created; SQLite has no
+// ADD COLUMN IF NOT EXISTS
+async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, definition: &str) -> DatabaseResult<()> {
+    let columns: Vec<(String,)> = sqlx::query_as(&format!("SELECT name FROM pragma_table_info('{}')", table))
+        .fetch_all(pool)
+        .await
+        .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    if columns.iter().any(|(name,)| name == column) {
+        return Ok(());
+    }
+    
+    sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+        .execute(pool)
+        .await
+        .map_err(|e| DatabaseError::Migration(e.to_string()))?;
+    Ok(())
+}
@@ -0,0 +1,412 @@
+// Every note's content is also kept as a Yjs document in `note_documents`, so
+// edits made in two places at once merge instead of one overwriting the other.
+// `notes.content` stays the plain text everything else reads. Whatever writes
+// it directly, like a sync, is caught up with by the document on its next
+// edit.
+use crate::error::{AppError, AppResult, Context};
+use crate::{count_characters, count_words, sanitize_content, Note, NoteStore};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use similar::{Algorithm, DiffTag};
+use sqlx::SqliteConnection;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use yrs::updates::decoder::Decode;
+use yrs::{
+    ClientID, Doc, GetString, Map, OffsetKind, Options, ReadTxn, StateVector, Text, TextRef, Transact,
+    TransactionMut, Update,
+};
+
+// Names of the shared types in each document. Yjs editor bindings can bind to
+// `content` directly; `meta` carries the title for peers that lack the note.
+const CONTENT: &str = "content";
+const META: &str = "meta";
+const TITLE: &str = "title";
+// Title for a shared note whose document does not name one
+const UNTITLED: &str = "Shared note";
+// Diffing a huge note character by character gives up after this and settles
+// for coarser edits
+const DIFF_TIMEOUT: Duration = Duration::from_millis(500);
+const UPDATES_CAPACITY: usize = 256;
+// Yjs client ids are 53 bits so JavaScript peers can hold them
+const CLIENT_ID_MASK: u64 = (1 << 53) - 1;
+
+// One change to a note's text. Offsets count UTF-16 code units, like
+// JavaScript string indices, and each edit applies to the text left by the
+// one before it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub index: u32,
+    #[serde(default)]
+    pub delete: u32,
+    #[serde(default)]
+    pub insert: String,
+}
+
+// An incremental Yjs update to a note's document
+#[derive(Debug, Clone)]
+pub struct DocumentUpdate {
+    pub note_id: String,
+    pub update: Vec<u8>,
+    // The collaboration connection the update arrived on, None for local edits
+    pub origin: Option<u64>,
+}
+
+// Per store: the client id its edits are made under, and where every change
+// to a document is announced
+#[derive(Clone)]
+pub(crate) struct Documents {
+    client_id: u64,
+    updates: broadcast::Sender<DocumentUpdate>,
+}
+
+impl Documents {
+    pub(crate) fn new() -> Self {
+        Documents {
+            // Yjs expects each session to pick a fresh id; two stores sharing
+            // one would make conflicting edits under the same name
+            client_id: Uuid::new_v4().as_u64_pair().0 & CLIENT_ID_MASK,
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+        }
+    }
+}
+
+enum Change<'a> {
+    Replace(&'a str),
+    Edits(&'a [TextEdit]),
+    Update(&'a [u8]),
+}
+
+// A document after a change, ready to store
+struct Edited {
+    text: String,
+    state: Vec<u8>,
+    // What changed since the stored state, None if nothing did
+    update: Option<Vec<u8>>,
+}
+
+impl NoteStore {
+    // Apply edits to the note's current text. Unlike `update_note`, text the
+    // caller has not seen yet, like a collaborator's typing, is kept.
+    pub async fn edit_note(&self, id: &str, edits: &[TextEdit]) -> AppResult<Note> {
+        self.change_document(id, Some(Change::Edits(edits)), None, None).await
+    }
+
+    // Every change to any note's document from now on, local or remote
+    pub fn subscribe_document_updates(&self) -> broadcast::Receiver<DocumentUpdate> {
+        self.documents().updates.subscribe()
+    }
+
+    // Turn the note's text into `content` with the smallest edits a character
+    // diff finds, so concurrent edits elsewhere in the note survive
+    pub(crate) async fn replace_content(
+        &self,
+        id: &str,
+        content: &str,
+        expected_updated_at: Option<DateTime<Utc>>,
+    ) -> AppResult<Note> {
+        self.change_document(id, Some(Change::Replace(content)), expected_updated_at, None).await
+    }
+
+    // Merge an update from a collaborator into the note
+    pub(crate) async fn apply_document_update(&self, id: &str, update: &[u8], origin: u64) -> AppResult<Note> {
+        self.change_document(id, Some(Change::Update(update)), None, Some(origin)).await
+    }
+
+    pub(crate) async fn document_state_vector(&self, id: &str) -> AppResult<StateVector> {
+        let state = self.document_state(id).await?;
+        Ok(decode_update(&state)?.state_vector())
+    }
+
+    // Everything in the note's document that `since` does not cover, as one
+    // update
+    pub(crate) async fn document_diff(&self, id: &str, since: &StateVector) -> AppResult<Vec<u8>> {
+        let state = self.document_state(id).await?;
+        let doc = Doc::new();
+        doc.transact_mut().apply_update(decode_update(&state)?).map_err(invalid_update)?;
+        let update = doc.transact().encode_state_as_update_v1(since);
+        Ok(update)
+    }
+
+    // A note shared by a collaborator that this vault does not have yet
+    pub(crate) async fn create_note_from_document(&self, id: &str, update: &[u8]) -> AppResult<Note> {
+        let (title, text, state) = {
+            let doc = self.new_document();
+            let meta = doc.get_or_insert_map(META);
+            let content = doc.get_or_insert_text(CONTENT);
+            let mut txn = doc.transact_mut();
+            txn.apply_update(decode_update(update)?).map_err(invalid_update)?;
+            let title = meta
+                .get(&txn, TITLE)
+                .map(|title| title.to_string(&txn))
+                .filter(|title| !title.trim().is_empty())
+                .unwrap_or_else(|| UNTITLED.to_string());
+            (title, content.get_string(&txn), txn.encode_state_as_update_v1(&StateVector::default()))
+        };
+
+        let now = Utc::now();
+        let note = self.insert_note(id, &title, &text, None, &[], (now, now)).await?;
+        let mut conn = self.pool().acquire().await.context("Failed to save shared note")?;
+        save_state(&mut conn, id, &state).await?;
+        Ok(note)
+    }
+
+    fn new_document(&self) -> Doc {
+        Doc::with_options(Options {
+            offset_kind: OffsetKind::Utf16,
+            ..Options::with_client_id(ClientID::new(self.documents().client_id))
+        })
+    }
+
+    // The note's current document as one update. Read-only callers still take
+    // the write lock, since a document that fell behind `notes.content` is
+    // caught up and saved on the way.
+    async fn document_state(&self, id: &str) -> AppResult<Vec<u8>> {
+        let mut tx = self.pool().begin_with("BEGIN IMMEDIATE").await.context("Failed to open note document")?;
+        let (note, stored) = load_document(&mut tx, id).await?;
+        if note.is_encrypted {
+            return Err(AppError::Validation(format!("Note {} is encrypted and cannot be shared", id)));
+        }
+        let edited = self.edit_document(&note, stored.as_deref(), None)?;
+        if let Some(update) = edited.update {
+            save_state(&mut tx, id, &edited.state).await?;
+            tx.commit().await.context("Failed to save note document")?;
+            self.announce(id, update, None);
+        }
+        Ok(edited.state)
+    }
+
+    async fn change_document(
+        &self,
+        id: &str,
+        change: Option<Change<'_>>,
+        expected_updated_at: Option<DateTime<Utc>>,
+        origin: Option<u64>,
+    ) -> AppResult<Note> {
+        let mut tx = self.pool().begin_with("BEGIN IMMEDIATE").await.context("Failed to update note")?;
+        let (note, stored) = load_document(&mut tx, id).await?;
+        if expected_updated_at.is_some_and(|expected| expected != note.updated_at) {
+            return Err(AppError::Conflict {
+                message: "The note was changed elsewhere in the meantime".to_string(),
+                id: Some(id.to_string()),
+            });
+        }
+
+        // Encrypted notes have no document; they are only ever saved whole
+        if note.is_encrypted {
+            let Some(Change::Replace(text)) = change else {
+                return Err(AppError::Validation(format!("Note {} is encrypted and can only be saved whole", id)));
+            };
+            let note = self.save_encrypted_content(&mut tx, note, text).await?;
+            tx.commit().await.context("Failed to update note")?;
+            return Ok(note);
+        }
+
+        // An update we already had is not an edit
+        let is_update = matches!(change, Some(Change::Update(_)));
+        let edited = self.edit_document(&note, stored.as_deref(), change)?;
+        if is_update && edited.update.is_none() {
+            return Ok(note);
+        }
+
+        let note = sqlx::query_as::<_, Note>(
+            r#"
+            UPDATE notes
+            SET content = ?1, updated_at = ?2, word_count = ?3, character_count = ?4
+            WHERE id = ?5
+            RETURNING *
+            "#,
+        )
+        .bind(&edited.text)
+        .bind(Utc::now())
+        .bind(count_words(&edited.text))
+        .bind(count_characters(&edited.text))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to update note")?;
+        save_state(&mut tx, id, &edited.state).await?;
+        tx.commit().await.context("Failed to update note")?;
+
+        if let Some(update) = edited.update {
+            self.announce(id, update, origin);
+        }
+        Ok(note)
+    }
+
+    // Rebuild the note's document from its stored state, or from the note for
+    // a first edit, catch it up with `notes.content` and apply the change
+    fn edit_document(&self, note: &Note, stored: Option<&[u8]>, change: Option<Change>) -> AppResult<Edited> {
+        let doc = self.new_document();
+        let content = doc.get_or_insert_text(CONTENT);
+        let mut txn = doc.transact_mut();
+
+        // A document created just now has nothing stored to compare against
+        let saved = match stored {
+            Some(state) => {
+                txn.apply_update(decode_update(state)?).map_err(|e| {
+                    AppError::Database(format!("Note document {} is damaged: {}", note.id, e))
+                })?;
+                txn.state_vector()
+            }
+            None => {
+                txn.apply_update(decode_update(&seed_state(note))?).map_err(invalid_update)?;
+                StateVector::default()
+            }
+        };
+        if content.get_string(&txn) != note.content {
+            replace_text(&content, &mut txn, &note.content);
+        }
+
+        match change {
+            Some(Change::Replace(text)) => replace_text(&content, &mut txn, &sanitize_content(text)),
+            Some(Change::Edits(edits)) => apply_edits(&content, &mut txn, edits)?,
+            Some(Change::Update(update)) => txn.apply_update(decode_update(update)?).map_err(invalid_update)?,
+            None => {}
+        }
+
+        // Whatever arrives, the stored text keeps the usual normalization
+        let text = content.get_string(&txn);
+        let sanitized = sanitize_content(&text);
+        if sanitized != text {
+            replace_text(&content, &mut txn, &sanitized);
+        }
+
+        let update = (txn.state_vector() != saved).then(|| txn.encode_state_as_update_v1(&saved));
+        Ok(Edited {
+            text: sanitized,
+            state: txn.encode_state_as_update_v1(&StateVector::default()),
+            update,
+        })
+    }
+
+    fn announce(&self, id: &str, update: Vec<u8>, origin: Option<u64>) {
+        // Nobody listening is fine
+        let _ = self.documents().updates.send(DocumentUpdate {
+            note_id: id.to_string(),
+            update,
+            origin,
+        });
+    }
+}
+
+// The note and its stored document, if it has one yet
+async fn load_document(conn: &mut SqliteConnection, id: &str) -> AppResult<(Note, Option<Vec<u8>>)> {
+    let note = sqlx::query_as::<_, Note>("SELECT * FROM notes WHERE id = ?1")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("Failed to get note")?
+        .ok_or_else(|| AppError::not_found("note", id))?;
+    let state: Option<(Vec<u8>,)> = sqlx::query_as("SELECT state FROM note_documents WHERE note_id = ?1")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("Failed to read note document")?;
+
+    Ok((note, state.map(|(state,)| state)))
+}
+
+fn decode_update(update: &[u8]) -> AppResult<Update> {
+    Update::decode_v1(update).map_err(|e| AppError::Validation(format!("Invalid document update: {}", e)))
+}
+
+fn invalid_update(e: yrs::error::UpdateError) -> AppError {
+    AppError::Validation(format!("Invalid document update: {}", e))
+}
+
+async fn save_state(conn: &mut SqliteConnection, id: &str, state: &[u8]) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO note_documents (note_id, state, updated_at) VALUES (?1, ?2, ?3)
+        ON CONFLICT (note_id) DO UPDATE SET state = excluded.state, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(id)
+    .bind(state)
+    .bind(Utc::now())
+    .execute(conn)
+    .await
+    .context("Failed to save note document")?;
+
+    Ok(())
+}
+
+// The first version of a note's document. It is built under a client id taken
+// from the note itself, so two vaults holding the same note create identical
+// documents that merge into one copy of the text rather than two.
+fn seed_state(note: &Note) -> Vec<u8> {
+    let doc = Doc::with_options(Options {
+        offset_kind: OffsetKind::Utf16,
+        ..Options::with_client_id(ClientID::new(seed_client_id(note)))
+    });
+    let meta = doc.get_or_insert_map(META);
+    let content = doc.get_or_insert_text(CONTENT);
+    let mut txn = doc.transact_mut();
+    meta.insert(&mut txn, TITLE, note.title.as_str());
+    content.insert(&mut txn, 0, &note.content);
+    txn.encode_state_as_update_v1(&StateVector::default())
+}
+
+fn seed_client_id(note: &Note) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(note.id.as_bytes())
+        .chain_update([0])
+        .chain_update(note.title.as_bytes())
+        .chain_update([0])
+        .chain_update(note.content.as_bytes())
+        .finalize();
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    // Zero is not a valid client id
+    (u64::from_le_bytes(bytes) & CLIENT_ID_MASK).max(1)
+}
+
+fn apply_edits(content: &TextRef, txn: &mut TransactionMut, edits: &[TextEdit]) -> AppResult<()> {
+    for edit in edits {
+        let len = content.len(txn);
+        if edit.index > len || edit.delete > len - edit.index {
+            return Err(AppError::Validation(format!(
+                "Edit at {} removing {} is outside the note's {} characters",
+                edit.index, edit.delete, len
+            )));
+        }
+        if edit.delete > 0 {
+            content.remove_range(txn, edit.index, edit.delete);
+        }
+        if !edit.insert.is_empty() {
+            content.insert(txn, edit.index, &edit.insert);
+        }
+    }
+    Ok(())
+}
+
+fn replace_text(content: &TextRef, txn: &mut TransactionMut, new: &str) {
+    let old: Vec<char> = content.get_string(txn).chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let ops = similar::capture_diff_slices_deadline(Algorithm::Myers, &old, &new, Some(Instant::now() + DIFF_TIMEOUT));
+
+    let mut index = 0;
+    for op in ops {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        let removed = utf16_len(&old[old_range]);
+        if tag == DiffTag::Equal {
+            index += removed;
+            continue;
+        }
+        if removed > 0 {
+            content.remove_range(txn, index, removed);
+        }
+        let inserted: String = new[new_range].iter().collect();
+        if !inserted.is_empty() {
+            content.insert(txn, index, &inserted);
+            index += inserted.encode_utf16().count() as u32;
+        }
+    }
+}
+
+fn utf16_len(chars: &[char]) -> u32 {
+    chars.iter().map(|c| c.len_utf16() as u32).sum()
+}
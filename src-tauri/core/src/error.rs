@@ -0,0 +1,215 @@
+use crate::database::DatabaseError;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::io;
+use thiserror::Error;
+
+// Error returned by every command. It reaches the frontend as
+// `{ code, message, entity?, id? }`; `code` is stable and meant for matching,
+// `message` is for display only.
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("{} with id {id} not found", capitalize(entity))]
+    NotFound { entity: &'static str, id: String },
+    #[error("{message}")]
+    Conflict { message: String, id: Option<String> },
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    StorageFull(String),
+    #[error("{0}")]
+    Io(String),
+    #[error("{0}")]
+    Database(String),
+    // The database is still opening or failed to open, or a remote server
+    // could not be reached
+    #[error("{0}")]
+    Unavailable(String),
+    // A request to the local HTTP API without a valid token, or a remote
+    // server that refused our credentials
+    #[error("{0}")]
+    Unauthorized(String),
+    // The app is locked with the master password
+    #[error("{0}")]
+    Locked(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+impl AppError {
+    pub fn not_found(entity: &'static str, id: impl Into<String>) -> Self {
+        AppError::NotFound { entity, id: id.into() }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound { .. } => "not_found",
+            AppError::Conflict { .. } => "conflict",
+            AppError::Validation(_) => "validation",
+            AppError::StorageFull(_) => "storage_full",
+            AppError::Io(_) => "io",
+            AppError::Database(_) => "database",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Locked(_) => "locked",
+            AppError::Internal(_) => "internal",
+        }
+    }
+
+    // Prefix the message with what was being done, keeping the category.
+    // Not-found errors already say what is missing and are left alone.
+    fn prefixed(self, action: &str) -> Self {
+        let prefix = |message: String| format!("{}: {}", action, message);
+        match self {
+            AppError::NotFound { .. } => self,
+            AppError::Conflict { message, id } => AppError::Conflict { message: prefix(message), id },
+            AppError::Validation(message) => AppError::Validation(prefix(message)),
+            AppError::StorageFull(message) => AppError::StorageFull(prefix(message)),
+            AppError::Io(message) => AppError::Io(prefix(message)),
+            AppError::Database(message) => AppError::Database(prefix(message)),
+            AppError::Unavailable(message) => AppError::Unavailable(prefix(message)),
+            AppError::Unauthorized(message) => AppError::Unauthorized(prefix(message)),
+            AppError::Locked(message) => AppError::Locked(prefix(message)),
+            AppError::Internal(message) => AppError::Internal(prefix(message)),
+        }
+    }
+}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("AppError", 4)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        match self {
+            AppError::NotFound { entity, id } => {
+                error.serialize_field("entity", entity)?;
+                error.serialize_field("id", id)?;
+            }
+            AppError::Conflict { id: Some(id), .. } => {
+                error.serialize_field("id", id)?;
+            }
+            _ => {}
+        }
+        error.end()
+    }
+}
+
+// Adds what was being done to an error from a lower layer, e.g.
+// `fs::read(path).context("Failed to read image")?`
+pub trait Context<T> {
+    fn context(self, action: &str) -> AppResult<T>;
+    fn with_context(self, action: impl FnOnce() -> String) -> AppResult<T>;
+}
+
+impl<T, E: Into<AppError>> Context<T> for Result<T, E> {
+    fn context(self, action: &str) -> AppResult<T> {
+        self.map_err(|e| e.into().prefixed(action))
+    }
+
+    fn with_context(self, action: impl FnOnce() -> String) -> AppResult<T> {
+        self.map_err(|e| e.into().prefixed(&action()))
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        if let sqlx::Error::Database(database_error) = &e {
+            // Primary SQLite result codes live in the low byte of extended ones
+            let code = database_error.code().and_then(|code| code.parse::<i32>().ok()).unwrap_or(0);
+            match code & 0xff {
+                13 => return AppError::StorageFull(e.to_string()),
+                19 if database_error.is_unique_violation() => {
+                    return AppError::Conflict { message: e.to_string(), id: None };
+                }
+                19 => return AppError::Validation(e.to_string()),
+                _ => {}
+            }
+        }
+
+        match e {
+            sqlx::Error::Io(e) => e.into(),
+            e => AppError::Database(e.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::StorageFull {
+            AppError::StorageFull(e.to_string())
+        } else {
+            AppError::Io(e.to_string())
+        }
+    }
+}
+
+impl From<DatabaseError> for AppError {
+    fn from(e: DatabaseError) -> Self {
+        match e {
+            DatabaseError::Connection(e) => e.into(),
+            DatabaseError::Locked => AppError::Locked(e.to_string()),
+            DatabaseError::WrongPassphrase => AppError::Unauthorized(e.to_string()),
+            DatabaseError::NotEncrypted => AppError::Validation(e.to_string()),
+            e => AppError::Database(e.to_string()),
+        }
+    }
+}
+
+impl From<zip::result::ZipError> for AppError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => e.into(),
+            e => AppError::Validation(e.to_string()),
+        }
+    }
+}
+
+impl From<image::ImageError> for AppError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => e.into(),
+            e => AppError::Validation(e.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            AppError::Io(e.to_string())
+        } else {
+            AppError::Validation(e.to_string())
+        }
+    }
+}
+
+impl From<tokio::task::JoinError> for AppError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        AppError::Unavailable(e.to_string())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for AppError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        match e {
+            tokio_tungstenite::tungstenite::Error::Io(e) => e.into(),
+            e => AppError::Unavailable(e.to_string()),
+        }
+    }
+}
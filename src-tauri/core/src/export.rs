@@ -0,0 +1,272 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::attachments::AttachmentMetadata;
+use crate::secrets::{self, CheckedExport, SecretPolicy};
+use crate::{count_characters, count_words, export_archive, Note, NoteStore};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::io::Read;
+use uuid::Uuid;
+
+// Bigger files are refused before they are read
+const MAX_IMPORT_SIZE: u64 = 50 * 1024 * 1024;
+
+// Export and import
+impl NoteStore {
+    // Export notes as a single markdown or JSON document. The notes are
+    // scanned for secrets first and `secrets` decides what happens to them.
+    pub async fn export_notes(
+        &self,
+        format: &str,
+        note_ids: &[String],
+        secrets: SecretPolicy,
+    ) -> AppResult<CheckedExport<String>> {
+        if !matches!(format, "markdown" | "json") {
+            return Err(AppError::Validation(format!("Unsupported export format: {}", format)));
+        }
+        let notes = self.fetch_notes_for_export(note_ids).await?;
+        let (notes, findings) = secrets::check_export(notes, secrets)?;
+        let attachments = self.get_attachments_for_notes(note_ids).await?;
+
+        let output = match format {
+            "markdown" => export_as_markdown(&notes, &attachments, false)?,
+            _ => export_as_json(&notes)?,
+        };
+        Ok(CheckedExport { output, findings })
+    }
+
+    // Export notes together with their attachments and images as a zip archive
+    // in the `exports` directory, returning the archive path. Secrets are
+    // handled as in `export_notes`; attachments and images are not scanned.
+    pub async fn export_notes_archive(
+        &self,
+        note_ids: &[String],
+        secrets: SecretPolicy,
+    ) -> AppResult<CheckedExport<PathBuf>> {
+        let notes = self.fetch_notes_for_export(note_ids).await?;
+        let (notes, findings) = secrets::check_export(notes, secrets)?;
+        let attachments = self.get_attachments_for_notes(note_ids).await?;
+
+        let mut images = HashMap::new();
+        for note_id in note_ids {
+            let note_images = self.get_images_for_note(note_id).await?;
+            if !note_images.is_empty() {
+                images.insert(note_id.clone(), note_images);
+            }
+        }
+
+        let markdown = export_as_markdown(&notes, &attachments, true)?;
+        let json = export_as_json(&notes)?;
+
+        let exports_dir = self.data_dir().join("exports");
+        std::fs::create_dir_all(&exports_dir)
+            .context("Failed to create exports directory")?;
+        let archive_path = exports_dir.join(format!("notura-export-{}.zip", Utc::now().format("%Y%m%d-%H%M%S")));
+
+        let target = archive_path.clone();
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let read_image = |path: &str| store.read_image_file(path);
+            export_archive::write_export_archive(&target, &notes, &markdown, &json, &attachments, &images, read_image)
+        })
+        .await
+        .map_err(|e| AppError::Internal(format!("Export task failed: {}", e)))??;
+
+        Ok(CheckedExport { output: archive_path, findings })
+    }
+
+    async fn fetch_notes_for_export(&self, note_ids: &[String]) -> AppResult<Vec<Note>> {
+        if note_ids.is_empty() {
+            return Err(AppError::Validation("No notes selected for export".to_string()));
+        }
+
+        // Get the notes to export
+        let placeholders = note_ids.iter()
+            .enumerate()
+            .map(|(i, _)| format!("?{}", i + 1))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let query_str = format!("SELECT * FROM notes WHERE id IN ({}) ORDER BY created_at", placeholders);
+        let mut query = sqlx::query_as::<_, Note>(&query_str);
+
+        for note_id in note_ids {
+            query = query.bind(note_id);
+        }
+
+        query
+            .fetch_all(self.pool())
+            .await
+            .context("Failed to fetch notes for export")
+    }
+
+    // Import a JSON export, or anything else as markdown split on horizontal rules
+    pub async fn import_notes(&self, file_path: &Path) -> AppResult<Vec<Note>> {
+        let content = read_import_file(file_path)?;
+
+        // Try to parse as JSON first
+        if let Ok(notes) = serde_json::from_str::<Vec<Note>>(&content) {
+            return self.import_json_notes(notes).await;
+        }
+
+        // Otherwise, treat as markdown
+        self.import_markdown_content(&content).await
+    }
+
+    async fn import_json_notes(&self, notes: Vec<Note>) -> AppResult<Vec<Note>> {
+        let mut imported_notes = Vec::new();
+
+        for note in notes {
+            let imported_note = self.insert_imported_note(
+                &note.title,
+                &note.content,
+                note.collection_id.as_deref(),
+                &note.tags,
+                note.is_encrypted,
+            )
+            .await
+            .context("Failed to import note")?;
+
+            imported_notes.push(imported_note);
+        }
+
+        Ok(imported_notes)
+    }
+
+    async fn import_markdown_content(&self, content: &str) -> AppResult<Vec<Note>> {
+        let mut imported_notes = Vec::new();
+
+        // Simple markdown parsing - split by horizontal rules
+        let sections: Vec<&str> = content.split("\n---\n").collect();
+
+        for (i, section) in sections.iter().enumerate() {
+            if section.trim().is_empty() {
+                continue;
+            }
+
+            let lines: Vec<&str> = section.lines().collect();
+            let title = if let Some(first_line) = lines.first() {
+                first_line.trim_start_matches('#').trim().to_string()
+            } else {
+                format!("Imported Note {}", i + 1)
+            };
+
+            let imported_note = self.insert_imported_note(&title, section, None, "[]", false)
+                .await
+                .context("Failed to import markdown note")?;
+
+            imported_notes.push(imported_note);
+        }
+
+        Ok(imported_notes)
+    }
+
+    // Imported notes always get a fresh id and timestamps. Encrypted notes come
+    // as their ciphertext and stay encrypted.
+    async fn insert_imported_note(
+        &self,
+        title: &str,
+        content: &str,
+        collection_id: Option<&str>,
+        tags: &str,
+        is_encrypted: bool,
+    ) -> AppResult<Note> {
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+
+        sqlx::query_as::<_, Note>(
+            r#"
+            INSERT INTO notes (id, title, content, collection_id, tags, created_at, updated_at, word_count, character_count, is_archived, is_encrypted)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            RETURNING *
+            "#,
+        )
+        .bind(&id)
+        .bind(title)
+        .bind(content)
+        .bind(collection_id)
+        .bind(tags)
+        .bind(now)
+        .bind(now)
+        .bind(if is_encrypted { 0 } else { count_words(content) })
+        .bind(if is_encrypted { 0 } else { count_characters(content) })
+        .bind(false)
+        .bind(is_encrypted)
+        .fetch_one(self.pool())
+        .await
+        .map_err(Into::into)
+    }
+}
+
+// `link_attachments` turns the attachment list into links to the files as
+// laid out by an export archive
+fn export_as_markdown(
+    notes: &[Note],
+    attachments: &HashMap<String, Vec<AttachmentMetadata>>,
+    link_attachments: bool,
+) -> AppResult<String> {
+    let mut markdown = String::new();
+
+    for note in notes {
+        markdown.push_str(&format!("# {}\n\n", note.title));
+        markdown.push_str(&format!("*Created: {}*\n", note.created_at.format("%Y-%m-%d %H:%M:%S")));
+        markdown.push_str(&format!("*Updated: {}*\n\n", note.updated_at.format("%Y-%m-%d %H:%M:%S")));
+
+        // Parse tags from JSON
+        if let Ok(tags) = serde_json::from_str::<Vec<String>>(&note.tags) {
+            if !tags.is_empty() {
+                markdown.push_str(&format!("*Tags: {}*\n\n", tags.join(", ")));
+            }
+        }
+
+        if let Some(note_attachments) = attachments.get(&note.id) {
+            let names = note_attachments.iter()
+                .map(|attachment| {
+                    if link_attachments {
+                        format!("[{}]({})", attachment.original_name, export_archive::attachment_archive_path(attachment))
+                    } else {
+                        attachment.original_name.clone()
+                    }
+                })
+                .collect::<Vec<_>>();
+            markdown.push_str(&format!("*Attachments: {}*\n\n", names.join(", ")));
+        }
+
+        markdown.push_str(&note.content);
+        markdown.push_str("\n\n---\n\n");
+    }
+
+    Ok(markdown)
+}
+
+fn export_as_json(notes: &[Note]) -> AppResult<String> {
+    serde_json::to_string_pretty(notes)
+        .context("Failed to serialize notes to JSON")
+}
+
+// The text of a file to import, refusing anything that is not a regular file
+// of UTF-8 text within `MAX_IMPORT_SIZE`
+fn read_import_file(path: &Path) -> AppResult<String> {
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let metadata = file.metadata().context("Failed to read file")?;
+    if !metadata.is_file() {
+        return Err(AppError::Validation(format!("{} is not a file", path.display())));
+    }
+    let too_large = || {
+        AppError::Validation(format!("The file is too large to import, the limit is {} MB", MAX_IMPORT_SIZE / 1024 / 1024))
+    };
+    if metadata.len() > MAX_IMPORT_SIZE {
+        return Err(too_large());
+    }
+
+    // The file may grow after the check, so the read is capped as well
+    let mut bytes = Vec::with_capacity(metadata.len() as usize);
+    file.take(MAX_IMPORT_SIZE + 1).read_to_end(&mut bytes).context("Failed to read file")?;
+    if bytes.len() as u64 > MAX_IMPORT_SIZE {
+        return Err(too_large());
+    }
+
+    let content = String::from_utf8(bytes)
+        .map_err(|_| AppError::Validation("The file is not UTF-8 text and cannot be imported".to_string()))?;
+    Ok(content.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(content))
+}
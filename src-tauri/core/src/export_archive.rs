@@ -0,0 +1,147 @@
+use crate::error::{AppResult, Context};
+use crate::attachments::AttachmentMetadata;
+use crate::{ImageMetadata, Note};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Where a note's files ended up inside the archive
+#[derive(Debug, Serialize)]
+struct ArchivedFile<'a> {
+    id: &'a str,
+    original_name: &'a str,
+    mime_type: &'a str,
+    path: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ArchivedNote<'a> {
+    note_id: &'a str,
+    title: &'a str,
+    attachments: Vec<ArchivedFile<'a>>,
+    images: Vec<ArchivedFile<'a>>,
+}
+
+// Path of an attachment inside the archive; the id directory keeps files with
+// the same original name apart
+pub fn attachment_archive_path(attachment: &AttachmentMetadata) -> String {
+    format!("attachments/{}/{}", attachment.id, safe_file_name(&attachment.original_name))
+}
+
+fn image_archive_path(image: &ImageMetadata) -> String {
+    format!("images/{}", safe_file_name(&image.filename))
+}
+
+fn safe_file_name(name: &str) -> String {
+    Path::new(name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.replace('\\', "_"))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "file".to_string())
+}
+
+// Write a self-contained export: the notes as Markdown and JSON, every file
+// they reference, and a manifest mapping notes to those files.
+pub fn write_export_archive(
+    archive_path: &Path,
+    notes: &[Note],
+    markdown: &str,
+    json: &str,
+    attachments: &HashMap<String, Vec<AttachmentMetadata>>,
+    images: &HashMap<String, Vec<ImageMetadata>>,
+    // Images are read through this, so an encrypted vault's are decrypted
+    read_image: impl Fn(&str) -> AppResult<Vec<u8>>,
+) -> AppResult<()> {
+    let file = File::create(archive_path)
+        .context("Failed to create export archive")?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+
+    let result = (|| -> AppResult<()> {
+        let mut manifest = Vec::new();
+        // Images can be shared between notes, store each file only once
+        let mut written_paths = std::collections::HashSet::new();
+
+        for note in notes {
+            let mut archived = ArchivedNote {
+                note_id: &note.id,
+                title: &note.title,
+                attachments: Vec::new(),
+                images: Vec::new(),
+            };
+
+            for attachment in attachments.get(&note.id).into_iter().flatten() {
+                let path = attachment_archive_path(attachment);
+                if written_paths.insert(path.clone()) {
+                    add_file(&mut zip, &path, Path::new(&attachment.file_path), options)?;
+                }
+                archived.attachments.push(ArchivedFile {
+                    id: &attachment.id,
+                    original_name: &attachment.original_name,
+                    mime_type: &attachment.mime_type,
+                    path,
+                });
+            }
+
+            for image in images.get(&note.id).into_iter().flatten() {
+                let path = image_archive_path(image);
+                if written_paths.insert(path.clone()) {
+                    let data = read_image(&image.file_path)?;
+                    zip.start_file(path.as_str(), options)
+                        .and_then(|_| zip.write_all(&data).map_err(Into::into))
+                        .with_context(|| format!("Failed to add {} to export archive", path))?;
+                }
+                archived.images.push(ArchivedFile {
+                    id: &image.id,
+                    original_name: &image.original_name,
+                    mime_type: &image.mime_type,
+                    path,
+                });
+            }
+
+            manifest.push(archived);
+        }
+
+        let manifest = serde_json::to_string_pretty(&manifest)
+            .context("Failed to serialize export manifest")?;
+
+        for (name, content) in [("notes.md", markdown), ("notes.json", json), ("manifest.json", manifest.as_str())] {
+            zip.start_file(name, options)
+                .and_then(|_| zip.write_all(content.as_bytes()).map_err(Into::into))
+                .with_context(|| format!("Failed to write {} to export archive", name))?;
+        }
+
+        zip.finish()
+            .context("Failed to finish export archive")?;
+
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(archive_path);
+    }
+
+    result
+}
+
+fn add_file(
+    zip: &mut ZipWriter<File>,
+    archive_name: &str,
+    source: &Path,
+    options: SimpleFileOptions,
+) -> AppResult<()> {
+    let mut input = File::open(source)
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+
+    zip.start_file(archive_name, options)
+        .with_context(|| format!("Failed to add {} to export archive", archive_name))?;
+    io::copy(&mut input, zip)
+        .with_context(|| format!("Failed to add {} to export archive", archive_name))?;
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+// Files the user picked in a native dialog, handed to the webview as
+// short-lived tokens instead of paths. The webview can only read a file
+// through a token it was given, once and within `GRANT_TTL`, so a script in
+// it cannot name arbitrary paths for the backend to read.
+use crate::error::{AppError, AppResult, Context};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const GRANT_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileGrant {
+    pub token: String,
+    // Just the name, for showing what was picked
+    pub file_name: String,
+    pub size: u64,
+}
+
+#[derive(Default)]
+pub struct FileGrants {
+    grants: Mutex<HashMap<String, (PathBuf, Instant)>>,
+}
+
+impl FileGrants {
+    // Grant access to a file the user picked
+    pub fn grant(&self, path: &Path) -> AppResult<FileGrant> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let metadata = path.metadata().with_context(|| format!("Failed to open {}", path.display()))?;
+        if !metadata.is_file() {
+            return Err(AppError::Validation(format!("{} is not a file", path.display())));
+        }
+
+        let token = Uuid::new_v4().simple().to_string();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut grants = self.grants.lock().unwrap();
+        grants.retain(|_, (_, granted)| granted.elapsed() < GRANT_TTL);
+        grants.insert(token.clone(), (path, Instant::now()));
+        Ok(FileGrant { token, file_name, size: metadata.len() })
+    }
+
+    // The granted path. Each token works once.
+    pub fn redeem(&self, token: &str) -> AppResult<PathBuf> {
+        match self.grants.lock().unwrap().remove(token) {
+            Some((path, granted)) if granted.elapsed() < GRANT_TTL => Ok(path),
+            Some(_) => Err(AppError::Unauthorized("The file selection has expired, pick the file again".to_string())),
+            None => Err(AppError::Unauthorized("No file was picked for this import".to_string())),
+        }
+    }
+}
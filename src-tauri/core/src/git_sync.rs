@@ -0,0 +1,380 @@
+// Versions the vault in a git repository: notes as the Markdown files the
+// mirror writes, under `notes/`, and images and attachments under `assets/`.
+// Syncing commits local changes, merges the remote branch and pushes back.
+// Notes changed on both sides keep the local version, with the remote one
+// saved next to it as a conflict copy. The `git` command line is used, so
+// remotes authenticate the same way they do for the user's own repositories.
+use crate::error::{AppError, AppResult, Context};
+use crate::mirror::{self, MirrorConflict};
+use crate::settings::{self, GitSyncSettings};
+use crate::{secrets, NoteStore};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const NOTES_DIR: &str = "notes";
+const ASSETS_DIR: &str = "assets";
+const REMOTE: &str = "origin";
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(60);
+// The mirror's bookkeeping is specific to this copy of the vault
+const GITIGNORE: &str = "notes/.notura-mirror.json\n";
+
+// Scheduled and on-demand syncs never run at the same time
+static SYNC_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GitCommit {
+    pub id: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct GitSyncReport {
+    pub commits: Vec<GitCommit>,
+    pub pulled: bool,
+    pub pushed: bool,
+    // Notes changed by what was pulled
+    pub imported: usize,
+    pub created: usize,
+    pub deleted: usize,
+    pub conflicts: Vec<MirrorConflict>,
+}
+
+// Commit the current state of the vault, without talking to the remote
+pub async fn commit(store: &NoteStore, settings: &GitSyncSettings) -> AppResult<GitSyncReport> {
+    crate::vault::check_unencrypted_copy(store, settings.allow_unencrypted, "git sync")?;
+    let _guard = SYNC_LOCK.lock().await;
+    secrets::check_sync(&store.get_all_notes().await?, "Git sync")?;
+    let repo = settings.repository(store.data_dir());
+    prepare_repository(&repo, &settings.branch).await?;
+
+    let mut report = GitSyncReport::default();
+    commit_snapshot(store, &repo, &mut report).await?;
+    Ok(report)
+}
+
+// Commit, then merge the remote branch into the vault and push the result
+pub async fn sync(store: &NoteStore, settings: &GitSyncSettings) -> AppResult<GitSyncReport> {
+    crate::vault::check_unencrypted_copy(store, settings.allow_unencrypted, "git sync")?;
+    let _guard = SYNC_LOCK.lock().await;
+    secrets::check_sync(&store.get_all_notes().await?, "Git sync")?;
+    let repo = settings.repository(store.data_dir());
+    prepare_repository(&repo, &settings.branch).await?;
+
+    let mut report = GitSyncReport::default();
+    commit_snapshot(store, &repo, &mut report).await?;
+    if settings.remote.is_empty() {
+        return Ok(report);
+    }
+
+    set_remote(&repo, &settings.remote).await?;
+    git(&repo, &["fetch", "-q", REMOTE]).await?;
+
+    let remote_branch = format!("refs/remotes/{}/{}", REMOTE, settings.branch);
+    if run_git(&repo, &["rev-parse", "-q", "--verify", &remote_branch]).await?.success {
+        let before = head(&repo).await?;
+        let merge = run_git(&repo, &["merge", "-q", "--no-edit", "--allow-unrelated-histories", &remote_branch]).await?;
+        if !merge.success {
+            let unmerged = git(&repo, &["diff", "--name-only", "--diff-filter=U"]).await?;
+            if unmerged.trim().is_empty() {
+                let _ = run_git(&repo, &["merge", "--abort"]).await;
+                return Err(AppError::Io(format!("git merge failed: {}", merge.stderr.trim())));
+            }
+            let paths: Vec<&str> = unmerged.lines().collect();
+            resolve_conflicts(&repo, &paths, &mut report).await?;
+            git(&repo, &["add", "-A"]).await?;
+            git(&repo, &["commit", "-q", "--no-edit"]).await?;
+        }
+        report.pulled = true;
+
+        report.deleted = delete_removed_notes(store, &repo, before.as_deref()).await?;
+        let mirrored = mirror::sync(store, &repo.join(NOTES_DIR)).await?;
+        report.imported += mirrored.imported;
+        report.created += mirrored.created;
+        report.conflicts.extend(mirrored.conflicts);
+        // Picks up whatever the import rewrote, like new notes' frontmatter
+        commit_snapshot(store, &repo, &mut report).await?;
+    }
+
+    if head(&repo).await?.is_some() {
+        git(&repo, &["push", "-q", REMOTE, &format!("HEAD:refs/heads/{}", settings.branch)]).await?;
+        report.pushed = true;
+    }
+    Ok(report)
+}
+
+pub async fn run_git_sync_scheduler(store: NoteStore) {
+    loop {
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+
+        if let Err(e) = run_scheduled_sync(&store).await {
+            eprintln!("Scheduled git sync failed: {}", e);
+        }
+    }
+}
+
+async fn run_scheduled_sync(store: &NoteStore) -> AppResult<()> {
+    let settings = GitSyncSettings::load(store.pool()).await?;
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    let last_sync = settings::get_setting(store.pool(), settings::GIT_LAST_SYNC)
+        .await?
+        .and_then(|time| DateTime::parse_from_rfc3339(&time).ok());
+    if let Some(last_sync) = last_sync {
+        if Utc::now() - last_sync.with_timezone(&Utc) < chrono::Duration::minutes(settings.interval_minutes as i64) {
+            return Ok(());
+        }
+    }
+
+    let report = sync(store, &settings).await?;
+    settings::set_setting(store.pool(), settings::GIT_LAST_SYNC, &Utc::now().to_rfc3339()).await?;
+    for conflict in &report.conflicts {
+        eprintln!(
+            "Warning: {} changed here and in the remote, kept the remote version as {}",
+            conflict.path, conflict.copy_path
+        );
+    }
+    Ok(())
+}
+
+struct GitOutput {
+    success: bool,
+    stdout: String,
+    stderr: String,
+}
+
+async fn run_git(repo: &Path, args: &[&str]) -> AppResult<GitOutput> {
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["-c", "core.quotePath=false"])
+        .args(args)
+        // Fail instead of waiting for a password nobody can type
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AppError::Unavailable("git is not installed or not on PATH".to_string()),
+            _ => AppError::Io(format!("Failed to run git: {}", e)),
+        })?;
+
+    Ok(GitOutput {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+async fn git(repo: &Path, args: &[&str]) -> AppResult<String> {
+    let output = run_git(repo, args).await?;
+    if output.success {
+        Ok(output.stdout)
+    } else {
+        Err(AppError::Io(format!("git {} failed: {}", args[0], output.stderr.trim())))
+    }
+}
+
+async fn head(repo: &Path) -> AppResult<Option<String>> {
+    let output = run_git(repo, &["rev-parse", "-q", "--verify", "HEAD"]).await?;
+    Ok(output.success.then(|| output.stdout.trim().to_string()))
+}
+
+async fn prepare_repository(repo: &Path, branch: &str) -> AppResult<()> {
+    fs::create_dir_all(repo)
+        .with_context(|| format!("Failed to create git repository {}", repo.display()))?;
+
+    if !repo.join(".git").exists() {
+        git(repo, &["init", "-q", "-b", branch]).await?;
+    }
+    if !repo.join(".gitignore").exists() {
+        fs::write(repo.join(".gitignore"), GITIGNORE).context("Failed to write .gitignore")?;
+    }
+    // Commits need an author; the user's own git config wins when there is one
+    if !run_git(repo, &["config", "user.email"]).await?.success {
+        git(repo, &["config", "user.name", "Notura"]).await?;
+        git(repo, &["config", "user.email", "notura@localhost"]).await?;
+    }
+    Ok(())
+}
+
+async fn set_remote(repo: &Path, remote: &str) -> AppResult<()> {
+    let current = run_git(repo, &["remote", "get-url", REMOTE]).await?;
+    if !current.success {
+        git(repo, &["remote", "add", REMOTE, remote]).await?;
+    } else if current.stdout.trim() != remote {
+        git(repo, &["remote", "set-url", REMOTE, remote]).await?;
+    }
+    Ok(())
+}
+
+// Write the vault into the work tree and commit it if anything changed
+async fn commit_snapshot(store: &NoteStore, repo: &Path, report: &mut GitSyncReport) -> AppResult<()> {
+    let mirrored = mirror::sync(store, &repo.join(NOTES_DIR)).await?;
+    report.conflicts.extend(mirrored.conflicts);
+    copy_assets(store, repo).await?;
+
+    git(repo, &["add", "-A"]).await?;
+    let changes = git(repo, &["diff", "--cached", "--name-status"]).await?;
+    if changes.trim().is_empty() {
+        return Ok(());
+    }
+
+    let message = commit_message(&changes);
+    git(repo, &["commit", "-q", "-m", &message]).await?;
+    let id = git(repo, &["rev-parse", "--short", "HEAD"]).await?.trim().to_string();
+    report.commits.push(GitCommit { id, message });
+    Ok(())
+}
+
+// Assets are only added, never removed, so files pulled from another copy of
+// the vault stay in the repository. An encrypted vault's images go in
+// decrypted, like the notes, which `sync` only allows when opted into.
+async fn copy_assets(store: &NoteStore, repo: &Path) -> AppResult<()> {
+    let images = store.get_all_images().await?
+        .into_iter()
+        .map(|image| (image.file_path, format!("images/{}", image.filename), true));
+    let attachments = store.get_all_attachments().await?
+        .into_iter()
+        .map(|attachment| (attachment.file_path, format!("attachments/{}", attachment.filename), false));
+
+    for (source, target, is_image) in images.chain(attachments) {
+        let target = repo.join(ASSETS_DIR).join(target);
+        let Ok(metadata) = fs::metadata(&source) else { continue };
+        // Encrypted files differ in size from their plain copies, but an
+        // image file never changes under the same name
+        let decrypt = is_image && store.is_encrypted();
+        let copied = match decrypt {
+            true => target.exists(),
+            false => fs::metadata(&target).is_ok_and(|existing| existing.len() == metadata.len()),
+        };
+        if copied {
+            continue;
+        }
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        if decrypt {
+            fs::write(&target, store.read_image_file(&source)?).with_context(|| format!("Failed to copy {}", source))?;
+        } else {
+            fs::copy(&source, &target).with_context(|| format!("Failed to copy {}", source))?;
+        }
+    }
+    Ok(())
+}
+
+// Subject names the note when only one changed, the body lists them all
+fn commit_message(name_status: &str) -> String {
+    let (mut added, mut updated, mut deleted, mut moved) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut assets = 0;
+
+    for line in name_status.lines() {
+        let mut fields = line.split('\t');
+        let status = fields.next().unwrap_or_default();
+        let Some(path) = fields.next_back() else { continue };
+        let Some(title) = path
+            .strip_prefix("notes/")
+            .and_then(|path| path.strip_suffix(".md"))
+            .map(|path| path.rsplit('/').next().unwrap_or(path).to_string())
+        else {
+            assets += usize::from(path.starts_with("assets/"));
+            continue;
+        };
+
+        match status.chars().next() {
+            Some('A') => added.push(title),
+            Some('D') => deleted.push(title),
+            Some('R') => moved.push(title),
+            _ => updated.push(title),
+        }
+    }
+
+    let notes = added.len() + updated.len() + deleted.len() + moved.len();
+    let subject = match notes {
+        0 if assets == 0 => "Update vault".to_string(),
+        0 => format!("Update {} asset{}", assets, if assets == 1 { "" } else { "s" }),
+        1 => [("Add", &added), ("Update", &updated), ("Delete", &deleted), ("Move", &moved)]
+            .into_iter()
+            .find_map(|(verb, titles)| titles.first().map(|title| format!("{} {}", verb, title)))
+            .unwrap_or_default(),
+        notes => format!("Update {} notes", notes),
+    };
+
+    let mut body = Vec::new();
+    for (label, titles) in [("Added", &added), ("Updated", &updated), ("Deleted", &deleted), ("Moved or renamed", &moved)] {
+        if !titles.is_empty() {
+            body.push(format!("{}: {}", label, titles.join(", ")));
+        }
+    }
+    if notes > 0 && assets > 0 {
+        body.push(format!("Assets: {} files", assets));
+    }
+
+    if notes > 1 || (notes == 1 && assets > 0) {
+        format!("{}\n\n{}", subject, body.join("\n"))
+    } else {
+        subject
+    }
+}
+
+// Settle a conflicted merge file by file. For notes the local version stays
+// and the remote one is kept as a conflict copy; a note deleted on one side
+// and edited on the other keeps the edit.
+async fn resolve_conflicts(repo: &Path, paths: &[&str], report: &mut GitSyncReport) -> AppResult<()> {
+    for path in paths {
+        let ours = run_git(repo, &["show", &format!(":2:{}", path)]).await?;
+        let theirs = run_git(repo, &["show", &format!(":3:{}", path)]).await?;
+        let ours = ours.success.then_some(ours.stdout);
+        let theirs = theirs.success.then_some(theirs.stdout);
+        let target = repo.join(path);
+
+        let kept = match (ours, theirs) {
+            (Some(ours), Some(theirs)) if path.starts_with("notes/") && path.ends_with(".md") => {
+                let copy = mirror::conflict_copy_path(path);
+                fs::write(repo.join(&copy), &theirs).with_context(|| format!("Failed to write {}", copy))?;
+                report.conflicts.push(MirrorConflict {
+                    note_id: mirror::parse_markdown(&ours).id.unwrap_or_default(),
+                    path: path.strip_prefix("notes/").unwrap_or(path).to_string(),
+                    copy_path: copy.strip_prefix("notes/").unwrap_or(&copy).to_string(),
+                });
+                Some(ours)
+            }
+            (Some(ours), _) => Some(ours),
+            (None, theirs) => theirs,
+        };
+
+        match kept {
+            Some(text) => fs::write(&target, text).with_context(|| format!("Failed to write {}", path))?,
+            None => {
+                let _ = fs::remove_file(&target);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Notes whose files the merge deleted are deleted here too, unless they
+// changed locally since the last commit
+async fn delete_removed_notes(store: &NoteStore, repo: &Path, before: Option<&str>) -> AppResult<usize> {
+    let Some(before) = before else { return Ok(0) };
+    let removed = git(repo, &["diff", "--name-only", "--diff-filter=D", before, "HEAD", "--", NOTES_DIR]).await?;
+    let mut deleted = 0;
+
+    for path in removed.lines().filter(|path| path.ends_with(".md")) {
+        let old = git(repo, &["show", &format!("{}:{}", before, path)]).await?;
+        let Some(id) = mirror::parse_markdown(&old).id else { continue };
+        let Ok(note) = store.get_note(&id).await else { continue };
+
+        if mirror::note_to_markdown(&note) == old {
+            store.delete_note(&id).await?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,64 @@
+// Markup the backend hands to the webview. Note text is never trusted as
+// HTML: it is escaped, and the only tags in the result are the ones added
+// here, so an imported note cannot smuggle elements or handlers into the app.
+
+// Put around matches by FTS5's `snippet` in place of tags. Private-use
+// characters, so they cannot be confused with anything markup-like.
+pub(crate) const MATCH_START: char = '\u{E000}';
+pub(crate) const MATCH_END: char = '\u{E001}';
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// A search excerpt from `snippet` output: the text escaped and the matches in
+// `<mark>`. Stray markers in the note itself can't unbalance the tags.
+pub(crate) fn highlight(snippet: &str) -> String {
+    let mut excerpt = String::with_capacity(snippet.len());
+    let mut marking = false;
+    for part in snippet.split_inclusive([MATCH_START, MATCH_END]) {
+        let (text, marker) = match part.strip_suffix([MATCH_START, MATCH_END]) {
+            Some(text) => (text, part.chars().last()),
+            None => (part, None),
+        };
+        excerpt.push_str(&escape(text));
+        match marker {
+            Some(MATCH_START) if !marking => {
+                excerpt.push_str("<mark>");
+                marking = true;
+            }
+            Some(MATCH_END) if marking => {
+                excerpt.push_str("</mark>");
+                marking = false;
+            }
+            _ => {}
+        }
+    }
+    if marking {
+        excerpt.push_str("</mark>");
+    }
+    excerpt
+}
+
+// The plain text of an excerpt, for the terminal
+pub fn excerpt_text(excerpt: &str) -> String {
+    excerpt
+        .replace("<mark>", "")
+        .replace("</mark>", "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,303 @@
+// Optional REST/JSON API for launchers, browser extensions and scripts that
+// can talk HTTP but not Tauri IPC. It only listens on loopback, and every
+// request except the OpenAPI description needs the per-install token as
+// `Authorization: Bearer <token>`. While the app is locked it answers 423.
+use crate::app_lock::AppLock;
+use crate::error::{AppError, AppResult, Context};
+use crate::settings::{HttpApiSettings, UploadSettings};
+use crate::{Collection, ImageMetadata, Note, NoteStore, SearchResult};
+use axum::body::Bytes;
+use axum::extract::rejection::{BytesRejection, JsonRejection, QueryRejection};
+use axum::extract::{DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+// Served at /api/v1/openapi.json
+pub const OPENAPI: &str = include_str!("openapi.json");
+
+#[derive(Clone)]
+struct ApiState {
+    store: NoteStore,
+    lock: Arc<AppLock>,
+    token: Arc<str>,
+}
+
+// A running server; dropping it without `stop` leaves it running
+pub struct ApiServer {
+    addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl ApiServer {
+    // Bind to 127.0.0.1 on the configured port, or any free port for 0
+    pub async fn start(store: NoteStore, lock: Arc<AppLock>, settings: &HttpApiSettings) -> AppResult<ApiServer> {
+        let max_image_size = UploadSettings::load(store.pool()).await?.max_image_size;
+        // Notes unlocked in the app stay locked here
+        let store = store.without_unlocked_notes();
+        let app = router(store, lock, &settings.token, max_image_size as usize);
+
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, settings.port))
+            .await
+            .with_context(|| format!("Failed to listen on port {}", settings.port))?;
+        let addr = listener.local_addr().context("Failed to read the API address")?;
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let server = axum::serve(listener, app).with_graceful_shutdown(async {
+                let _ = stopped.await;
+            });
+            if let Err(e) = server.await {
+                eprintln!("Warning: Local API server stopped: {}", e);
+            }
+        });
+
+        Ok(ApiServer { addr, shutdown, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Stop accepting requests and wait for the ones in flight
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+fn router(store: NoteStore, lock: Arc<AppLock>, token: &str, max_image_size: usize) -> Router {
+    let state = ApiState {
+        store,
+        lock,
+        token: Arc::from(token),
+    };
+
+    let api = Router::new()
+        .route("/notes", get(list_notes).post(create_note))
+        .route("/notes/{id}", get(get_note).patch(update_note))
+        .route("/search", get(search_notes))
+        .route("/collections", get(list_collections))
+        .route(
+            "/images",
+            post(upload_image).layer(DefaultBodyLimit::max(max_image_size)),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize));
+
+    Router::new()
+        .nest("/api/v1", api.route("/openapi.json", get(openapi)))
+        .layer(middleware::from_fn(check_host))
+        .with_state(state)
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            AppError::NotFound { .. } => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::StorageFull(_) => StatusCode::INSUFFICIENT_STORAGE,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Locked(_) => StatusCode::LOCKED,
+            AppError::Io(_) | AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+// A web page can point its own hostname at 127.0.0.1 (DNS rebinding), so only
+// requests addressed to a loopback name are served
+async fn check_host(request: Request, next: Next) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default();
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if port.bytes().all(|b| b.is_ascii_digit()) => hostname,
+        _ => host,
+    };
+
+    if matches!(hostname, "127.0.0.1" | "localhost" | "[::1]") {
+        next.run(request).await
+    } else {
+        AppError::Validation(format!("Requests must be addressed to 127.0.0.1, not {}", host)).into_response()
+    }
+}
+
+async fn authorize(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    if !constant_time_eq(token.trim().as_bytes(), state.token.as_bytes()) {
+        return AppError::Unauthorized("Missing or invalid API token".to_string()).into_response();
+    }
+    match state.lock.check_unlocked() {
+        Ok(()) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+// Compare without bailing out at the first difference, so response times do
+// not reveal how much of a guessed token was right
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Malformed bodies and query strings get the same JSON errors as everything else
+fn json_body<T>(body: Result<Json<T>, JsonRejection>) -> AppResult<T> {
+    body.map(|Json(body)| body)
+        .map_err(|e| AppError::Validation(e.body_text()))
+}
+
+fn query<T>(query: Result<Query<T>, QueryRejection>) -> AppResult<T> {
+    query.map(|Query(query)| query)
+        .map_err(|e| AppError::Validation(e.body_text()))
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI)
+}
+
+#[derive(Deserialize)]
+struct ListNotesQuery {
+    collection_id: Option<String>,
+    tag: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn list_notes(
+    State(state): State<ApiState>,
+    params: Result<Query<ListNotesQuery>, QueryRejection>,
+) -> AppResult<Json<Vec<Note>>> {
+    let params = query(params)?;
+
+    let notes = state.store.get_all_notes().await?
+        .into_iter()
+        .filter(|note| params.collection_id.is_none() || note.collection_id == params.collection_id)
+        .filter(|note| {
+            params.tag.as_ref().is_none_or(|tag| {
+                serde_json::from_str::<Vec<String>>(&note.tags).unwrap_or_default().contains(tag)
+            })
+        })
+        .take(params.limit.unwrap_or(usize::MAX))
+        .collect();
+    Ok(Json(notes))
+}
+
+#[derive(Deserialize)]
+struct CreateNote {
+    title: String,
+    #[serde(default)]
+    content: String,
+    collection_id: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+async fn create_note(
+    State(state): State<ApiState>,
+    body: Result<Json<CreateNote>, JsonRejection>,
+) -> AppResult<(StatusCode, Json<Note>)> {
+    let body = json_body(body)?;
+    if let Some(collection_id) = &body.collection_id {
+        state.store.get_collection(collection_id).await?;
+    }
+
+    let mut note = state.store
+        .create_note(&body.title, &body.content, body.collection_id.as_deref())
+        .await?;
+    if let Some(tags) = &body.tags {
+        note = state.store.set_tags(&note.id, tags).await?;
+    }
+    Ok((StatusCode::CREATED, Json(note)))
+}
+
+async fn get_note(State(state): State<ApiState>, Path(id): Path<String>) -> AppResult<Json<Note>> {
+    Ok(Json(state.store.get_note(&id).await?))
+}
+
+#[derive(Deserialize)]
+struct UpdateNote {
+    content: Option<String>,
+    tags: Option<Vec<String>>,
+    // Refuse the content change with 409 if the note was saved since then
+    if_updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+async fn update_note(
+    State(state): State<ApiState>,
+    Path(id): Path<String>,
+    body: Result<Json<UpdateNote>, JsonRejection>,
+) -> AppResult<Json<Note>> {
+    let body = json_body(body)?;
+
+    let mut note = match (&body.content, body.if_updated_at) {
+        (Some(content), Some(expected)) => state.store.update_note_if_unchanged(&id, content, expected).await?,
+        (Some(content), None) => state.store.update_note(&id, content).await?,
+        (None, _) => state.store.get_note(&id).await?,
+    };
+    if let Some(tags) = &body.tags {
+        note = state.store.set_tags(&id, tags).await?;
+    }
+    Ok(Json(note))
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    limit: Option<usize>,
+}
+
+async fn search_notes(
+    State(state): State<ApiState>,
+    params: Result<Query<SearchQuery>, QueryRejection>,
+) -> AppResult<Json<Vec<SearchResult>>> {
+    let params = query(params)?;
+
+    let mut results = state.store.search_notes(&params.q, None).await?;
+    results.truncate(params.limit.unwrap_or(usize::MAX));
+    Ok(Json(results))
+}
+
+async fn list_collections(State(state): State<ApiState>) -> AppResult<Json<Vec<Collection>>> {
+    Ok(Json(state.store.get_all_collections().await?))
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    name: String,
+    note_id: Option<String>,
+}
+
+// The image is the raw request body; its type is checked from the bytes
+async fn upload_image(
+    State(state): State<ApiState>,
+    params: Result<Query<UploadQuery>, QueryRejection>,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> AppResult<(StatusCode, Json<ImageMetadata>)> {
+    let params = query(params)?;
+    let body = body.map_err(|e| AppError::Validation(e.body_text()))?;
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream");
+
+    let image = state.store
+        .save_image(&body, &params.name, mime_type, params.note_id.as_deref())
+        .await?;
+    Ok((StatusCode::CREATED, Json(image)))
+}
@@ -0,0 +1,224 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::uploads::image_extension;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+// A single transformation applied to the decoded pixels, in request order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageOperation {
+    // Fit inside width x height keeping the aspect ratio, unless `exact` is set
+    Resize {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        exact: bool,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    // Clockwise rotation, only right angles are supported
+    Rotate { degrees: i32 },
+    // Leave out the EXIF (camera, GPS, timestamps) and ICC metadata, which
+    // edits otherwise keep. Applies to the whole output wherever it appears.
+    StripMetadata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageOutputFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl ImageOutputFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ImageOutputFormat::Png => "image/png",
+            ImageOutputFormat::Jpeg => "image/jpeg",
+            ImageOutputFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageOutputFormat::Png => "png",
+            ImageOutputFormat::Jpeg => "jpg",
+            ImageOutputFormat::Webp => "webp",
+        }
+    }
+
+    fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(ImageOutputFormat::Png),
+            ImageFormat::Jpeg => Some(ImageOutputFormat::Jpeg),
+            ImageFormat::WebP => Some(ImageOutputFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageEditRequest {
+    #[serde(default)]
+    pub operations: Vec<ImageOperation>,
+    // Defaults to the source format, or PNG when the source can't be written back
+    pub output_format: Option<ImageOutputFormat>,
+    // JPEG quality (1-100), ignored for the lossless formats
+    pub quality: Option<u8>,
+    // Overwrite the existing image record instead of saving a new one
+    #[serde(default)]
+    pub replace_original: bool,
+    // The note a new copy is linked to; without one it is linked to none.
+    // Notes using the source image keep referencing the source.
+    #[serde(default)]
+    pub note_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct EditedImage {
+    pub data: Vec<u8>,
+    pub format: ImageOutputFormat,
+}
+
+// Decode, transform and re-encode an image. The source's EXIF and ICC
+// metadata is carried over unless `StripMetadata` is requested. The EXIF
+// orientation is baked into the pixels and reset in the metadata, so phone
+// photos aren't turned twice.
+pub fn apply_edits(data: &[u8], request: &ImageEditRequest) -> AppResult<EditedImage> {
+    let reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .context("Failed to read image")?;
+    let source_format = reader
+        .format()
+        .ok_or_else(|| AppError::Validation("Unrecognized image format".to_string()))?;
+    // AVIF uploads are accepted for display, but decoding them needs a
+    // native library this build doesn't link
+    if !source_format.reading_enabled() {
+        return Err(AppError::Validation(format!(
+            "{} images can't be edited",
+            image_extension(source_format).to_uppercase()
+        )));
+    }
+
+    let mut decoder = reader
+        .into_decoder()
+        .context("Failed to decode image")?;
+    let mut exif = decoder
+        .exif_metadata()
+        .context("Failed to read image metadata")?;
+    let icc_profile = decoder
+        .icc_profile()
+        .context("Failed to read image metadata")?;
+    let orientation = exif
+        .as_mut()
+        .and_then(|exif| Orientation::remove_from_exif_chunk(exif))
+        .unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder)
+        .context("Failed to decode image")?;
+    image.apply_orientation(orientation);
+
+    for operation in &request.operations {
+        image = apply_operation(image, operation)?;
+    }
+
+    let format = request
+        .output_format
+        .or_else(|| ImageOutputFormat::from_image_format(source_format))
+        .unwrap_or(ImageOutputFormat::Png);
+    let quality = request.quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+    let metadata = if request.operations.iter().any(|operation| matches!(operation, ImageOperation::StripMetadata)) {
+        Metadata::default()
+    } else {
+        Metadata { exif, icc_profile }
+    };
+    let data = encode(&image, format, quality, metadata)?;
+
+    Ok(EditedImage { data, format })
+}
+
+fn apply_operation(image: DynamicImage, operation: &ImageOperation) -> AppResult<DynamicImage> {
+    match *operation {
+        ImageOperation::Resize { width, height, exact } => {
+            if width == 0 || height == 0 {
+                return Err(AppError::Validation("Resize dimensions must be greater than zero".to_string()));
+            }
+            if exact {
+                Ok(image.resize_exact(width, height, FilterType::Lanczos3))
+            } else {
+                Ok(image.resize(width, height, FilterType::Lanczos3))
+            }
+        }
+        ImageOperation::Crop { x, y, width, height } => {
+            if width == 0 || height == 0 {
+                return Err(AppError::Validation("Crop dimensions must be greater than zero".to_string()));
+            }
+            let fits_horizontally = x.checked_add(width).is_some_and(|right| right <= image.width());
+            let fits_vertically = y.checked_add(height).is_some_and(|bottom| bottom <= image.height());
+            if !fits_horizontally || !fits_vertically {
+                return Err(AppError::Validation(format!(
+                    "Crop region {}x{} at ({}, {}) is outside the {}x{} image",
+                    width, height, x, y, image.width(), image.height()
+                )));
+            }
+            Ok(image.crop_imm(x, y, width, height))
+        }
+        ImageOperation::Rotate { degrees } => match degrees.rem_euclid(360) {
+            0 => Ok(image),
+            90 => Ok(image.rotate90()),
+            180 => Ok(image.rotate180()),
+            270 => Ok(image.rotate270()),
+            _ => Err(AppError::Validation(format!("Unsupported rotation: {} degrees (use multiples of 90)", degrees))),
+        },
+        ImageOperation::StripMetadata => Ok(image),
+    }
+}
+
+#[derive(Default)]
+struct Metadata {
+    exif: Option<Vec<u8>>,
+    icc_profile: Option<Vec<u8>>,
+}
+
+fn encode(image: &DynamicImage, format: ImageOutputFormat, quality: u8, metadata: Metadata) -> AppResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+
+    let result = match format {
+        ImageOutputFormat::Png => image.write_with_encoder(with_metadata(PngEncoder::new(&mut buffer), metadata)?),
+        // JPEG has no alpha channel
+        ImageOutputFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(with_metadata(JpegEncoder::new_with_quality(&mut buffer, quality), metadata)?),
+        // The WebP encoder only accepts 8-bit RGB(A)
+        ImageOutputFormat::Webp => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_with_encoder(with_metadata(WebPEncoder::new_lossless(&mut buffer), metadata)?),
+    };
+
+    result.with_context(|| format!("Failed to encode image as {:?}", format))?;
+    Ok(buffer)
+}
+
+fn with_metadata<E: ImageEncoder>(mut encoder: E, metadata: Metadata) -> AppResult<E> {
+    if let Some(exif) = metadata.exif {
+        encoder
+            .set_exif_metadata(exif)
+            .map_err(|e| AppError::Validation(format!("Can't keep the image metadata: {}", e)))?;
+    }
+    if let Some(icc_profile) = metadata.icc_profile {
+        encoder
+            .set_icc_profile(icc_profile)
+            .map_err(|e| AppError::Validation(format!("Can't keep the image metadata: {}", e)))?;
+    }
+    Ok(encoder)
+}
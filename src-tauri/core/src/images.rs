@@ -0,0 +1,336 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::image_editing::{self, EditedImage, ImageEditRequest};
+use crate::settings::UploadSettings;
+use crate::{uploads, ImageMetadata, ImageWithData, NoteStore};
+use base64::Engine;
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+impl NoteStore {
+    fn images_dir(&self) -> AppResult<PathBuf> {
+        let images_dir = self.data_dir().join("images");
+        fs::create_dir_all(&images_dir)
+            .context("Failed to create images directory")?;
+        Ok(images_dir)
+    }
+
+    pub async fn save_image(
+        &self,
+        file_data: &[u8],
+        original_name: &str,
+        mime_type: &str,
+        note_id: Option<&str>,
+    ) -> AppResult<ImageMetadata> {
+        let upload_settings = UploadSettings::load(self.pool()).await?;
+
+        // Trust the bytes, not the caller-provided name and MIME type
+        let format = uploads::validate_image_upload(
+            file_data,
+            original_name,
+            mime_type,
+            upload_settings.max_image_size,
+        )?;
+
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let images_dir = self.images_dir()?;
+
+        // Generate unique filename from the detected format
+        let unique_filename = format!("{}_{}.{}", id, now.timestamp(), uploads::image_extension(format));
+
+        // Save file to disk
+        let file_path = uploads::write_file_atomically(&images_dir, &unique_filename, &self.seal_image(file_data)?)
+            .context("Failed to save image file")?;
+
+        let mut tx = match self.pool().begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                let _ = fs::remove_file(&file_path);
+                return Err(e).context("Failed to start transaction");
+            }
+        };
+
+        // Metadata and the note association are committed together; if either
+        // fails the file written above is removed again
+        let result: AppResult<ImageMetadata> = async {
+            let image_metadata = sqlx::query_as::<_, ImageMetadata>(
+                r#"
+                INSERT INTO images (id, filename, original_name, file_path, size, mime_type, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                RETURNING *
+                "#,
+            )
+            .bind(&id)
+            .bind(&unique_filename)
+            .bind(original_name)
+            .bind(file_path.to_string_lossy().to_string())
+            .bind(file_data.len() as i64)
+            .bind(uploads::image_mime_type(format))
+            .bind(now)
+            .fetch_one(&mut *tx)
+            .await
+            .context("Failed to save image metadata")?;
+
+            // If note_id is provided, create the association
+            if let Some(note_id) = note_id {
+                sqlx::query(
+                    "INSERT INTO note_images (note_id, image_id, created_at) VALUES (?1, ?2, ?3)"
+                )
+                .bind(note_id)
+                .bind(&id)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to associate image with note")?;
+            }
+
+            tx.commit().await
+                .context("Failed to commit image")?;
+
+            Ok(image_metadata)
+        }
+        .await;
+
+        if result.is_err() {
+            if let Err(e) = fs::remove_file(&file_path) {
+                eprintln!("Warning: Failed to clean up image file {}: {}", file_path.display(), e);
+            }
+        }
+
+        result
+    }
+
+    pub async fn get_image_metadata(&self, id: &str) -> AppResult<ImageMetadata> {
+        sqlx::query_as::<_, ImageMetadata>("SELECT * FROM images WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(self.pool())
+            .await
+            .context("Failed to get image metadata")?
+            .ok_or_else(|| AppError::not_found("image", id))
+    }
+
+    pub async fn get_image(&self, id: &str) -> AppResult<ImageWithData> {
+        let image_metadata = self.get_image_metadata(id).await?;
+
+        // Read file data
+        let file_data = self.read_image_file(&image_metadata.file_path)?;
+
+        // Convert to base64 for frontend
+        let base64_data = base64::engine::general_purpose::STANDARD.encode(&file_data);
+        let data_url = format!("data:{};base64,{}", image_metadata.mime_type, base64_data);
+
+        Ok(ImageWithData {
+            id: image_metadata.id,
+            filename: image_metadata.filename,
+            original_name: image_metadata.original_name,
+            file_path: image_metadata.file_path,
+            size: image_metadata.size,
+            mime_type: image_metadata.mime_type,
+            created_at: image_metadata.created_at,
+            data_url,
+        })
+    }
+
+    pub async fn get_all_images(&self) -> AppResult<Vec<ImageMetadata>> {
+        sqlx::query_as::<_, ImageMetadata>(
+            "SELECT * FROM images ORDER BY created_at DESC"
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to get images")
+    }
+
+    pub async fn get_images_for_note(&self, note_id: &str) -> AppResult<Vec<ImageMetadata>> {
+        sqlx::query_as::<_, ImageMetadata>(
+            r#"
+            SELECT i.* FROM images i
+            JOIN note_images ni ON i.id = ni.image_id
+            WHERE ni.note_id = ?1
+            ORDER BY i.created_at DESC
+            "#
+        )
+        .bind(note_id)
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to get images for note")
+    }
+
+    pub async fn delete_image(&self, id: &str) -> AppResult<()> {
+        // Get image metadata first
+        let image_metadata = self.get_image_metadata(id).await?;
+
+        // Delete file from disk
+        if let Err(e) = fs::remove_file(&image_metadata.file_path) {
+            eprintln!("Warning: Failed to delete image file {}: {}", image_metadata.file_path, e);
+        }
+
+        // Delete from database (this will cascade to note_images due to foreign key)
+        let result = sqlx::query("DELETE FROM images WHERE id = ?1")
+            .bind(id)
+            .execute(self.pool())
+            .await
+            .context("Failed to delete image from database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::not_found("image", id));
+        }
+
+        Ok(())
+    }
+
+    pub async fn update_image_note_association(&self, image_id: &str, note_id: &str, is_used: bool) -> AppResult<()> {
+        let now = Utc::now();
+
+        if is_used {
+            // Add association if it doesn't exist
+            sqlx::query(
+                r#"
+                INSERT INTO note_images (note_id, image_id, created_at)
+                VALUES (?1, ?2, ?3)
+                ON CONFLICT (note_id, image_id) DO NOTHING
+                "#
+            )
+            .bind(note_id)
+            .bind(image_id)
+            .bind(now)
+            .execute(self.pool())
+            .await
+            .context("Failed to add image association")?;
+        } else {
+            // Remove association
+            sqlx::query("DELETE FROM note_images WHERE note_id = ?1 AND image_id = ?2")
+                .bind(note_id)
+                .bind(image_id)
+                .execute(self.pool())
+                .await
+                .context("Failed to remove image association")?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn edit_image(&self, id: &str, edits: ImageEditRequest) -> AppResult<ImageMetadata> {
+        let original = self.get_image_metadata(id).await?;
+        let note_id = edits.note_id.clone();
+        match &note_id {
+            Some(_) if edits.replace_original => {
+                return Err(AppError::Validation("An image edited in place keeps its notes".to_string()))
+            }
+            Some(note_id) => {
+                self.get_note(note_id).await?;
+            }
+            None => {}
+        }
+
+        let file_data = self.read_image_file(&original.file_path)?;
+
+        // Decoding and re-encoding large images is CPU bound, keep it off the async runtime
+        let replace_original = edits.replace_original;
+        let edited = tokio::task::spawn_blocking(move || image_editing::apply_edits(&file_data, &edits))
+            .await
+            .map_err(|e| AppError::Internal(format!("Image editing task failed: {}", e)))??;
+
+        let images_dir = self.images_dir()?;
+
+        // Replacing keeps the record id so existing note_images links stay valid
+        let target_id = if replace_original { original.id.clone() } else { Uuid::new_v4().to_string() };
+        let extension = edited.format.extension();
+        let unique_filename = format!("{}_{}.{}", target_id, Utc::now().timestamp_millis(), extension);
+        let file_path = images_dir.join(&unique_filename);
+        let original_name = Path::new(&original.original_name)
+            .with_extension(extension)
+            .to_string_lossy()
+            .to_string();
+
+        uploads::write_file_atomically(&images_dir, &unique_filename, &self.seal_image(&edited.data)?)
+            .context("Failed to save image file")?;
+
+        let result = if replace_original {
+            sqlx::query_as::<_, ImageMetadata>(
+                r#"
+                UPDATE images
+                SET filename = ?1, original_name = ?2, file_path = ?3, size = ?4, mime_type = ?5
+                WHERE id = ?6
+                RETURNING *
+                "#,
+            )
+            .bind(&unique_filename)
+            .bind(&original_name)
+            .bind(file_path.to_string_lossy().to_string())
+            .bind(edited.data.len() as i64)
+            .bind(edited.format.mime_type())
+            .bind(&target_id)
+            .fetch_one(self.pool())
+            .await
+            .context("Failed to update image metadata")
+        } else {
+            self.insert_edited_image_copy(note_id.as_deref(), &target_id, &unique_filename, &original_name, &file_path, &edited).await
+        };
+
+        let image_metadata = match result {
+            Ok(image_metadata) => image_metadata,
+            Err(e) => {
+                let _ = fs::remove_file(&file_path);
+                return Err(e);
+            }
+        };
+
+        if replace_original && Path::new(&original.file_path) != file_path.as_path() {
+            if let Err(e) = fs::remove_file(&original.file_path) {
+                eprintln!("Warning: Failed to delete replaced image file {}: {}", original.file_path, e);
+            }
+        }
+
+        Ok(image_metadata)
+    }
+
+    // Save an edited image as a new record, linked to `note_id` if given
+    async fn insert_edited_image_copy(
+        &self,
+        note_id: Option<&str>,
+        id: &str,
+        filename: &str,
+        original_name: &str,
+        file_path: &Path,
+        edited: &EditedImage,
+    ) -> AppResult<ImageMetadata> {
+        let now = Utc::now();
+        let mut tx = self.pool().begin().await
+            .context("Failed to start transaction")?;
+
+        let image_metadata = sqlx::query_as::<_, ImageMetadata>(
+            r#"
+            INSERT INTO images (id, filename, original_name, file_path, size, mime_type, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(filename)
+        .bind(original_name)
+        .bind(file_path.to_string_lossy().to_string())
+        .bind(edited.data.len() as i64)
+        .bind(edited.format.mime_type())
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to save image metadata")?;
+
+        if let Some(note_id) = note_id {
+            sqlx::query("INSERT INTO note_images (note_id, image_id, created_at) VALUES (?1, ?2, ?3)")
+                .bind(note_id)
+                .bind(id)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to associate edited image with note")?;
+        }
+
+        tx.commit().await
+            .context("Failed to commit edited image")?;
+
+        Ok(image_metadata)
+    }
+}
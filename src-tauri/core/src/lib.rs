@@ -0,0 +1,57 @@
+// Notes, collections, search, images and export for Notura, over a SQLite
+// pool and the app data directory. Nothing here depends on Tauri, so the
+// desktop commands, tests and other front ends all share the same code.
+pub mod app_lock;
+pub mod attachments;
+pub mod backup;
+pub mod changes;
+pub mod collab;
+pub mod crypto;
+pub mod database;
+pub mod documents;
+pub mod error;
+pub mod export_archive;
+pub mod file_grants;
+pub mod git_sync;
+pub mod html;
+pub mod http_api;
+pub mod image_editing;
+pub mod maintenance;
+pub mod mirror;
+pub mod models;
+pub mod recovery;
+pub mod s3_backup;
+pub mod secrets;
+pub mod settings;
+pub mod text_extraction;
+pub mod uploads;
+pub mod vault;
+pub mod webdav_sync;
+mod export;
+mod images;
+mod note_encryption;
+mod notes;
+mod search;
+mod shred;
+mod store;
+#[cfg(test)]
+mod test_utils;
+#[cfg(test)]
+mod api_tests;
+
+pub use error::{AppError, AppResult};
+pub use models::*;
+pub use store::{default_data_dir, NoteStore};
+
+pub fn count_words(text: &str) -> i32 {
+    text.split_whitespace().count() as i32
+}
+
+pub fn count_characters(text: &str) -> i32 {
+    text.chars().count() as i32
+}
+
+pub fn sanitize_content(content: &str) -> String {
+    // Basic sanitization - remove null bytes and normalize line endings
+    content.replace('\0', "").replace("\r\n", "\n").replace('\r', "\n")
+}
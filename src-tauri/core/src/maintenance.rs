@@ -0,0 +1,269 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::{settings, webdav_sync};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+const LAST_RUN_KEY: &str = "maintenance.last_run";
+// The automatic run waits for a quiet period with no note edits
+const IDLE_THRESHOLD_MINUTES: i64 = 10;
+const AUTOMATIC_RUN_INTERVAL_HOURS: i64 = 24;
+const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceOptions {
+    // Full `integrity_check` instead of the faster `quick_check`
+    #[serde(default)]
+    pub full_integrity_check: bool,
+    // Rebuild the search index even when its own check passes
+    #[serde(default)]
+    pub rebuild_search_index: bool,
+    #[serde(default)]
+    pub vacuum: bool,
+}
+
+impl MaintenanceOptions {
+    // What the idle-time run does: checks, repairs drift, no rewrite of the file
+    pub fn lightweight() -> Self {
+        MaintenanceOptions {
+            full_integrity_check: false,
+            rebuild_search_index: false,
+            vacuum: false,
+        }
+    }
+
+    pub fn full() -> Self {
+        MaintenanceOptions {
+            full_integrity_check: true,
+            rebuild_search_index: true,
+            vacuum: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    pub rowid: Option<i64>,
+    pub parent: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceReport {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub integrity_ok: bool,
+    // Problems reported by SQLite, empty when the check passed
+    pub integrity_errors: Vec<String>,
+    pub foreign_key_violations: Vec<ForeignKeyViolation>,
+    // Whether notes_fts failed its check before any rebuild
+    pub search_index_out_of_sync: bool,
+    pub search_index_rebuilt: bool,
+    pub counts_corrected: u64,
+    pub vacuumed: bool,
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+pub async fn run_maintenance(pool: &SqlitePool, options: &MaintenanceOptions) -> AppResult<MaintenanceReport> {
+    let started_at = Utc::now();
+    let size_before = database_size(pool).await?;
+
+    let integrity_errors = integrity_check(pool, options.full_integrity_check).await?;
+    let foreign_key_violations = foreign_key_check(pool).await?;
+
+    // Repairs rewrite pages, so they are skipped on a damaged database where
+    // they could make things worse; restoring a backup is the way out there
+    let integrity_ok = integrity_errors.is_empty();
+
+    let search_index_out_of_sync = !search_index_in_sync(pool).await?;
+    let mut search_index_rebuilt = false;
+    let mut counts_corrected = 0;
+    let mut vacuumed = false;
+
+    if integrity_ok {
+        if search_index_out_of_sync || options.rebuild_search_index {
+            rebuild_search_index(pool).await?;
+            search_index_rebuilt = true;
+        }
+
+        counts_corrected = recompute_counts(pool).await?;
+
+        if options.vacuum {
+            // VACUUM leaves the old pages in the WAL until it is checkpointed
+            sqlx::query("VACUUM")
+                .execute(pool)
+                .await
+                .context("Failed to vacuum database")?;
+            sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+                .execute(pool)
+                .await
+                .context("Failed to checkpoint database")?;
+            vacuumed = true;
+        }
+
+        sqlx::query("PRAGMA optimize")
+            .execute(pool)
+            .await
+            .context("Failed to optimize database")?;
+    }
+
+    let report = MaintenanceReport {
+        started_at,
+        finished_at: Utc::now(),
+        integrity_ok,
+        integrity_errors,
+        foreign_key_violations,
+        search_index_out_of_sync,
+        search_index_rebuilt,
+        counts_corrected,
+        vacuumed,
+        size_before,
+        size_after: database_size(pool).await?,
+    };
+
+    settings::set_setting(pool, LAST_RUN_KEY, &report.finished_at.to_rfc3339()).await?;
+
+    Ok(report)
+}
+
+pub async fn integrity_check(pool: &SqlitePool, full: bool) -> AppResult<Vec<String>> {
+    let pragma = if full { "PRAGMA integrity_check" } else { "PRAGMA quick_check" };
+    let rows: Vec<(String,)> = sqlx::query_as(pragma)
+        .fetch_all(pool)
+        .await
+        .context("Failed to check database integrity")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(row,)| row)
+        .filter(|row| row != "ok")
+        .collect())
+}
+
+pub async fn foreign_key_check(pool: &SqlitePool) -> AppResult<Vec<ForeignKeyViolation>> {
+    let rows: Vec<(String, Option<i64>, String, i64)> = sqlx::query_as("PRAGMA foreign_key_check")
+        .fetch_all(pool)
+        .await
+        .context("Failed to check foreign keys")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(table, rowid, parent, _)| ForeignKeyViolation { table, rowid, parent })
+        .collect())
+}
+
+// FTS5's own check compares the index against the notes table it mirrors
+async fn search_index_in_sync(pool: &SqlitePool) -> AppResult<bool> {
+    match sqlx::query("INSERT INTO notes_fts(notes_fts, rank) VALUES ('integrity-check', 1)")
+        .execute(pool)
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(sqlx::Error::Database(e)) if e.message().contains("malformed") => Ok(false),
+        Err(e) => Err(AppError::from(e)).context("Failed to check search index"),
+    }
+}
+
+pub async fn rebuild_search_index(pool: &SqlitePool) -> AppResult<()> {
+    sqlx::query("INSERT INTO notes_fts(notes_fts) VALUES ('rebuild')")
+        .execute(pool)
+        .await
+        .context("Failed to rebuild search index")?;
+
+    Ok(())
+}
+
+// Fix stored word and character counts that no longer match the content,
+// returning how many notes were corrected
+pub async fn recompute_counts(pool: &SqlitePool) -> AppResult<u64> {
+    let notes: Vec<(String, String, i32, i32)> =
+        sqlx::query_as("SELECT id, content, word_count, character_count FROM notes WHERE NOT is_encrypted")
+            .fetch_all(pool)
+            .await
+            .context("Failed to read notes")?;
+
+    let mut tx = pool.begin().await
+        .context("Failed to start transaction")?;
+    let mut corrected = 0;
+
+    for (id, content, word_count, character_count) in notes {
+        let expected_words = crate::count_words(&content);
+        let expected_characters = crate::count_characters(&content);
+        if expected_words == word_count && expected_characters == character_count {
+            continue;
+        }
+
+        // Leaves updated_at alone, the note itself did not change
+        sqlx::query("UPDATE notes SET word_count = ?1, character_count = ?2 WHERE id = ?3")
+            .bind(expected_words)
+            .bind(expected_characters)
+            .bind(&id)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("Failed to update counts for note {}", id))?;
+        corrected += 1;
+    }
+
+    tx.commit().await
+        .context("Failed to commit counts")?;
+
+    Ok(corrected)
+}
+
+async fn database_size(pool: &SqlitePool) -> AppResult<u64> {
+    let (page_count,): (i64,) = sqlx::query_as("PRAGMA page_count")
+        .fetch_one(pool)
+        .await
+        .context("Failed to read database size")?;
+    let (page_size,): (i64,) = sqlx::query_as("PRAGMA page_size")
+        .fetch_one(pool)
+        .await
+        .context("Failed to read database size")?;
+
+    Ok((page_count * page_size) as u64)
+}
+
+pub async fn last_run(pool: &SqlitePool) -> AppResult<Option<DateTime<Utc>>> {
+    Ok(settings::get_setting(pool, LAST_RUN_KEY)
+        .await?
+        .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+        .map(|time| time.with_timezone(&Utc)))
+}
+
+// Run the lightweight maintenance once a day, at a moment when no note has
+// been edited for a while. Runs for the app's lifetime.
+pub async fn run_idle_maintenance_scheduler(pool: SqlitePool) {
+    loop {
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+
+        match run_idle_maintenance(&pool).await {
+            Ok(Some(report)) if !report.integrity_ok => {
+                eprintln!("Warning: Database integrity check failed: {}", report.integrity_errors.join("; "));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Warning: Scheduled maintenance failed: {}", e),
+        }
+    }
+}
+
+async fn run_idle_maintenance(pool: &SqlitePool) -> AppResult<Option<MaintenanceReport>> {
+    let now = Utc::now();
+
+    if let Some(last_run) = last_run(pool).await? {
+        if now - last_run < Duration::hours(AUTOMATIC_RUN_INTERVAL_HOURS) {
+            return Ok(None);
+        }
+    }
+
+    let (last_edit,): (Option<DateTime<Utc>>,) = sqlx::query_as("SELECT MAX(updated_at) FROM notes")
+        .fetch_one(pool)
+        .await
+        .context("Failed to read last edit time")?;
+    if last_edit.is_some_and(|last_edit| now - last_edit < Duration::minutes(IDLE_THRESHOLD_MINUTES)) {
+        return Ok(None);
+    }
+
+    webdav_sync::trim_sync_state(pool).await?;
+    run_maintenance(pool, &MaintenanceOptions::lightweight()).await.map(Some)
+}
@@ -0,0 +1,570 @@
+// Keeps a folder of plain Markdown files in step with the notes table, so
+// grep, git and file-sync tools can work on the vault. Collections become
+// directories and each file starts with frontmatter carrying the note's id,
+// title, tags and dates.
+//
+// A manifest in the folder remembers what was last written for every note.
+// A note whose file changed since then is updated from the file; if the note
+// changed in the database as well, the database wins and the file's version
+// is kept next to it as a conflict copy. Deleting a file does not delete its
+// note, the file is written again on the next sync.
+use crate::changes::ChangeWatcher;
+use crate::error::{AppError, AppResult, Context};
+use crate::settings::MirrorSettings;
+use crate::{sanitize_content, uploads, Collection, Note, NoteStore};
+use chrono::{DateTime, Utc};
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+const MANIFEST_FILE: &str = ".notura-mirror.json";
+// Marks the copies kept on conflicts, which are never imported as notes
+const CONFLICT_MARKER: &str = ".conflict-";
+// How long file events are collected before syncing, and how often the
+// database is checked for changes made elsewhere
+const DEBOUNCE: Duration = Duration::from_millis(500);
+const DB_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MirrorReport {
+    // Files written from the database
+    pub written: usize,
+    // Notes updated from edited files
+    pub imported: usize,
+    // Notes created from new files
+    pub created: usize,
+    // Files removed because their note is gone
+    pub removed: usize,
+    pub conflicts: Vec<MirrorConflict>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorConflict {
+    pub note_id: String,
+    pub path: String,
+    // Where the file's version was kept
+    pub copy_path: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    notes: HashMap<String, MirroredNote>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct MirroredNote {
+    // Relative to the mirror folder, `/`-separated
+    path: String,
+    updated_at: DateTime<Utc>,
+    hash: String,
+}
+
+// A mirror file split into frontmatter and body
+#[derive(Debug, Default, PartialEq)]
+pub struct MirrorFile {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub created: Option<DateTime<Utc>>,
+    pub updated: Option<DateTime<Utc>>,
+    pub content: String,
+}
+
+pub fn note_to_markdown(note: &Note) -> String {
+    let tags: Vec<String> = serde_json::from_str(&note.tags).unwrap_or_default();
+    // Values are JSON, which YAML reads as well
+    let mut text = format!(
+        "---\nid: {}\ntitle: {}\ntags: {}\ncreated: {}\nupdated: {}\n---\n{}",
+        note.id,
+        Value::from(note.title.as_str()),
+        Value::from(tags),
+        note.created_at.to_rfc3339(),
+        note.updated_at.to_rfc3339(),
+        note.content
+    );
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+pub fn parse_markdown(text: &str) -> MirrorFile {
+    let text = sanitize_content(text);
+    let Some((frontmatter, body)) = text
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.split_once("\n---\n").or_else(|| rest.strip_suffix("\n---").map(|front| (front, ""))))
+    else {
+        return MirrorFile { content: text, ..Default::default() };
+    };
+
+    let mut file = MirrorFile { content: body.to_string(), ..Default::default() };
+    for line in frontmatter.lines() {
+        let Some((key, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        let parsed: Option<Value> = serde_json::from_str(value).ok();
+        let text = match &parsed {
+            Some(Value::String(text)) => text.clone(),
+            _ => value.trim_matches(['"', '\'']).to_string(),
+        };
+
+        match key.trim() {
+            "id" if !text.is_empty() => file.id = Some(text),
+            "title" if !text.is_empty() => file.title = Some(text),
+            "created" => file.created = DateTime::parse_from_rfc3339(&text).ok().map(|time| time.with_timezone(&Utc)),
+            "updated" => file.updated = DateTime::parse_from_rfc3339(&text).ok().map(|time| time.with_timezone(&Utc)),
+            "tags" => {
+                file.tags = Some(match parsed {
+                    Some(Value::Array(tags)) => tags.iter().filter_map(|tag| tag.as_str().map(str::to_string)).collect(),
+                    _ => value
+                        .trim_matches(['[', ']'])
+                        .split(',')
+                        .map(|tag| tag.trim().trim_matches(['"', '\'']).to_string())
+                        .filter(|tag| !tag.is_empty())
+                        .collect(),
+                })
+            }
+            _ => {}
+        }
+    }
+    file
+}
+
+// Bring the folder and the database in step once
+pub async fn sync(store: &NoteStore, root: &Path) -> AppResult<MirrorReport> {
+    fs::create_dir_all(root)
+        .with_context(|| format!("Failed to create mirror folder {}", root.display()))?;
+
+    let mut manifest = load_manifest(root)?;
+    let mut report = MirrorReport::default();
+    let mut notes = store.get_all_notes().await?;
+    let collections = store.get_all_collections().await?;
+    let mut collection_dirs = collection_dirs(&collections);
+
+    // Files of notes that are gone from the database go too, unless they were
+    // edited since; those lose their manifest entry and come back as new notes
+    let live: HashSet<&str> = notes.iter().map(|note| note.id.as_str()).collect();
+    let gone: Vec<String> = manifest.notes.keys().filter(|id| !live.contains(id.as_str())).cloned().collect();
+    for id in gone {
+        let Some(entry) = manifest.notes.remove(&id) else { continue };
+        let path = root.join(&entry.path);
+        if let Ok(text) = fs::read_to_string(&path) {
+            if hash(&text) == entry.hash {
+                fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+                remove_empty_dirs(root, &path);
+                report.removed += 1;
+            }
+        }
+    }
+
+    // New files become notes
+    let tracked: HashSet<String> = manifest.notes.values().map(|entry| entry.path.to_lowercase()).collect();
+    for relative in markdown_files(root)? {
+        if tracked.contains(&relative.to_lowercase()) {
+            continue;
+        }
+        let path = root.join(&relative);
+        let text = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let parsed = parse_markdown(&text);
+
+        if let Some(note) = parsed.id.as_ref().and_then(|id| notes.iter().find(|note| &note.id == id)) {
+            // A note's file from before the manifest was lost: treat both
+            // sides as changed so differences surface as a conflict
+            if !manifest.notes.contains_key(&note.id) {
+                manifest.notes.insert(note.id.clone(), MirroredNote {
+                    path: relative,
+                    updated_at: DateTime::<Utc>::MIN_UTC,
+                    hash: String::new(),
+                });
+            }
+            continue;
+        }
+
+        let title = parsed.title.clone().unwrap_or_else(|| {
+            Path::new(&relative).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default()
+        });
+        let collection_id = collection_for_dir(store, &mut collection_dirs, parent_dir(&relative)).await?;
+
+        // Keep the id and dates a file already has, so copies of the vault
+        // synced through the folder write the same file for the same note
+        let id = parsed.id.filter(|id| uuid::Uuid::parse_str(id).is_ok()).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let now = Utc::now();
+        let note = store
+            .insert_note(
+                &id,
+                &title,
+                &parsed.content,
+                collection_id.as_deref(),
+                parsed.tags.as_deref().unwrap_or_default(),
+                (parsed.created.unwrap_or(now), parsed.updated.unwrap_or(now)),
+            )
+            .await?;
+        let written = write_note(root, &relative, &note)?;
+        manifest.notes.insert(note.id.clone(), written);
+        notes.push(note);
+        report.created += 1;
+    }
+
+    // Files claimed by notes, lowercased for case-insensitive file systems
+    let mut claimed: HashSet<String> = HashSet::new();
+    for note in &notes {
+        let expected = unique_path(&note_path(note, &collection_dirs), &note.id, &mut claimed);
+
+        let Some(entry) = manifest.notes.get(&note.id).cloned() else {
+            let written = write_note(root, &expected, note)?;
+            manifest.notes.insert(note.id.clone(), written);
+            report.written += 1;
+            continue;
+        };
+
+        let path = root.join(&entry.path);
+        let Ok(text) = fs::read_to_string(&path) else {
+            // Removed outside the app; the note stays and so does its file
+            let written = write_note(root, &expected, note)?;
+            manifest.notes.insert(note.id.clone(), written);
+            report.written += 1;
+            continue;
+        };
+        let file_changed = hash(&text) != entry.hash;
+        let db_changed = note.updated_at != entry.updated_at;
+
+        let mut current = note.clone();
+        // An encrypted note's file only holds its ciphertext; edits to it are
+        // undone below
+        if file_changed && !note.is_encrypted {
+            let parsed = parse_markdown(&text);
+            let tags: Vec<String> = serde_json::from_str(&note.tags).unwrap_or_default();
+            let same_content = parsed.content.trim_end() == note.content.trim_end();
+            let same_tags = parsed.tags.as_ref().is_none_or(|parsed| parsed == &tags);
+
+            if db_changed && !(same_content && same_tags) {
+                let copy = conflict_copy_path(&entry.path);
+                uploads::write_file_atomically(&root.join(parent_dir(&copy)), file_name(&copy), text.as_bytes())
+                    .with_context(|| format!("Failed to keep conflicting copy of {}", entry.path))?;
+                report.conflicts.push(MirrorConflict {
+                    note_id: note.id.clone(),
+                    path: expected.clone(),
+                    copy_path: copy,
+                });
+            } else if !db_changed && same_content && same_tags && entry.path == expected {
+                // Only the frontmatter changed, e.g. dates written by another
+                // copy of the vault; rewriting it would just flip it back
+                manifest.notes.insert(note.id.clone(), MirroredNote { hash: hash(&text), ..entry });
+                continue;
+            } else if !db_changed {
+                if !same_content {
+                    current = store.update_note_if_unchanged(&note.id, &parsed.content, note.updated_at).await?;
+                }
+                if !same_tags {
+                    current = store.set_tags(&note.id, parsed.tags.as_deref().unwrap_or_default()).await?;
+                }
+                if !(same_content && same_tags) {
+                    report.imported += 1;
+                }
+            }
+        }
+
+        if file_changed || db_changed || entry.path != expected {
+            let written = write_note(root, &expected, &current)?;
+            if !entry.path.eq_ignore_ascii_case(&expected) {
+                let _ = fs::remove_file(&path);
+                remove_empty_dirs(root, &path);
+            }
+            if db_changed && !file_changed {
+                report.written += 1;
+            }
+            manifest.notes.insert(note.id.clone(), written);
+        }
+    }
+
+    save_manifest(root, &manifest)?;
+    Ok(report)
+}
+
+// A mirror running in the background; dropping it without `stop` leaves it
+// running
+pub struct MirrorHandle {
+    root: PathBuf,
+    store: NoteStore,
+    // Held while syncing, so a manual sync never overlaps the background one
+    syncing: Arc<Mutex<()>>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl MirrorHandle {
+    // Sync once, then again whenever files in `root` or notes in the database
+    // change
+    pub async fn start(store: NoteStore, root: PathBuf) -> AppResult<MirrorHandle> {
+        let allowed = MirrorSettings::load(store.pool()).await?.allow_unencrypted;
+        crate::vault::check_unencrypted_copy(&store, allowed, "the Markdown mirror")?;
+        let report = sync(&store, &root).await?;
+        log_conflicts(&report);
+
+        let (events, mut changed) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            // Our own manifest and temporary files are not worth a sync
+            if let Ok(event) = event {
+                if event.paths.iter().any(|path| !is_hidden(path)) {
+                    let _ = events.send(());
+                }
+            }
+        })
+        .map_err(|e| AppError::Io(format!("Failed to watch mirror folder: {}", e)))?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| AppError::Io(format!("Failed to watch mirror folder: {}", e)))?;
+
+        let mut db_changes = ChangeWatcher::new(&store).await?;
+        let syncing = Arc::new(Mutex::new(()));
+        let (shutdown, mut stopped) = oneshot::channel::<()>();
+
+        let task = tokio::spawn({
+            let store = store.clone();
+            let root = root.clone();
+            let syncing = syncing.clone();
+            async move {
+                // Dropping the watcher stops it, so it lives as long as the task
+                let _watcher = watcher;
+                let mut ticker = tokio::time::interval(DB_POLL_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = &mut stopped => break,
+                        Some(()) = changed.recv() => {
+                            tokio::time::sleep(DEBOUNCE).await;
+                            while changed.try_recv().is_ok() {}
+                        }
+                        _ = ticker.tick() => match db_changes.poll(&store).await {
+                            Ok(changes) if changes.is_empty() => continue,
+                            Ok(_) => {}
+                            Err(e) => {
+                                eprintln!("Warning: {}", e);
+                                continue;
+                            }
+                        },
+                    }
+
+                    let _guard = syncing.lock().await;
+                    match sync(&store, &root).await {
+                        Ok(report) => log_conflicts(&report),
+                        Err(e) => eprintln!("Warning: Failed to sync Markdown mirror: {}", e),
+                    }
+                    // Skip the changes the sync itself made
+                    let _ = db_changes.poll(&store).await;
+                }
+            }
+        });
+
+        Ok(MirrorHandle { root, store, syncing, shutdown, task })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub async fn sync_now(&self) -> AppResult<MirrorReport> {
+        let _guard = self.syncing.lock().await;
+        let report = sync(&self.store, &self.root).await?;
+        log_conflicts(&report);
+        Ok(report)
+    }
+
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+fn log_conflicts(report: &MirrorReport) {
+    for conflict in &report.conflicts {
+        eprintln!(
+            "Warning: {} changed in the app and on disk, kept the file's version as {}",
+            conflict.path, conflict.copy_path
+        );
+    }
+}
+
+fn load_manifest(root: &Path) -> AppResult<Manifest> {
+    match fs::read_to_string(root.join(MANIFEST_FILE)) {
+        Ok(text) => serde_json::from_str(&text).context("Failed to read mirror manifest"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(e).context("Failed to read mirror manifest"),
+    }
+}
+
+fn save_manifest(root: &Path, manifest: &Manifest) -> AppResult<()> {
+    let text = serde_json::to_string_pretty(manifest).context("Failed to serialize mirror manifest")?;
+    uploads::write_file_atomically(root, MANIFEST_FILE, text.as_bytes())
+        .context("Failed to save mirror manifest")?;
+    Ok(())
+}
+
+fn write_note(root: &Path, relative: &str, note: &Note) -> AppResult<MirroredNote> {
+    let text = note_to_markdown(note);
+    let dir = root.join(parent_dir(relative));
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    uploads::write_file_atomically(&dir, file_name(relative), text.as_bytes())
+        .with_context(|| format!("Failed to write {}", relative))?;
+
+    Ok(MirroredNote {
+        path: relative.to_string(),
+        updated_at: note.updated_at,
+        hash: hash(&text),
+    })
+}
+
+fn hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+fn parent_dir(relative: &str) -> &str {
+    relative.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+}
+
+fn file_name(relative: &str) -> &str {
+    relative.rsplit_once('/').map(|(_, name)| name).unwrap_or(relative)
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+// Relative directory of every collection, following parents
+fn collection_dirs(collections: &[Collection]) -> HashMap<String, String> {
+    let by_id: HashMap<&str, &Collection> = collections.iter().map(|c| (c.id.as_str(), c)).collect();
+
+    collections
+        .iter()
+        .map(|collection| {
+            let mut parts = vec![safe_name(&collection.name)];
+            let mut parent = collection.parent_id.as_deref();
+            // Bounded in case of a cycle in the data
+            while let Some(parent_collection) = parent.and_then(|id| by_id.get(id)).filter(|_| parts.len() < 32) {
+                parts.push(safe_name(&parent_collection.name));
+                parent = parent_collection.parent_id.as_deref();
+            }
+            parts.reverse();
+            (collection.id.clone(), parts.join("/"))
+        })
+        .collect()
+}
+
+// The collection a directory stands for, creating the ones that do not exist
+// yet, e.g. for folders made by hand or pulled from another machine
+async fn collection_for_dir(
+    store: &NoteStore,
+    collection_dirs: &mut HashMap<String, String>,
+    directory: &str,
+) -> AppResult<Option<String>> {
+    let mut parent: Option<String> = None;
+    let mut path = String::new();
+
+    for segment in directory.split('/').filter(|segment| !segment.is_empty()) {
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(segment);
+
+        let existing = collection_dirs
+            .iter()
+            .find(|(_, dir)| dir.eq_ignore_ascii_case(&path))
+            .map(|(id, _)| id.clone());
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let collection = store.create_collection(segment, None, parent.as_deref()).await?;
+                collection_dirs.insert(collection.id.clone(), path.clone());
+                collection.id
+            }
+        };
+        parent = Some(id);
+    }
+
+    Ok(parent)
+}
+
+fn note_path(note: &Note, collection_dirs: &HashMap<String, String>) -> String {
+    let name = format!("{}.md", safe_name(&note.title));
+    match note.collection_id.as_ref().and_then(|id| collection_dirs.get(id)) {
+        Some(dir) => format!("{}/{}", dir, name),
+        None => name,
+    }
+}
+
+// Notes sharing a title get their short id added to the file name
+fn unique_path(path: &str, id: &str, claimed: &mut HashSet<String>) -> String {
+    let path = if claimed.contains(&path.to_lowercase()) {
+        let stem = path.strip_suffix(".md").unwrap_or(path);
+        format!("{} ({}).md", stem, id.get(..8).unwrap_or(id))
+    } else {
+        path.to_string()
+    };
+    claimed.insert(path.to_lowercase());
+    path
+}
+
+pub(crate) fn conflict_copy_path(path: &str) -> String {
+    let stem = path.strip_suffix(".md").unwrap_or(path);
+    format!("{}{}{}.md", stem, CONFLICT_MARKER, Utc::now().format("%Y%m%d-%H%M%S"))
+}
+
+// A file or directory name that is valid everywhere and not hidden
+fn safe_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '-' } else { c })
+        .take(100)
+        .collect();
+    let name = name.trim().trim_matches('.').trim();
+    if name.is_empty() {
+        "Untitled".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// Every `.md` file under `root` except hidden ones and conflict copies
+fn markdown_files(root: &Path) -> AppResult<Vec<String>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative) = pending.pop() {
+        let dir = root.join(&relative);
+        let entries = fs::read_dir(&dir).with_context(|| format!("Failed to list {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to list {}", dir.display()))?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = relative.join(&name);
+            let file_type = entry.file_type().with_context(|| format!("Failed to list {}", dir.display()))?;
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if name.ends_with(".md") && !name.contains(CONFLICT_MARKER) {
+                let parts: Vec<String> = path.iter().map(|part| part.to_string_lossy().to_string()).collect();
+                files.push(parts.join("/"));
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn remove_empty_dirs(root: &Path, file: &Path) {
+    let mut dir = file.parent();
+    while let Some(current) = dir.filter(|dir| *dir != root && dir.starts_with(root)) {
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Data models
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ImageMetadata {
+    pub id: String,
+    pub filename: String,
+    pub original_name: String,
+    pub file_path: String,
+    pub size: i64,
+    pub mime_type: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageWithData {
+    pub id: String,
+    pub filename: String,
+    pub original_name: String,
+    pub file_path: String,
+    pub size: i64,
+    pub mime_type: String,
+    pub created_at: DateTime<Utc>,
+    pub data_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Note {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+    pub collection_id: Option<String>,
+    pub tags: String, // JSON string
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub word_count: i32,
+    pub character_count: i32,
+    pub is_archived: bool,
+    // Content is ciphertext under the note's own password; see `note_encryption.rs`
+    #[serde(default)]
+    pub is_encrypted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Collection {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub parent_id: Option<String>,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub sort_order: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub note_id: String,
+    pub title: String,
+    pub excerpt: String,
+    pub highlights: Vec<String>,
+    pub relevance_score: f64,
+    pub last_modified: DateTime<Utc>,
+    // Name of the attachment whose text matched, if the note matched through one
+    pub matched_attachment: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchFilters {
+    pub collections: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+    pub date_range: Option<DateRange>,
+    pub content_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DateRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageInfo {
+    pub total_notes: u32,
+    pub total_collections: u32,
+    pub database_size: u64,
+    pub last_backup: Option<String>,
+}
+
+// What `shred_note` and `shred_image` could not finish. The item is deleted
+// either way; a warning means some of its data is still on disk for now.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShredResult {
+    pub warnings: Vec<String>,
+}
@@ -0,0 +1,195 @@
+// Notes encrypted with a password of their own, for the few that hold
+// secrets. `notes.content` keeps only the ciphertext (base64 of the format in
+// `crypto.rs`), the note is left out of the search index and its Yjs document
+// is dropped, so the text is nowhere else in the database. Notes are listed
+// as encrypted; the text is only given out after `unlock_note`, for the rest
+// of the session or until the note is locked again. An unlocked note's key is
+// kept, never its password.
+use crate::crypto::{self, PassphraseKey};
+use crate::error::{AppError, AppResult, Context};
+use crate::{count_characters, count_words, sanitize_content, Note, NoteStore};
+use base64::Engine;
+use chrono::Utc;
+use sqlx::SqliteConnection;
+
+const MIN_NOTE_PASSWORD_LEN: usize = 8;
+
+impl NoteStore {
+    // Encrypt the note's content with `password`. The note stays locked.
+    pub async fn encrypt_note(&self, id: &str, password: &str) -> AppResult<Note> {
+        check_strength(password)?;
+
+        let mut tx = self.pool().begin_with("BEGIN IMMEDIATE").await.context("Failed to encrypt note")?;
+        let note = fetch_note(&mut tx, id).await?;
+        if note.is_encrypted {
+            return Err(AppError::Conflict { message: "The note is already encrypted".to_string(), id: Some(id.to_string()) });
+        }
+
+        let note = sqlx::query_as::<_, Note>(
+            r#"
+            UPDATE notes
+            SET content = ?1, is_encrypted = TRUE, word_count = 0, character_count = 0, updated_at = ?2
+            WHERE id = ?3
+            RETURNING *
+            "#,
+        )
+        .bind(seal(&note.content, password)?)
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to encrypt note")?;
+        sqlx::query("DELETE FROM note_documents WHERE note_id = ?1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to encrypt note")?;
+        tx.commit().await.context("Failed to encrypt note")?;
+
+        self.unlocked_notes().remove(id);
+        Ok(note)
+    }
+
+    // The note with its text, which stays readable and editable for the rest
+    // of the session
+    pub async fn unlock_note(&self, id: &str, password: &str) -> AppResult<Note> {
+        let note = self.get_note(id).await?;
+        require_encrypted(&note)?;
+        let key = PassphraseKey::for_data(&decode(&note.content)?, password)?;
+        let content = open(&note.content, &key)?;
+
+        self.unlocked_notes().insert(id.to_string(), key);
+        Ok(Note { content, ..note })
+    }
+
+    pub fn lock_note(&self, id: &str) {
+        self.unlocked_notes().remove(id);
+    }
+
+    // Lock every note unlocked this session
+    pub fn lock_notes(&self) {
+        self.unlocked_notes().clear();
+    }
+
+    // The note as the user sees it: an encrypted note unlocked this session
+    // comes with its text, any other as stored
+    pub async fn read_note(&self, id: &str) -> AppResult<Note> {
+        let note = self.get_note(id).await?;
+        if !note.is_encrypted {
+            return Ok(note);
+        }
+        let content = match self.unlocked_notes().get(id) {
+            Some(key) => open(&note.content, key)?,
+            None => return Ok(note),
+        };
+        Ok(Note { content, ..note })
+    }
+
+    // Take the note's encryption off for good; it is searchable again
+    pub async fn decrypt_note(&self, id: &str, password: &str) -> AppResult<Note> {
+        let mut tx = self.pool().begin_with("BEGIN IMMEDIATE").await.context("Failed to decrypt note")?;
+        let note = fetch_note(&mut tx, id).await?;
+        require_encrypted(&note)?;
+        let key = PassphraseKey::for_data(&decode(&note.content)?, password)?;
+        let content = open(&note.content, &key)?;
+
+        let note = sqlx::query_as::<_, Note>(
+            r#"
+            UPDATE notes
+            SET content = ?1, is_encrypted = FALSE, word_count = ?2, character_count = ?3, updated_at = ?4
+            WHERE id = ?5
+            RETURNING *
+            "#,
+        )
+        .bind(&content)
+        .bind(count_words(&content))
+        .bind(count_characters(&content))
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to decrypt note")?;
+        tx.commit().await.context("Failed to decrypt note")?;
+
+        self.unlocked_notes().remove(id);
+        Ok(note)
+    }
+
+    // New text for an encrypted note, which has to be unlocked; saving a note
+    // with `update_note` ends up here for encrypted ones. Returns the note
+    // with its text.
+    pub(crate) async fn save_encrypted_content(&self, conn: &mut SqliteConnection, note: Note, text: &str) -> AppResult<Note> {
+        let text = sanitize_content(text);
+        let sealed = match self.unlocked_notes().get(&note.id) {
+            Some(key) => seal_with(&text, key)?,
+            None => {
+                return Err(AppError::Locked(format!(
+                    "Note {} is encrypted, unlock it with its password first",
+                    note.id
+                )))
+            }
+        };
+
+        let note = sqlx::query_as::<_, Note>("UPDATE notes SET content = ?1, updated_at = ?2 WHERE id = ?3 RETURNING *")
+            .bind(sealed)
+            .bind(Utc::now())
+            .bind(&note.id)
+            .fetch_one(&mut *conn)
+            .await
+            .context("Failed to update note")?;
+        Ok(Note { content: text, ..note })
+    }
+}
+
+async fn fetch_note(conn: &mut SqliteConnection, id: &str) -> AppResult<Note> {
+    sqlx::query_as::<_, Note>("SELECT * FROM notes WHERE id = ?1")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("Failed to get note")?
+        .ok_or_else(|| AppError::not_found("note", id))
+}
+
+fn require_encrypted(note: &Note) -> AppResult<()> {
+    if !note.is_encrypted {
+        return Err(AppError::Validation(format!("Note {} is not encrypted", note.id)));
+    }
+    Ok(())
+}
+
+fn check_strength(password: &str) -> AppResult<()> {
+    if password.chars().count() < MIN_NOTE_PASSWORD_LEN {
+        return Err(AppError::Validation(format!(
+            "The note password needs at least {} characters",
+            MIN_NOTE_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+fn seal(text: &str, password: &str) -> AppResult<String> {
+    let mut sealed = Vec::new();
+    crypto::encrypt(&mut text.as_bytes(), &mut sealed, password)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(sealed))
+}
+
+fn seal_with(text: &str, key: &PassphraseKey) -> AppResult<String> {
+    let mut sealed = Vec::new();
+    key.encrypt(&mut text.as_bytes(), &mut sealed)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(sealed))
+}
+
+fn decode(ciphertext: &str) -> AppResult<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(ciphertext.trim())
+        .map_err(|_| AppError::Validation("The encrypted note is damaged".to_string()))
+}
+
+fn open(ciphertext: &str, key: &PassphraseKey) -> AppResult<String> {
+    let mut plain = Vec::new();
+    key.decrypt(&mut &decode(ciphertext)?[..], &mut plain).map_err(|e| match e {
+        AppError::Unauthorized(_) => AppError::Unauthorized("Wrong note password".to_string()),
+        e => e,
+    })?;
+    String::from_utf8(plain).map_err(|_| AppError::Validation("The encrypted note is damaged".to_string()))
+}
@@ -0,0 +1,339 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::{count_characters, count_words, sanitize_content, Collection, Note, NoteStore};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+// Note management
+impl NoteStore {
+    pub async fn create_note(
+        &self,
+        title: &str,
+        content: &str,
+        collection_id: Option<&str>,
+    ) -> AppResult<Note> {
+        let now = Utc::now();
+        self.insert_note(&Uuid::new_v4().to_string(), title, content, collection_id, &[], (now, now)).await
+    }
+
+    // For notes that already have an id and dates elsewhere, like a Markdown
+    // file written by another copy of the vault
+    pub(crate) async fn insert_note(
+        &self,
+        id: &str,
+        title: &str,
+        content: &str,
+        collection_id: Option<&str>,
+        tags: &[String],
+        (created_at, updated_at): (DateTime<Utc>, DateTime<Utc>),
+    ) -> AppResult<Note> {
+        let sanitized_content = sanitize_content(content);
+        let word_count = count_words(&sanitized_content);
+        let character_count = count_characters(&sanitized_content);
+        let tags = tags_json(tags)?;
+
+        let note = sqlx::query_as::<_, Note>(
+            r#"
+            INSERT INTO notes (id, title, content, collection_id, tags, created_at, updated_at, word_count, character_count, is_archived)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(title)
+        .bind(&sanitized_content)
+        .bind(collection_id)
+        .bind(&tags)
+        .bind(created_at)
+        .bind(updated_at)
+        .bind(word_count)
+        .bind(character_count)
+        .bind(false)
+        .fetch_one(self.pool())
+        .await
+        .context("Failed to create note")?;
+
+        Ok(note)
+    }
+
+    // Save the note's full text. Only the parts that differ from what is
+    // stored are changed, so a collaborator's edits elsewhere in the note are
+    // kept; see `documents.rs`.
+    pub async fn update_note(&self, id: &str, content: &str) -> AppResult<Note> {
+        self.replace_content(id, content, None).await
+    }
+
+    // Update only if nobody else changed the note since `expected_updated_at`,
+    // for edits made outside the app that may race with it
+    pub async fn update_note_if_unchanged(
+        &self,
+        id: &str,
+        content: &str,
+        expected_updated_at: DateTime<Utc>,
+    ) -> AppResult<Note> {
+        self.replace_content(id, content, Some(expected_updated_at)).await
+    }
+
+    // Replace the note's tags; blank and repeated tags are dropped
+    pub async fn set_tags(&self, id: &str, tags: &[String]) -> AppResult<Note> {
+        let tags = tags_json(tags)?;
+
+        sqlx::query_as::<_, Note>(
+            "UPDATE notes SET tags = ?1, updated_at = ?2 WHERE id = ?3 RETURNING *"
+        )
+        .bind(&tags)
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_optional(self.pool())
+        .await
+        .context("Failed to update tags")?
+        .ok_or_else(|| AppError::not_found("note", id))
+    }
+
+    pub async fn delete_note(&self, id: &str) -> AppResult<()> {
+        let result = sqlx::query("DELETE FROM notes WHERE id = ?1")
+            .bind(id)
+            .execute(self.pool())
+            .await
+            .context("Failed to delete note")?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::not_found("note", id));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_note(&self, id: &str) -> AppResult<Note> {
+        sqlx::query_as::<_, Note>("SELECT * FROM notes WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(self.pool())
+            .await
+            .context("Failed to get note")?
+            .ok_or_else(|| AppError::not_found("note", id))
+    }
+
+    // Full id of the note whose id is `id` or starts with it, so people can
+    // type the short ids tools print
+    pub async fn resolve_note_id(&self, id: &str) -> AppResult<String> {
+        let matches: Vec<(String,)> = sqlx::query_as(
+            "SELECT id FROM notes WHERE substr(id, 1, length(?1)) = ?1 ORDER BY id = ?1 DESC LIMIT 2"
+        )
+        .bind(id)
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to look up note")?;
+
+        match matches.as_slice() {
+            [(full_id,), ..] if full_id == id => Ok(full_id.clone()),
+            [(full_id,)] => Ok(full_id.clone()),
+            [] => Err(AppError::not_found("note", id)),
+            _ => Err(AppError::Validation(format!("Note id prefix {} matches more than one note", id))),
+        }
+    }
+
+    // The note a link in note text points at: `[[Title]]`, `[[Title|label]]`,
+    // `[[Title#heading]]`, a bare title, or a note id or id prefix. Titles
+    // match case-insensitively; when several notes share one, the most
+    // recently updated wins.
+    pub async fn resolve_link(&self, target: &str) -> AppResult<Note> {
+        let target = target.trim();
+        let target = target
+            .strip_prefix("[[")
+            .and_then(|target| target.strip_suffix("]]"))
+            .unwrap_or(target);
+        let target = target.split(['|', '#']).next().unwrap_or_default().trim();
+        if target.is_empty() {
+            return Err(AppError::Validation("Link target is empty".to_string()));
+        }
+
+        let by_title = sqlx::query_as::<_, Note>(
+            "SELECT * FROM notes WHERE title = ?1 COLLATE NOCASE ORDER BY updated_at DESC LIMIT 1"
+        )
+        .bind(target)
+        .fetch_optional(self.pool())
+        .await
+        .context("Failed to resolve link")?;
+
+        match by_title {
+            Some(note) => Ok(note),
+            None => {
+                let id = self.resolve_note_id(target).await.map_err(|e| match e {
+                    AppError::NotFound { .. } => AppError::not_found("note", target),
+                    e => e,
+                })?;
+                self.get_note(&id).await
+            }
+        }
+    }
+
+    pub async fn get_all_notes(&self) -> AppResult<Vec<Note>> {
+        sqlx::query_as::<_, Note>("SELECT * FROM notes WHERE is_archived = FALSE ORDER BY updated_at DESC")
+            .fetch_all(self.pool())
+            .await
+            .context("Failed to get notes")
+    }
+
+    pub async fn move_note_to_collection(&self, note_id: &str, collection_id: Option<&str>) -> AppResult<()> {
+        let now = Utc::now();
+
+        let result = sqlx::query(
+            "UPDATE notes SET collection_id = ?1, updated_at = ?2 WHERE id = ?3"
+        )
+        .bind(collection_id)
+        .bind(now)
+        .bind(note_id)
+        .execute(self.pool())
+        .await
+        .context("Failed to move note")?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::not_found("note", note_id));
+        }
+
+        Ok(())
+    }
+}
+
+fn tags_json(tags: &[String]) -> AppResult<String> {
+    let mut cleaned: Vec<&str> = Vec::new();
+    for tag in tags.iter().map(|tag| tag.trim()) {
+        if !tag.is_empty() && !cleaned.contains(&tag) {
+            cleaned.push(tag);
+        }
+    }
+    serde_json::to_string(&cleaned).context("Failed to serialize tags")
+}
+
+// Collection management
+impl NoteStore {
+    pub async fn create_collection(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        parent_id: Option<&str>,
+    ) -> AppResult<Collection> {
+        let id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+
+        // Get the next sort order for this parent
+        let sort_order = if let Some(parent) = parent_id {
+            let (max_order,): (Option<i32>,) = sqlx::query_as(
+                "SELECT MAX(sort_order) FROM collections WHERE parent_id = ?1"
+            )
+            .bind(parent)
+            .fetch_one(self.pool())
+            .await
+            .context("Failed to get sort order")?;
+
+            max_order.unwrap_or(0) + 1
+        } else {
+            let (max_order,): (Option<i32>,) = sqlx::query_as(
+                "SELECT MAX(sort_order) FROM collections WHERE parent_id IS NULL"
+            )
+            .fetch_one(self.pool())
+            .await
+            .context("Failed to get sort order")?;
+
+            max_order.unwrap_or(0) + 1
+        };
+
+        let collection = sqlx::query_as::<_, Collection>(
+            r#"
+            INSERT INTO collections (id, name, description, parent_id, sort_order, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            RETURNING *
+            "#,
+        )
+        .bind(&id)
+        .bind(name)
+        .bind(description)
+        .bind(parent_id)
+        .bind(sort_order)
+        .bind(now)
+        .bind(now)
+        .fetch_one(self.pool())
+        .await
+        .context("Failed to create collection")?;
+
+        Ok(collection)
+    }
+
+    pub async fn update_collection(
+        &self,
+        id: &str,
+        name: &str,
+        description: Option<&str>,
+    ) -> AppResult<Collection> {
+        let now = Utc::now();
+
+        sqlx::query_as::<_, Collection>(
+            r#"
+            UPDATE collections
+            SET name = ?1, description = ?2, updated_at = ?3
+            WHERE id = ?4
+            RETURNING *
+            "#,
+        )
+        .bind(name)
+        .bind(description)
+        .bind(now)
+        .bind(id)
+        .fetch_optional(self.pool())
+        .await
+        .context("Failed to update collection")?
+        .ok_or_else(|| AppError::not_found("collection", id))
+    }
+
+    pub async fn delete_collection(&self, id: &str) -> AppResult<()> {
+        // Check if collection has child collections
+        let (child_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM collections WHERE parent_id = ?1")
+            .bind(id)
+            .fetch_one(self.pool())
+            .await
+            .context("Failed to check child collections")?;
+
+        if child_count > 0 {
+            return Err(AppError::Conflict {
+                message: "Cannot delete collection with child collections".to_string(),
+                id: Some(id.to_string()),
+            });
+        }
+
+        // Move notes in this collection to no collection
+        sqlx::query("UPDATE notes SET collection_id = NULL WHERE collection_id = ?1")
+            .bind(id)
+            .execute(self.pool())
+            .await
+            .context("Failed to update notes")?;
+
+        let result = sqlx::query("DELETE FROM collections WHERE id = ?1")
+            .bind(id)
+            .execute(self.pool())
+            .await
+            .context("Failed to delete collection")?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::not_found("collection", id));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_collection(&self, id: &str) -> AppResult<Collection> {
+        sqlx::query_as::<_, Collection>("SELECT * FROM collections WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(self.pool())
+            .await
+            .context("Failed to get collection")?
+            .ok_or_else(|| AppError::not_found("collection", id))
+    }
+
+    pub async fn get_all_collections(&self) -> AppResult<Vec<Collection>> {
+        sqlx::query_as::<_, Collection>(
+            "SELECT * FROM collections ORDER BY parent_id, sort_order"
+        )
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to get collections")
+    }
+}
@@ -0,0 +1,180 @@
+use crate::error::{AppError, AppResult, Context};
+use crate::backup::DATABASE_FILE;
+use crate::maintenance;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// Holds the path of a database the user opened from elsewhere; absent means
+// the default `notura.db` in the app data directory
+const LOCATION_FILE: &str = "database-location";
+pub(crate) const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepairReport {
+    // Where copies of the files were saved before anything was changed
+    pub saved_copy: String,
+    // Problems found before the repair, empty if the database was readable
+    pub problems_found: Vec<String>,
+    pub discarded_wal: bool,
+    pub reindexed: bool,
+}
+
+pub fn database_path(app_dir: &Path) -> PathBuf {
+    fs::read_to_string(app_dir.join(LOCATION_FILE))
+        .ok()
+        .map(|location| location.trim().to_string())
+        .filter(|location| !location.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| app_dir.join(DATABASE_FILE))
+}
+
+// Use the database at `path` from now on, or go back to the default with None
+pub fn set_database_location(app_dir: &Path, path: Option<&Path>) -> AppResult<()> {
+    let location_file = app_dir.join(LOCATION_FILE);
+
+    match path {
+        Some(path) => fs::write(&location_file, path.to_string_lossy().as_bytes())
+            .context("Failed to save database location"),
+        None if location_file.exists() => fs::remove_file(&location_file)
+            .context("Failed to reset database location"),
+        None => Ok(()),
+    }
+}
+
+pub fn validate_database_file(path: &Path) -> AppResult<()> {
+    let mut header = [0u8; 16];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    if header != SQLITE_HEADER {
+        return Err(AppError::Validation(format!("{} is not a Notura database", path.display())));
+    }
+
+    Ok(())
+}
+
+// The database file and its WAL and shared-memory companions
+pub(crate) fn database_files(database_path: &Path) -> Vec<PathBuf> {
+    let name = database_path.to_string_lossy();
+    vec![
+        database_path.to_path_buf(),
+        PathBuf::from(format!("{}-wal", name)),
+        PathBuf::from(format!("{}-shm", name)),
+    ]
+}
+
+fn timestamped_dir(database_path: &Path, prefix: &str) -> AppResult<PathBuf> {
+    let parent = database_path.parent().unwrap_or(Path::new("."));
+    let dir = parent.join(format!("{}-{}", prefix, Utc::now().format("%Y%m%d-%H%M%S")));
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+// Move a broken database out of the way so a fresh one can be created in its
+// place. Returns the directory the files were moved to.
+pub fn move_database_aside(database_path: &Path) -> AppResult<PathBuf> {
+    let aside_dir = timestamped_dir(database_path, "broken")?;
+
+    for file in database_files(database_path) {
+        if let Some(name) = file.file_name().filter(|_| file.exists()) {
+            fs::rename(&file, aside_dir.join(name))
+                .with_context(|| format!("Failed to move {} aside", file.display()))?;
+        }
+    }
+
+    Ok(aside_dir)
+}
+
+async fn open_for_repair(database_path: &Path) -> AppResult<SqlitePool> {
+    let options = SqliteConnectOptions::new()
+        .filename(database_path)
+        .journal_mode(SqliteJournalMode::Wal);
+
+    SqlitePool::connect_with(options)
+        .await
+        .context("Failed to open database")
+}
+
+// Open the database and run a full integrity check, treating a database that
+// cannot even be opened as one more problem
+async fn check(database_path: &Path) -> Vec<String> {
+    let pool = match open_for_repair(database_path).await {
+        Ok(pool) => pool,
+        Err(e) => return vec![e.to_string()],
+    };
+
+    let problems = maintenance::integrity_check(&pool, true)
+        .await
+        .unwrap_or_else(|e| vec![e.to_string()]);
+    pool.close().await;
+    problems
+}
+
+// Try to bring a damaged database back into a usable state. Copies of the
+// original files are saved first. A corrupted WAL is discarded, which loses
+// the changes that were not yet checkpointed but keeps everything else, and
+// broken indexes are rebuilt. Fails if the main database file itself is damaged.
+pub async fn repair_database(database_path: &Path) -> AppResult<RepairReport> {
+    if !database_path.exists() {
+        return Err(AppError::Io(format!("Database {} does not exist", database_path.display())));
+    }
+
+    let saved_copy = timestamped_dir(database_path, "recovery")?;
+    for file in database_files(database_path) {
+        if let Some(name) = file.file_name().filter(|_| file.exists()) {
+            fs::copy(&file, saved_copy.join(name))
+                .with_context(|| format!("Failed to save a copy of {}", file.display()))?;
+        }
+    }
+
+    let mut report = RepairReport {
+        saved_copy: saved_copy.to_string_lossy().to_string(),
+        problems_found: check(database_path).await,
+        discarded_wal: false,
+        reindexed: false,
+    };
+    if report.problems_found.is_empty() {
+        return Ok(report);
+    }
+
+    let mut problems = report.problems_found.clone();
+
+    let wal_files = &database_files(database_path)[1..];
+    if wal_files.iter().any(|file| file.exists()) {
+        for file in wal_files {
+            let _ = fs::remove_file(file);
+        }
+        report.discarded_wal = true;
+        problems = check(database_path).await;
+    }
+
+    if !problems.is_empty() {
+        let pool = open_for_repair(database_path).await?;
+        let reindexed = sqlx::query("REINDEX").execute(&pool).await;
+        report.reindexed = reindexed.is_ok();
+        problems = maintenance::integrity_check(&pool, true)
+            .await
+            .unwrap_or_else(|e| vec![e.to_string()]);
+
+        // The search index may have drifted along with the damaged pages
+        if problems.is_empty() {
+            let _ = maintenance::rebuild_search_index(&pool).await;
+        }
+        pool.close().await;
+    }
+
+    if !problems.is_empty() {
+        return Err(AppError::Database(format!(
+            "The database could not be repaired ({}). Restore a backup or start fresh instead.",
+            problems.join("; ")
+        )));
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,619 @@
+// Off-machine copies of backups in an S3-compatible bucket (AWS, MinIO,
+// Backblaze B2, Wasabi, ...). Archives are encrypted with the configured
+// passphrase before they leave the machine, so the bucket only ever holds
+// ciphertext, and go up as multipart uploads. An upload that is cut short
+// keeps its encrypted copy and upload id next to the backup and carries on
+// from the parts the bucket already has the next time.
+use crate::backup::{self, BackupInfo};
+use crate::crypto;
+use crate::error::{AppError, AppResult, Context};
+use crate::settings::S3BackupSettings;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::{header, Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+// The smallest part S3 accepts, except for the last one
+const PART_SIZE: u64 = 5 * 1024 * 1024;
+// Remote copies are named after the backup plus this
+const ENCRYPTED_SUFFIX: &str = ".enc";
+const PENDING_SUFFIX: &str = ".upload";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteBackup {
+    // The backup it was uploaded from, and is restored as
+    pub file_name: String,
+    pub key: String,
+    pub created_at: DateTime<Utc>,
+    // Of the encrypted copy
+    pub size: u64,
+}
+
+// An upload in progress, saved next to its encrypted copy
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingUpload {
+    key: String,
+    upload_id: String,
+}
+
+// Encrypt a local backup and upload it, or finish uploading it if an earlier
+// attempt was cut short
+pub async fn upload_backup(app_dir: &Path, settings: &S3BackupSettings, file_name: &str) -> AppResult<RemoteBackup> {
+    let archive_path = backup::resolve_backup(app_dir, file_name)?;
+    let bucket = Bucket::new(settings)?;
+    let dir = backup::backups_dir(app_dir);
+
+    let encrypted = encrypted_path(&dir, file_name);
+    if !pending_path(&dir, file_name).exists() || !encrypted.exists() {
+        let _ = fs::remove_file(pending_path(&dir, file_name));
+        let passphrase = settings.passphrase.clone();
+        let destination = encrypted.clone();
+        tokio::task::spawn_blocking(move || crypto::encrypt_file(&archive_path, &destination, &passphrase))
+            .await
+            .context("Encryption task failed")?
+            .context("Failed to encrypt the backup")?;
+    }
+
+    finish_upload(&bucket, &dir, file_name).await
+}
+
+// Finish every upload that was cut short; they go on even if the local
+// backup has been pruned since
+pub async fn resume_uploads(app_dir: &Path, settings: &S3BackupSettings) -> AppResult<Vec<RemoteBackup>> {
+    let dir = backup::backups_dir(app_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let pending = fs::read_dir(&dir)
+        .context("Failed to read backups directory")?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let file_name = name.strip_prefix('.')?.strip_suffix(PENDING_SUFFIX)?;
+            backup::check_backup_name(file_name).ok()?;
+            Some(file_name.to_string())
+        })
+        .collect::<Vec<_>>();
+    if pending.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let bucket = Bucket::new(settings)?;
+    let mut uploaded = Vec::new();
+    for file_name in pending {
+        if !encrypted_path(&dir, &file_name).exists() {
+            let _ = fs::remove_file(pending_path(&dir, &file_name));
+            continue;
+        }
+        uploaded.push(finish_upload(&bucket, &dir, &file_name).await?);
+    }
+    Ok(uploaded)
+}
+
+async fn finish_upload(bucket: &Bucket, dir: &Path, file_name: &str) -> AppResult<RemoteBackup> {
+    let encrypted = encrypted_path(dir, file_name);
+    let pending_path = pending_path(dir, file_name);
+    let key = bucket.key(file_name);
+    let size = fs::metadata(&encrypted)
+        .context("Failed to read the encrypted backup")?
+        .len();
+
+    // Parts the bucket kept from an earlier attempt; an upload that has
+    // expired or was aborted meanwhile starts over
+    let pending = fs::read(&pending_path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<PendingUpload>(&data).ok())
+        .filter(|pending| pending.key == key);
+    let existing = match &pending {
+        Some(pending) => bucket.list_parts(&key, &pending.upload_id).await?,
+        None => None,
+    };
+    let (upload_id, existing) = match (pending, existing) {
+        (Some(pending), Some(parts)) => (pending.upload_id, parts),
+        _ => {
+            let upload_id = bucket.create_upload(&key).await?;
+            let pending = PendingUpload { key: key.clone(), upload_id: upload_id.clone() };
+            fs::write(&pending_path, serde_json::to_vec(&pending)?)
+                .context("Failed to save the upload's progress")?;
+            (upload_id, HashMap::new())
+        }
+    };
+
+    let mut file = tokio::fs::File::open(&encrypted)
+        .await
+        .context("Failed to read the encrypted backup")?;
+    let part_count = size.div_ceil(PART_SIZE).max(1);
+    let mut parts = Vec::new();
+    for number in 1..=part_count {
+        let offset = (number - 1) * PART_SIZE;
+        let length = PART_SIZE.min(size - offset);
+        if let Some((etag, part_size)) = existing.get(&number) {
+            if *part_size == length {
+                parts.push((number, etag.clone()));
+                continue;
+            }
+        }
+
+        let mut data = vec![0u8; length as usize];
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .context("Failed to read the encrypted backup")?;
+        file.read_exact(&mut data)
+            .await
+            .context("Failed to read the encrypted backup")?;
+        let etag = bucket.upload_part(&key, &upload_id, number, data).await?;
+        parts.push((number, etag));
+    }
+
+    bucket.complete_upload(&key, &upload_id, &parts).await?;
+    let _ = fs::remove_file(&encrypted);
+    let _ = fs::remove_file(&pending_path);
+
+    Ok(RemoteBackup {
+        file_name: file_name.to_string(),
+        created_at: backup::backup_time(file_name).unwrap_or_else(Utc::now),
+        key,
+        size,
+    })
+}
+
+fn encrypted_path(dir: &Path, file_name: &str) -> PathBuf {
+    dir.join(format!(".{}{}", file_name, ENCRYPTED_SUFFIX))
+}
+
+fn pending_path(dir: &Path, file_name: &str) -> PathBuf {
+    dir.join(format!(".{}{}", file_name, PENDING_SUFFIX))
+}
+
+// Backups in the bucket under the configured prefix, newest first
+pub async fn list_remote_backups(settings: &S3BackupSettings) -> AppResult<Vec<RemoteBackup>> {
+    let bucket = Bucket::new(settings)?;
+    let mut backups = bucket
+        .list(&bucket.prefix)
+        .await?
+        .into_iter()
+        .filter_map(|(key, size)| {
+            let file_name = key.strip_prefix(&bucket.prefix)?.strip_suffix(ENCRYPTED_SUFFIX)?.to_string();
+            let created_at = backup::backup_time(&file_name)?;
+            Some(RemoteBackup { file_name, key, created_at, size })
+        })
+        .collect::<Vec<_>>();
+
+    backups.sort_by_key(|backup| Reverse(backup.created_at));
+    Ok(backups)
+}
+
+// Download and decrypt a remote backup into the backups directory, where it
+// can be restored like any other. A backup that is already there is kept.
+pub async fn download_backup(app_dir: &Path, settings: &S3BackupSettings, file_name: &str) -> AppResult<BackupInfo> {
+    backup::check_backup_name(file_name)?;
+    let dir = backup::backups_dir(app_dir);
+    let destination = dir.join(file_name);
+    if let Some(info) = backup::backup_info(&destination) {
+        return Ok(info);
+    }
+
+    let bucket = Bucket::new(settings)?;
+    fs::create_dir_all(&dir).context("Failed to create backups directory")?;
+    let encrypted = dir.join(format!(".{}{}.download", file_name, ENCRYPTED_SUFFIX));
+    let decrypted = dir.join(format!(".{}.download", file_name));
+
+    let result = async {
+        bucket.download(&bucket.key(file_name), &encrypted).await?;
+
+        let passphrase = settings.passphrase.clone();
+        let (source, target) = (encrypted.clone(), decrypted.clone());
+        tokio::task::spawn_blocking(move || crypto::decrypt_file(&source, &target, &passphrase))
+            .await
+            .context("Decryption task failed")?
+            .context("Failed to decrypt the backup")?;
+
+        backup::verify_backup(&decrypted).await?;
+        fs::rename(&decrypted, &destination).context("Failed to move the backup into place")
+    }
+    .await;
+
+    let _ = fs::remove_file(&encrypted);
+    let _ = fs::remove_file(&decrypted);
+    result?;
+
+    backup::backup_info(&destination).ok_or_else(|| AppError::Io(format!("Failed to read backup {}", file_name)))
+}
+
+pub async fn delete_remote_backup(settings: &S3BackupSettings, file_name: &str) -> AppResult<()> {
+    backup::check_backup_name(file_name)?;
+    let bucket = Bucket::new(settings)?;
+    bucket.delete(&bucket.key(file_name)).await
+}
+
+// Apply the remote retention policy, returning the backups removed
+pub async fn prune_remote_backups(settings: &S3BackupSettings) -> AppResult<Vec<RemoteBackup>> {
+    let remote = list_remote_backups(settings).await?;
+    let candidates = remote
+        .iter()
+        .map(|backup| BackupInfo {
+            file_name: backup.file_name.clone(),
+            path: backup.key.clone(),
+            created_at: backup.created_at,
+            size: backup.size,
+        })
+        .collect::<Vec<_>>();
+    let pruned = backup::backups_to_prune(&candidates, &settings.retention())
+        .into_iter()
+        .map(|backup| backup.file_name)
+        .collect::<Vec<_>>();
+
+    let bucket = Bucket::new(settings)?;
+    let mut removed = Vec::new();
+    for backup in remote.into_iter().filter(|backup| pruned.contains(&backup.file_name)) {
+        bucket.delete(&backup.key).await?;
+        removed.push(backup);
+    }
+    Ok(removed)
+}
+
+// Just enough S3: multipart uploads, listing, GET and DELETE, signed with
+// AWS Signature Version 4
+struct Bucket {
+    client: reqwest::Client,
+    scheme: String,
+    // The endpoint's host and port, or the bucket's subdomain of it
+    host: String,
+    // `/<bucket>` for path-style addressing, empty otherwise
+    base_path: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    // Empty, or ends with a slash
+    prefix: String,
+}
+
+impl Bucket {
+    fn new(settings: &S3BackupSettings) -> AppResult<Bucket> {
+        settings.check_complete()?;
+        let endpoint = Url::parse(settings.endpoint.trim())
+            .map_err(|e| AppError::Validation(format!("Invalid S3 endpoint {}: {}", settings.endpoint, e)))?;
+        let host = endpoint
+            .host_str()
+            .ok_or_else(|| AppError::Validation(format!("Invalid S3 endpoint {}", settings.endpoint)))?;
+        let host = match endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+
+        let bucket = settings.bucket.trim();
+        let (host, base_path) = if settings.path_style {
+            (host, format!("/{}", uri_encode(bucket, true)))
+        } else {
+            (format!("{}.{}", bucket, host), String::new())
+        };
+        let prefix = settings.prefix.trim();
+        let prefix = if prefix.is_empty() || prefix.ends_with('/') {
+            prefix.to_string()
+        } else {
+            format!("{}/", prefix)
+        };
+
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to set up the S3 client")?;
+
+        Ok(Bucket {
+            client,
+            scheme: endpoint.scheme().to_string(),
+            host,
+            base_path,
+            region: settings.region.trim().to_string(),
+            access_key_id: settings.access_key_id.trim().to_string(),
+            secret_access_key: settings.secret_access_key.clone(),
+            prefix,
+        })
+    }
+
+    fn key(&self, file_name: &str) -> String {
+        format!("{}{}{}", self.prefix, file_name, ENCRYPTED_SUFFIX)
+    }
+
+    async fn send(&self, method: Method, key: &str, query: &[(&str, &str)], body: Vec<u8>) -> AppResult<reqwest::Response> {
+        let path = if key.is_empty() && !self.base_path.is_empty() {
+            self.base_path.clone()
+        } else {
+            format!("{}/{}", self.base_path, uri_encode(key, false))
+        };
+        let mut query = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect::<Vec<_>>();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let payload_hash = hex(&Sha256::digest(&body));
+        let now = Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, self.host, payload_hash, timestamp, SIGNED_HEADERS, payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut signing_key = hmac(format!("AWS4{}", self.secret_access_key).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes());
+        }
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            scope,
+            SIGNED_HEADERS,
+            hex(&hmac(&signing_key, string_to_sign.as_bytes()))
+        );
+
+        let url = if query.is_empty() {
+            format!("{}://{}{}", self.scheme, self.host, path)
+        } else {
+            format!("{}://{}{}?{}", self.scheme, self.host, path, query)
+        };
+        self.client
+            .request(method, url)
+            .header("x-amz-date", timestamp)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(header::AUTHORIZATION, authorization)
+            .body(body)
+            .send()
+            .await
+            .context("Failed to reach the S3 endpoint")
+    }
+
+    async fn create_upload(&self, key: &str) -> AppResult<String> {
+        let response = self.send(Method::POST, key, &[("uploads", "")], Vec::new()).await?;
+        let body = expect_success(response, "Failed to start the upload").await?;
+        xml_records(&body, "InitiateMultipartUploadResult")?
+            .into_iter()
+            .find_map(|mut result| result.remove("UploadId"))
+            .ok_or_else(|| AppError::Unavailable("The S3 server did not start the upload".to_string()))
+    }
+
+    // The parts uploaded so far by number, with their ETag and size; None if
+    // the upload no longer exists
+    async fn list_parts(&self, key: &str, upload_id: &str) -> AppResult<Option<HashMap<u64, (String, u64)>>> {
+        let mut parts = HashMap::new();
+        let mut marker = String::new();
+        loop {
+            let mut query = vec![("uploadId", upload_id)];
+            if !marker.is_empty() {
+                query.push(("part-number-marker", marker.as_str()));
+            }
+            let response = self.send(Method::GET, key, &query, Vec::new()).await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let body = expect_success(response, "Failed to list uploaded parts").await?;
+
+            for part in xml_records(&body, "Part")? {
+                let number = part.get("PartNumber").and_then(|number| number.parse().ok());
+                let size = part.get("Size").and_then(|size| size.parse().ok());
+                if let (Some(number), Some(size), Some(etag)) = (number, size, part.get("ETag")) {
+                    parts.insert(number, (etag.clone(), size));
+                }
+            }
+
+            let result = xml_records(&body, "ListPartsResult")?.into_iter().next().unwrap_or_default();
+            match result.get("NextPartNumberMarker") {
+                Some(next) if result.get("IsTruncated").map(String::as_str) == Some("true") => marker = next.clone(),
+                _ => return Ok(Some(parts)),
+            }
+        }
+    }
+
+    async fn upload_part(&self, key: &str, upload_id: &str, number: u64, data: Vec<u8>) -> AppResult<String> {
+        let number_text = number.to_string();
+        let query = [("partNumber", number_text.as_str()), ("uploadId", upload_id)];
+        let response = self.send(Method::PUT, key, &query, data).await?;
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        expect_success(response, &format!("Failed to upload part {}", number)).await?;
+        etag.ok_or_else(|| AppError::Unavailable(format!("The S3 server did not acknowledge part {}", number)))
+    }
+
+    async fn complete_upload(&self, key: &str, upload_id: &str, parts: &[(u64, String)]) -> AppResult<()> {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (number, etag) in parts {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number,
+                quick_xml::escape::escape(etag.as_str())
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+
+        let response = self.send(Method::POST, key, &[("uploadId", upload_id)], body.into_bytes()).await?;
+        let body = expect_success(response, "Failed to finish the upload").await?;
+        // Failures can also arrive as an error document with status 200
+        match xml_records(&body, "Error")?.into_iter().next() {
+            Some(error) => Err(AppError::Unavailable(format!(
+                "Failed to finish the upload: {}",
+                error.get("Message").or_else(|| error.get("Code")).cloned().unwrap_or_default()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // Keys and sizes of the objects under `prefix`
+    async fn list(&self, prefix: &str) -> AppResult<Vec<(String, u64)>> {
+        let mut objects = Vec::new();
+        let mut continuation = String::new();
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if !continuation.is_empty() {
+                query.push(("continuation-token", continuation.as_str()));
+            }
+            let response = self.send(Method::GET, "", &query, Vec::new()).await?;
+            let body = expect_success(response, "Failed to list remote backups").await?;
+
+            for object in xml_records(&body, "Contents")? {
+                if let Some(key) = object.get("Key") {
+                    let size = object.get("Size").and_then(|size| size.parse().ok()).unwrap_or(0);
+                    objects.push((key.clone(), size));
+                }
+            }
+
+            let result = xml_records(&body, "ListBucketResult")?.into_iter().next().unwrap_or_default();
+            match result.get("NextContinuationToken") {
+                Some(next) if result.get("IsTruncated").map(String::as_str) == Some("true") => continuation = next.clone(),
+                _ => return Ok(objects),
+            }
+        }
+    }
+
+    async fn download(&self, key: &str, destination: &Path) -> AppResult<()> {
+        let response = self.send(Method::GET, key, &[], Vec::new()).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(AppError::not_found("remote backup", key));
+        }
+        let mut response = expect_status(response, "Failed to download the backup").await?;
+
+        let mut file = tokio::fs::File::create(destination)
+            .await
+            .context("Failed to save the downloaded backup")?;
+        while let Some(chunk) = response.chunk().await.context("Failed to download the backup")? {
+            file.write_all(&chunk)
+                .await
+                .context("Failed to save the downloaded backup")?;
+        }
+        file.sync_all().await.context("Failed to save the downloaded backup")
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        let response = self.send(Method::DELETE, key, &[], Vec::new()).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        expect_success(response, "Failed to delete the remote backup").await.map(|_| ())
+    }
+}
+
+// The response body if the request succeeded
+async fn expect_success(response: reqwest::Response, action: &str) -> AppResult<String> {
+    let response = expect_status(response, action).await?;
+    response.text().await.with_context(|| action.to_string())
+}
+
+async fn expect_status(response: reqwest::Response, action: &str) -> AppResult<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let error = xml_records(&body, "Error").ok().and_then(|errors| errors.into_iter().next()).unwrap_or_default();
+    let reason = error
+        .get("Message")
+        .or_else(|| error.get("Code"))
+        .cloned()
+        .unwrap_or_else(|| format!("HTTP {}", status));
+
+    Err(match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            AppError::Unauthorized(format!("{}: the S3 server refused the access key ({})", action, reason))
+        }
+        _ => AppError::Unavailable(format!("{}: {}", action, reason)),
+    })
+}
+
+// The text of the child elements of every `element` in an S3 response, e.g.
+// Key and Size of each Contents entry in a listing
+fn xml_records(xml: &str, element: &str) -> AppResult<Vec<HashMap<String, String>>> {
+    let mut reader = Reader::from_str(xml);
+    let mut records = Vec::new();
+    let mut current: Option<HashMap<String, String>> = None;
+    // Inside the current record: 1 in one of its fields, more deeper down
+    let mut depth = 0;
+    let mut field = String::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(start)) => {
+                let name = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+                if current.is_some() {
+                    depth += 1;
+                    if depth == 1 {
+                        field = name;
+                    }
+                } else if name == element {
+                    current = Some(HashMap::new());
+                    depth = 0;
+                }
+            }
+            Ok(Event::End(_)) if current.is_some() => {
+                if depth == 0 {
+                    records.extend(current.take());
+                } else {
+                    depth -= 1;
+                }
+            }
+            Ok(Event::Text(text)) if depth == 1 => {
+                let text = text
+                    .unescape()
+                    .map_err(|e| AppError::Unavailable(format!("Malformed response from the S3 server: {}", e)))?;
+                if let Some(record) = current.as_mut() {
+                    record.entry(field.clone()).or_default().push_str(&text);
+                }
+            }
+            Ok(Event::Eof) => return Ok(records),
+            Ok(_) => {}
+            Err(e) => return Err(AppError::Unavailable(format!("Malformed response from the S3 server: {}", e))),
+        }
+    }
+}
+
+// Percent-encode everything but unreserved characters, and slashes unless
+// `encode_slash`, the way Signature Version 4 expects
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
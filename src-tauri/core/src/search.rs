@@ -0,0 +1,126 @@
+use crate::error::{AppResult, Context};
+use crate::html::{self, MATCH_END, MATCH_START};
+use crate::{NoteStore, SearchFilters, SearchResult};
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+impl NoteStore {
+    pub async fn search_notes(&self, query: &str, _filters: Option<&SearchFilters>) -> AppResult<Vec<SearchResult>> {
+        if query.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        // For now, use a simpler approach with basic search (without complex filtering)
+        let search_results = sqlx::query_as::<_, (String, String, String, DateTime<Utc>, String, f64)>(
+            r#"
+            SELECT n.id, n.title, n.content, n.updated_at,
+                   snippet(notes_fts, 1, ?2, ?3, '...', 32) as excerpt,
+                   rank as relevance_score
+            FROM notes_fts
+            JOIN notes n ON notes_fts.rowid = n.rowid
+            WHERE notes_fts MATCH ?1 AND n.is_archived = FALSE
+            ORDER BY rank
+            LIMIT 50
+            "#
+        )
+        .bind(query)
+        // Matches are marked with placeholders, which only become tags once
+        // the note text around them is escaped
+        .bind(MATCH_START.to_string())
+        .bind(MATCH_END.to_string())
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to search notes")?;
+
+        let mut results: Vec<SearchResult> = search_results
+            .into_iter()
+            .map(|(id, title, content, updated_at, excerpt, relevance_score)| {
+                // Extract highlights from the content
+                let highlights = extract_highlights(&content, query);
+
+                SearchResult {
+                    note_id: id,
+                    title,
+                    excerpt: html::highlight(&excerpt),
+                    highlights,
+                    relevance_score,
+                    last_modified: updated_at,
+                    matched_attachment: None,
+                }
+            })
+            .collect();
+
+        // Notes can also match through the text of their attachments
+        let attachment_results = sqlx::query_as::<_, (String, String, DateTime<Utc>, String, String, f64)>(
+            r#"
+            SELECT n.id, n.title, n.updated_at, a.original_name,
+                   snippet(attachments_fts, -1, ?2, ?3, '...', 32) as excerpt,
+                   attachments_fts.rank as relevance_score
+            FROM attachments_fts
+            JOIN attachments a ON a.id = attachments_fts.attachment_id
+            JOIN note_attachments na ON na.attachment_id = a.id
+            JOIN notes n ON n.id = na.note_id
+            WHERE attachments_fts MATCH ?1 AND n.is_archived = FALSE
+            ORDER BY attachments_fts.rank
+            LIMIT 50
+            "#
+        )
+        .bind(query)
+        .bind(MATCH_START.to_string())
+        .bind(MATCH_END.to_string())
+        .fetch_all(self.pool())
+        .await
+        .context("Failed to search attachments")?;
+
+        for (id, title, updated_at, attachment_name, excerpt, relevance_score) in attachment_results {
+            match results.iter_mut().find(|result| result.note_id == id) {
+                // Rows arrive best match first, so keep the first attachment seen
+                Some(existing) => {
+                    existing.matched_attachment.get_or_insert(attachment_name);
+                }
+                None => results.push(SearchResult {
+                    note_id: id,
+                    title,
+                    excerpt: html::highlight(&excerpt),
+                    highlights: vec![],
+                    relevance_score,
+                    last_modified: updated_at,
+                    matched_attachment: Some(attachment_name),
+                }),
+            }
+        }
+
+        // FTS5 ranks are negative bm25 scores, lower is better
+        results.sort_by(|a, b| a.relevance_score.total_cmp(&b.relevance_score));
+        results.truncate(50);
+
+        Ok(results)
+    }
+}
+
+fn extract_highlights(content: &str, query: &str) -> Vec<String> {
+    let query_terms: Vec<&str> = query.split_whitespace().collect();
+    let mut highlights = Vec::new();
+
+    for term in query_terms {
+        // Matched in the original text, so the offsets fit it even where
+        // lowercasing would change its length
+        let Ok(pattern) = Regex::new(&format!("(?i){}", regex::escape(term))) else {
+            continue;
+        };
+        if let Some(found) = pattern.find(content) {
+            // Find context around the term, on character boundaries
+            let mut start = found.start().saturating_sub(30);
+            while !content.is_char_boundary(start) {
+                start -= 1;
+            }
+            let mut end = (found.end() + 30).min(content.len());
+            while !content.is_char_boundary(end) {
+                end += 1;
+            }
+            highlights.push(format!("...{}...", &content[start..end]));
+        }
+    }
+
+    highlights
+}
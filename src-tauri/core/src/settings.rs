@@ -0,0 +1,556 @@
+use crate::error::{AppError, AppResult, Context};
+use chrono::Utc;
+use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Keys in the `settings` table
+pub const MAX_IMAGE_SIZE: &str = "uploads.max_image_size";
+pub const MAX_ATTACHMENT_SIZE: &str = "uploads.max_attachment_size";
+pub const BACKUP_ENABLED: &str = "backup.enabled";
+pub const BACKUP_INTERVAL_HOURS: &str = "backup.interval_hours";
+pub const BACKUP_KEEP_DAILY: &str = "backup.keep_daily";
+pub const BACKUP_KEEP_WEEKLY: &str = "backup.keep_weekly";
+pub const BACKUP_KEEP_MONTHLY: &str = "backup.keep_monthly";
+pub const HTTP_API_ENABLED: &str = "http_api.enabled";
+pub const HTTP_API_PORT: &str = "http_api.port";
+pub const HTTP_API_TOKEN: &str = "http_api.token";
+pub const MIRROR_ENABLED: &str = "mirror.enabled";
+pub const MIRROR_PATH: &str = "mirror.path";
+pub const MIRROR_ALLOW_UNENCRYPTED: &str = "mirror.allow_unencrypted";
+pub const GIT_ENABLED: &str = "git.enabled";
+pub const GIT_PATH: &str = "git.path";
+pub const GIT_REMOTE: &str = "git.remote";
+pub const GIT_BRANCH: &str = "git.branch";
+pub const GIT_INTERVAL_MINUTES: &str = "git.interval_minutes";
+pub const GIT_ALLOW_UNENCRYPTED: &str = "git.allow_unencrypted";
+// When the scheduler last synced, RFC 3339
+pub const GIT_LAST_SYNC: &str = "git.last_sync";
+pub const WEBDAV_ENABLED: &str = "webdav.enabled";
+pub const WEBDAV_URL: &str = "webdav.url";
+pub const WEBDAV_USERNAME: &str = "webdav.username";
+pub const WEBDAV_PASSWORD: &str = "webdav.password";
+pub const WEBDAV_INTERVAL_MINUTES: &str = "webdav.interval_minutes";
+pub const WEBDAV_DEVICE_NAME: &str = "webdav.device_name";
+pub const WEBDAV_ALLOW_UNENCRYPTED: &str = "webdav.allow_unencrypted";
+// When the scheduler last synced, RFC 3339
+pub const WEBDAV_LAST_SYNC: &str = "webdav.last_sync";
+pub const COLLAB_ENABLED: &str = "collab.enabled";
+pub const COLLAB_PORT: &str = "collab.port";
+pub const COLLAB_TOKEN: &str = "collab.token";
+pub const S3_ENABLED: &str = "s3.enabled";
+pub const S3_ENDPOINT: &str = "s3.endpoint";
+pub const S3_REGION: &str = "s3.region";
+pub const S3_BUCKET: &str = "s3.bucket";
+pub const S3_PREFIX: &str = "s3.prefix";
+pub const S3_PATH_STYLE: &str = "s3.path_style";
+pub const S3_ACCESS_KEY_ID: &str = "s3.access_key_id";
+pub const S3_SECRET_ACCESS_KEY: &str = "s3.secret_access_key";
+pub const S3_PASSPHRASE: &str = "s3.passphrase";
+pub const S3_KEEP_DAILY: &str = "s3.keep_daily";
+pub const S3_KEEP_WEEKLY: &str = "s3.keep_weekly";
+pub const S3_KEEP_MONTHLY: &str = "s3.keep_monthly";
+// Argon2 hashes, in PHC string format
+pub const LOCK_PASSWORD_HASH: &str = "lock.password_hash";
+pub const LOCK_RECOVERY_HASH: &str = "lock.recovery_hash";
+pub const LOCK_TIMEOUT_MINUTES: &str = "lock.timeout_minutes";
+// Only ever stored inside an encrypted database: the key image files are
+// encrypted with, base64, and a marker for images still left in plain form
+pub const VAULT_IMAGE_KEY: &str = "vault.image_key";
+pub const VAULT_IMAGES_PENDING: &str = "vault.images_pending";
+
+pub const DEFAULT_MAX_IMAGE_SIZE: u64 = 20 * 1024 * 1024;
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: u64 = 200 * 1024 * 1024;
+pub const DEFAULT_HTTP_API_PORT: u16 = 27450;
+pub const DEFAULT_COLLAB_PORT: u16 = 27451;
+
+pub async fn get_setting(pool: &SqlitePool, key: &str) -> AppResult<Option<String>> {
+    let value: Option<(String,)> = sqlx::query_as("SELECT value FROM settings WHERE key = ?1")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .with_context(|| format!("Failed to read setting {}", key))?;
+
+    Ok(value.map(|(value,)| value))
+}
+
+pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)
+        ON CONFLICT (key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(key)
+    .bind(value)
+    .bind(Utc::now())
+    .execute(pool)
+    .await
+    .with_context(|| format!("Failed to save setting {}", key))?;
+
+    Ok(())
+}
+
+pub async fn remove_setting(pool: &SqlitePool, key: &str) -> AppResult<()> {
+    sqlx::query("DELETE FROM settings WHERE key = ?1")
+        .bind(key)
+        .execute(pool)
+        .await
+        .with_context(|| format!("Failed to remove setting {}", key))?;
+
+    Ok(())
+}
+
+// Unparseable values fall back to the default rather than failing the caller
+async fn get_parsed<T: FromStr>(pool: &SqlitePool, key: &str, default: T) -> AppResult<T> {
+    Ok(get_setting(pool, key)
+        .await?
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSettings {
+    pub max_image_size: u64,
+    pub max_attachment_size: u64,
+}
+
+impl UploadSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        Ok(UploadSettings {
+            max_image_size: get_parsed(pool, MAX_IMAGE_SIZE, DEFAULT_MAX_IMAGE_SIZE).await?,
+            max_attachment_size: get_parsed(pool, MAX_ATTACHMENT_SIZE, DEFAULT_MAX_ATTACHMENT_SIZE).await?,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        if self.max_image_size == 0 || self.max_attachment_size == 0 {
+            return Err(AppError::Validation("Maximum upload sizes must be greater than zero".to_string()));
+        }
+
+        set_setting(pool, MAX_IMAGE_SIZE, &self.max_image_size.to_string()).await?;
+        set_setting(pool, MAX_ATTACHMENT_SIZE, &self.max_attachment_size.to_string()).await
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupSettings {
+    pub enabled: bool,
+    pub interval_hours: u64,
+    // How many of the most recent days, weeks and months keep a backup
+    pub keep_daily: u64,
+    pub keep_weekly: u64,
+    pub keep_monthly: u64,
+}
+
+impl BackupSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        Ok(BackupSettings {
+            enabled: get_parsed(pool, BACKUP_ENABLED, true).await?,
+            interval_hours: get_parsed(pool, BACKUP_INTERVAL_HOURS, 24).await?,
+            keep_daily: get_parsed(pool, BACKUP_KEEP_DAILY, 7).await?,
+            keep_weekly: get_parsed(pool, BACKUP_KEEP_WEEKLY, 4).await?,
+            keep_monthly: get_parsed(pool, BACKUP_KEEP_MONTHLY, 6).await?,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        if self.interval_hours == 0 {
+            return Err(AppError::Validation("Backup interval must be at least one hour".to_string()));
+        }
+
+        set_setting(pool, BACKUP_ENABLED, &self.enabled.to_string()).await?;
+        set_setting(pool, BACKUP_INTERVAL_HOURS, &self.interval_hours.to_string()).await?;
+        set_setting(pool, BACKUP_KEEP_DAILY, &self.keep_daily.to_string()).await?;
+        set_setting(pool, BACKUP_KEEP_WEEKLY, &self.keep_weekly.to_string()).await?;
+        set_setting(pool, BACKUP_KEEP_MONTHLY, &self.keep_monthly.to_string()).await
+    }
+
+    pub fn retention(&self) -> Retention {
+        Retention {
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+        }
+    }
+}
+
+// What `backup::backups_to_prune` keeps, for local and remote backups
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub keep_daily: u64,
+    pub keep_weekly: u64,
+    pub keep_monthly: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    // Generated on first load; only `regenerate_token` changes it
+    #[serde(default)]
+    pub token: String,
+}
+
+impl HttpApiSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        let token = match get_setting(pool, HTTP_API_TOKEN).await? {
+            Some(token) if !token.is_empty() => token,
+            _ => Self::regenerate_token(pool).await?,
+        };
+
+        Ok(HttpApiSettings {
+            enabled: get_parsed(pool, HTTP_API_ENABLED, false).await?,
+            port: get_parsed(pool, HTTP_API_PORT, DEFAULT_HTTP_API_PORT).await?,
+            token,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        if self.port < 1024 {
+            return Err(AppError::Validation("The API port must be 1024 or higher".to_string()));
+        }
+
+        set_setting(pool, HTTP_API_ENABLED, &self.enabled.to_string()).await?;
+        set_setting(pool, HTTP_API_PORT, &self.port.to_string()).await
+    }
+
+    // Replace the token, locking out every client that has the old one
+    pub async fn regenerate_token(pool: &SqlitePool) -> AppResult<String> {
+        let token = new_token();
+        set_setting(pool, HTTP_API_TOKEN, &token).await?;
+        Ok(token)
+    }
+}
+
+fn new_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorSettings {
+    pub enabled: bool,
+    // Empty for `mirror/` in the data directory
+    pub path: String,
+    // Copy notes out in plain text even when the vault is encrypted
+    #[serde(default)]
+    pub allow_unencrypted: bool,
+}
+
+impl MirrorSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        Ok(MirrorSettings {
+            enabled: get_parsed(pool, MIRROR_ENABLED, false).await?,
+            path: get_setting(pool, MIRROR_PATH).await?.unwrap_or_default(),
+            allow_unencrypted: get_parsed(pool, MIRROR_ALLOW_UNENCRYPTED, false).await?,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        if !self.path.is_empty() && !Path::new(&self.path).is_absolute() {
+            return Err(AppError::Validation("The mirror folder must be an absolute path".to_string()));
+        }
+
+        set_setting(pool, MIRROR_ENABLED, &self.enabled.to_string()).await?;
+        set_setting(pool, MIRROR_PATH, &self.path).await?;
+        set_setting(pool, MIRROR_ALLOW_UNENCRYPTED, &self.allow_unencrypted.to_string()).await
+    }
+
+    pub fn folder(&self, data_dir: &Path) -> PathBuf {
+        if self.path.is_empty() {
+            data_dir.join("mirror")
+        } else {
+            PathBuf::from(&self.path)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitSyncSettings {
+    // Sync on a schedule; syncing on demand works either way
+    pub enabled: bool,
+    // Empty for `git/` in the data directory
+    pub path: String,
+    // URL or path of the repository to pull from and push to, empty for none
+    pub remote: String,
+    pub branch: String,
+    pub interval_minutes: u64,
+    // Copy notes out in plain text even when the vault is encrypted
+    #[serde(default)]
+    pub allow_unencrypted: bool,
+}
+
+impl GitSyncSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        Ok(GitSyncSettings {
+            enabled: get_parsed(pool, GIT_ENABLED, false).await?,
+            path: get_setting(pool, GIT_PATH).await?.unwrap_or_default(),
+            remote: get_setting(pool, GIT_REMOTE).await?.unwrap_or_default(),
+            branch: get_setting(pool, GIT_BRANCH).await?.filter(|branch| !branch.is_empty()).unwrap_or_else(|| "main".to_string()),
+            interval_minutes: get_parsed(pool, GIT_INTERVAL_MINUTES, 60).await?,
+            allow_unencrypted: get_parsed(pool, GIT_ALLOW_UNENCRYPTED, false).await?,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        if !self.path.is_empty() && !Path::new(&self.path).is_absolute() {
+            return Err(AppError::Validation("The git repository must be an absolute path".to_string()));
+        }
+        if self.interval_minutes == 0 {
+            return Err(AppError::Validation("The sync interval must be at least one minute".to_string()));
+        }
+        if self.branch.is_empty() || self.branch.starts_with('-') || self.branch.contains(char::is_whitespace) {
+            return Err(AppError::Validation(format!("Invalid branch name: {}", self.branch)));
+        }
+        if self.remote.starts_with('-') {
+            return Err(AppError::Validation(format!("Invalid remote: {}", self.remote)));
+        }
+
+        set_setting(pool, GIT_ENABLED, &self.enabled.to_string()).await?;
+        set_setting(pool, GIT_PATH, &self.path).await?;
+        set_setting(pool, GIT_REMOTE, &self.remote).await?;
+        set_setting(pool, GIT_BRANCH, &self.branch).await?;
+        set_setting(pool, GIT_INTERVAL_MINUTES, &self.interval_minutes.to_string()).await?;
+        set_setting(pool, GIT_ALLOW_UNENCRYPTED, &self.allow_unencrypted.to_string()).await
+    }
+
+    pub fn repository(&self, data_dir: &Path) -> PathBuf {
+        if self.path.is_empty() {
+            data_dir.join("git")
+        } else {
+            PathBuf::from(&self.path)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebDavSettings {
+    // Sync on a schedule; syncing on demand works either way
+    pub enabled: bool,
+    // The WebDAV folder to sync through, e.g. a Nextcloud folder's URL
+    pub url: String,
+    pub username: String,
+    // Kept in the settings table like everything else, so an app password
+    // is better than the account's own where the server offers one
+    pub password: String,
+    pub interval_minutes: u64,
+    // Shown in the titles of conflict copies; empty for the host name
+    pub device_name: String,
+    // Copy notes out in plain text even when the vault is encrypted
+    #[serde(default)]
+    pub allow_unencrypted: bool,
+}
+
+impl WebDavSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        Ok(WebDavSettings {
+            enabled: get_parsed(pool, WEBDAV_ENABLED, false).await?,
+            url: get_setting(pool, WEBDAV_URL).await?.unwrap_or_default(),
+            username: get_setting(pool, WEBDAV_USERNAME).await?.unwrap_or_default(),
+            password: get_setting(pool, WEBDAV_PASSWORD).await?.unwrap_or_default(),
+            interval_minutes: get_parsed(pool, WEBDAV_INTERVAL_MINUTES, 15).await?,
+            device_name: get_setting(pool, WEBDAV_DEVICE_NAME).await?.unwrap_or_default(),
+            allow_unencrypted: get_parsed(pool, WEBDAV_ALLOW_UNENCRYPTED, false).await?,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        let url = self.url.trim();
+        if !url.is_empty() && !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(AppError::Validation("The WebDAV address must start with https:// or http://".to_string()));
+        }
+        if self.enabled && url.is_empty() {
+            return Err(AppError::Validation("Enter the WebDAV folder's address to sync on a schedule".to_string()));
+        }
+        if self.interval_minutes == 0 {
+            return Err(AppError::Validation("The sync interval must be at least one minute".to_string()));
+        }
+
+        set_setting(pool, WEBDAV_ENABLED, &self.enabled.to_string()).await?;
+        set_setting(pool, WEBDAV_URL, url).await?;
+        set_setting(pool, WEBDAV_USERNAME, &self.username).await?;
+        set_setting(pool, WEBDAV_PASSWORD, &self.password).await?;
+        set_setting(pool, WEBDAV_INTERVAL_MINUTES, &self.interval_minutes.to_string()).await?;
+        set_setting(pool, WEBDAV_DEVICE_NAME, self.device_name.trim()).await?;
+        set_setting(pool, WEBDAV_ALLOW_UNENCRYPTED, &self.allow_unencrypted.to_string()).await
+    }
+
+    pub fn device(&self) -> String {
+        if !self.device_name.trim().is_empty() {
+            return self.device_name.trim().to_string();
+        }
+        std::env::var("HOSTNAME")
+            .or_else(|_| std::env::var("COMPUTERNAME"))
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "another device".to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollabSettings {
+    // Accept collaborators on this device; joining someone else's note works
+    // either way
+    pub enabled: bool,
+    pub port: u16,
+    // Collaborators need it to join; generated on first load, only
+    // `regenerate_token` changes it
+    #[serde(default)]
+    pub token: String,
+}
+
+impl CollabSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        let token = match get_setting(pool, COLLAB_TOKEN).await? {
+            Some(token) if !token.is_empty() => token,
+            _ => Self::regenerate_token(pool).await?,
+        };
+
+        Ok(CollabSettings {
+            enabled: get_parsed(pool, COLLAB_ENABLED, false).await?,
+            port: get_parsed(pool, COLLAB_PORT, DEFAULT_COLLAB_PORT).await?,
+            token,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        if self.port < 1024 {
+            return Err(AppError::Validation("The collaboration port must be 1024 or higher".to_string()));
+        }
+
+        set_setting(pool, COLLAB_ENABLED, &self.enabled.to_string()).await?;
+        set_setting(pool, COLLAB_PORT, &self.port.to_string()).await
+    }
+
+    // Replace the token; collaborators need the new one to join again
+    pub async fn regenerate_token(pool: &SqlitePool) -> AppResult<String> {
+        let token = new_token();
+        set_setting(pool, COLLAB_TOKEN, &token).await?;
+        Ok(token)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3BackupSettings {
+    // Upload scheduled backups; uploading on demand works either way
+    pub enabled: bool,
+    // e.g. https://s3.eu-central-1.amazonaws.com or http://localhost:9000
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    // Prepended to object names, e.g. `notura/`
+    pub prefix: String,
+    // Address the bucket as <endpoint>/<bucket> rather than
+    // <bucket>.<endpoint>; MinIO and most self-hosted servers need it
+    pub path_style: bool,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // Backups are encrypted with it before they leave the machine; without
+    // it they cannot be restored
+    pub passphrase: String,
+    // Retention in the bucket, like the local one
+    pub keep_daily: u64,
+    pub keep_weekly: u64,
+    pub keep_monthly: u64,
+}
+
+impl S3BackupSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        Ok(S3BackupSettings {
+            enabled: get_parsed(pool, S3_ENABLED, false).await?,
+            endpoint: get_setting(pool, S3_ENDPOINT).await?.unwrap_or_default(),
+            region: get_setting(pool, S3_REGION).await?.filter(|region| !region.is_empty()).unwrap_or_else(|| "us-east-1".to_string()),
+            bucket: get_setting(pool, S3_BUCKET).await?.unwrap_or_default(),
+            prefix: get_setting(pool, S3_PREFIX).await?.unwrap_or_default(),
+            path_style: get_parsed(pool, S3_PATH_STYLE, true).await?,
+            access_key_id: get_setting(pool, S3_ACCESS_KEY_ID).await?.unwrap_or_default(),
+            secret_access_key: get_setting(pool, S3_SECRET_ACCESS_KEY).await?.unwrap_or_default(),
+            passphrase: get_setting(pool, S3_PASSPHRASE).await?.unwrap_or_default(),
+            keep_daily: get_parsed(pool, S3_KEEP_DAILY, 7).await?,
+            keep_weekly: get_parsed(pool, S3_KEEP_WEEKLY, 4).await?,
+            keep_monthly: get_parsed(pool, S3_KEEP_MONTHLY, 12).await?,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        let endpoint = self.endpoint.trim();
+        if !endpoint.is_empty() && !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+            return Err(AppError::Validation("The S3 endpoint must start with https:// or http://".to_string()));
+        }
+        if self.enabled {
+            self.check_complete()?;
+        }
+        if self.prefix.starts_with('/') {
+            return Err(AppError::Validation("The S3 prefix must not start with /".to_string()));
+        }
+
+        set_setting(pool, S3_ENABLED, &self.enabled.to_string()).await?;
+        set_setting(pool, S3_ENDPOINT, endpoint).await?;
+        set_setting(pool, S3_REGION, self.region.trim()).await?;
+        set_setting(pool, S3_BUCKET, self.bucket.trim()).await?;
+        set_setting(pool, S3_PREFIX, self.prefix.trim()).await?;
+        set_setting(pool, S3_PATH_STYLE, &self.path_style.to_string()).await?;
+        set_setting(pool, S3_ACCESS_KEY_ID, self.access_key_id.trim()).await?;
+        set_setting(pool, S3_SECRET_ACCESS_KEY, &self.secret_access_key).await?;
+        set_setting(pool, S3_PASSPHRASE, &self.passphrase).await?;
+        set_setting(pool, S3_KEEP_DAILY, &self.keep_daily.to_string()).await?;
+        set_setting(pool, S3_KEEP_WEEKLY, &self.keep_weekly.to_string()).await?;
+        set_setting(pool, S3_KEEP_MONTHLY, &self.keep_monthly.to_string()).await
+    }
+
+    // Everything an upload needs is filled in
+    pub fn check_complete(&self) -> AppResult<()> {
+        let missing = [
+            (self.endpoint.trim(), "endpoint"),
+            (self.bucket.trim(), "bucket"),
+            (self.access_key_id.trim(), "access key id"),
+            (self.secret_access_key.as_str(), "secret access key"),
+            (self.passphrase.as_str(), "encryption passphrase"),
+        ]
+        .into_iter()
+        .filter(|(value, _)| value.is_empty())
+        .map(|(_, name)| name)
+        .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(format!("Remote backups need the S3 {}", missing.join(", "))))
+        }
+    }
+
+    pub fn retention(&self) -> Retention {
+        Retention {
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockSettings {
+    // Lock after this long without activity, 0 for only on startup; the
+    // password itself is set through `app_lock`
+    pub timeout_minutes: u64,
+}
+
+impl LockSettings {
+    pub async fn load(pool: &SqlitePool) -> AppResult<Self> {
+        Ok(LockSettings {
+            timeout_minutes: get_parsed(pool, LOCK_TIMEOUT_MINUTES, 10).await?,
+        })
+    }
+
+    pub async fn save(&self, pool: &SqlitePool) -> AppResult<()> {
+        set_setting(pool, LOCK_TIMEOUT_MINUTES, &self.timeout_minutes.to_string()).await
+    }
+
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        (self.timeout_minutes > 0).then(|| std::time::Duration::from_secs(self.timeout_minutes * 60))
+    }
+}
@@ -0,0 +1,111 @@
+// Deletion that leaves nothing behind in the data directory. Every connection
+// runs with SQLite's secure_delete, so deleted rows are overwritten in the
+// database pages as they go. A plain delete still leaves the old pages in the
+// WAL and the deleted entries in the search index's segments until the next
+// merge; shredding merges the segments and truncates the WAL, and overwrites
+// files before unlinking them. Copies elsewhere (backups, the Markdown
+// mirror, git history, other devices) are not touched.
+use crate::error::{AppError, AppResult, Context};
+use crate::{ImageMetadata, NoteStore, ShredResult};
+use sqlx::SqlitePool;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+impl NoteStore {
+    // Delete the note and scrub its text out of the database files
+    pub async fn shred_note(&self, id: &str) -> AppResult<ShredResult> {
+        self.delete_note(id).await?;
+        let mut result = ShredResult::default();
+        scrub(self.pool(), &mut result).await?;
+        Ok(result)
+    }
+
+    // Delete the image's record, then overwrite and unlink its file. The file
+    // goes only once the delete is committed, so a failed delete never leaves
+    // a record pointing at a zeroed file.
+    pub async fn shred_image(&self, id: &str) -> AppResult<ShredResult> {
+        let mut tx = self.pool().begin_with("BEGIN IMMEDIATE").await.context("Failed to delete image")?;
+        let image = sqlx::query_as::<_, ImageMetadata>("DELETE FROM images WHERE id = ?1 RETURNING *")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to delete image from database")?
+            .ok_or_else(|| AppError::not_found("image", id))?;
+        tx.commit().await.context("Failed to delete image from database")?;
+
+        let mut result = ShredResult::default();
+        if let Err(e) = shred_file(Path::new(&image.file_path)) {
+            result.warnings.push(format!("Deleted, but the image file {} could not be shredded: {}", image.file_path, e));
+        }
+        scrub(self.pool(), &mut result).await?;
+        Ok(result)
+    }
+}
+
+// Overwrite a file with zeros, flush it to disk and unlink it. A missing file
+// counts as done. On SSDs and copy-on-write file systems the old blocks may
+// survive anyway; the encrypted vault is the answer there.
+pub(crate) fn shred_file(path: &Path) -> io::Result<()> {
+    let mut file = match OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let zeros = [0u8; 64 * 1024];
+    let mut remaining = file.metadata()?.len();
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        remaining -= chunk as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+
+    fs::remove_file(path)
+}
+
+// `shred_file` for everything below a directory, which is then removed. A
+// missing directory counts as done.
+pub(crate) fn shred_dir(dir: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            shred_dir(&entry.path())?;
+        } else {
+            shred_file(&entry.path())?;
+        }
+    }
+    fs::remove_dir(dir)
+}
+
+// Clear what a delete leaves in the index segments and the WAL. No VACUUM:
+// it would make every other pooled connection fail its next delete from
+// `notes`, and secure_delete already zeroed the freed pages.
+async fn scrub(pool: &SqlitePool, result: &mut ShredResult) -> AppResult<()> {
+    for table in ["notes_fts", "attachments_fts"] {
+        sqlx::query(&format!("INSERT INTO {table}({table}) VALUES ('optimize')"))
+            .execute(pool)
+            .await
+            .context("Failed to merge the search index")?;
+    }
+
+    // A busy result means readers kept the WAL from being cleared; it is then
+    // only overwritten as the database is used
+    let (busy,): (i64,) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+        .fetch_one(pool)
+        .await
+        .context("Failed to checkpoint database")?;
+    if busy != 0 {
+        result
+            .warnings
+            .push("Deleted, but the database was busy and its write-ahead log could not be cleared yet".to_string());
+    }
+    Ok(())
+}
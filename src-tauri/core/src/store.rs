@@ -0,0 +1,123 @@
+use crate::error::{AppResult, Context};
+use crate::crypto::PassphraseKey;
+use crate::database::{self, DatabaseResult};
+use crate::documents::Documents;
+use crate::vault::{self, ImageKey};
+use crate::{backup, recovery, StorageInfo};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Tauri keeps app data in a directory named after the bundle identifier
+pub const APP_IDENTIFIER: &str = "com.lusan.notura";
+
+// Where the desktop app keeps its data, so other front ends open the same vault
+pub fn default_data_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER))
+}
+
+// Repository over the notes database and the files kept next to it. Images
+// and attachments live in `images/` and `attachments/` under `data_dir`.
+#[derive(Clone)]
+pub struct NoteStore {
+    pool: SqlitePool,
+    data_dir: PathBuf,
+    documents: Documents,
+    // Set for an encrypted vault
+    image_key: Option<ImageKey>,
+    // Keys of the encrypted notes unlocked this session, by note id. Never the
+    // passwords; the keys are wiped when the notes are locked.
+    unlocked_notes: Arc<Mutex<HashMap<String, PassphraseKey>>>,
+}
+
+impl NoteStore {
+    // Wrap an already migrated pool
+    pub fn new(pool: SqlitePool, data_dir: impl Into<PathBuf>) -> Self {
+        NoteStore {
+            pool,
+            data_dir: data_dir.into(),
+            documents: Documents::new(),
+            image_key: None,
+            unlocked_notes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Open the database in `data_dir`, creating it if needed
+    pub async fn open(data_dir: impl Into<PathBuf>) -> DatabaseResult<Self> {
+        let data_dir = data_dir.into();
+        let pool = database::open_database(&data_dir).await?;
+        Ok(NoteStore::new(pool, data_dir))
+    }
+
+    // Open an encrypted vault with its passphrase, creating it encrypted if
+    // `data_dir` has no database yet
+    pub async fn open_encrypted(data_dir: impl Into<PathBuf>, passphrase: &str) -> AppResult<Self> {
+        let data_dir = data_dir.into();
+        let pool = database::open_database_with_key(&data_dir, Some(passphrase)).await?;
+        let mut store = NoteStore::new(pool, data_dir);
+        store.image_key = Some(vault::load_image_key(&store.pool).await?);
+        vault::seal_pending_images(&store).await?;
+        Ok(store)
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub(crate) fn documents(&self) -> &Documents {
+        &self.documents
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.image_key.is_some()
+    }
+
+    pub(crate) fn image_key(&self) -> Option<&ImageKey> {
+        self.image_key.as_ref()
+    }
+
+    // The same store with no notes unlocked, for front ends other than the
+    // app's own window: notes unlocked in the app stay locked through them
+    pub fn without_unlocked_notes(&self) -> NoteStore {
+        NoteStore { unlocked_notes: Arc::default(), ..self.clone() }
+    }
+
+    pub(crate) fn unlocked_notes(&self) -> std::sync::MutexGuard<'_, HashMap<String, PassphraseKey>> {
+        self.unlocked_notes.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    pub async fn storage_info(&self) -> AppResult<StorageInfo> {
+        let notes_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM notes")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count notes")?;
+
+        let collections_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM collections")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count collections")?;
+
+        // Get database file size
+        let database_size = std::fs::metadata(recovery::database_path(&self.data_dir))
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        let last_backup = backup::last_backup_time(&self.data_dir)?;
+
+        Ok(StorageInfo {
+            total_notes: notes_count.0 as u32,
+            total_collections: collections_count.0 as u32,
+            database_size,
+            last_backup: last_backup.map(|time| time.to_rfc3339()),
+        })
+    }
+}
@@ -0,0 +1,37 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+pub mod test_utils {
+    use crate::database::{run_migrations, DatabaseResult};
+    use crate::NoteStore;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::SqlitePool;
+    use tempfile::TempDir;
+
+    pub async fn create_test_database() -> DatabaseResult<SqlitePool> {
+        // A private in-memory database lives exactly as long as its connection,
+        // so the pool is pinned to a single connection that is never recycled
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+
+        // The same schema, triggers included, as the real database
+        run_migrations(&pool).await?;
+
+        Ok(pool)
+    }
+
+    // A store over an in-memory database, with files going to a temporary
+    // directory that is removed when the returned `TempDir` is dropped
+    pub async fn create_test_store() -> DatabaseResult<(NoteStore, TempDir)> {
+        let pool = create_test_database().await?;
+        let dir = TempDir::new().map_err(sqlx::Error::Io)?;
+        Ok((NoteStore::new(pool, dir.path()), dir))
+    }
+
+    pub async fn cleanup_test_database(pool: SqlitePool) {
+        pool.close().await;
+    }
+}
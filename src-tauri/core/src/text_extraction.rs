@@ -0,0 +1,104 @@
+use crate::error::{AppError, AppResult, Context};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+// Cap on indexed text per attachment, long enough for books but bounded
+const MAX_EXTRACTED_CHARS: usize = 2_000_000;
+
+const DOCX_MIME_TYPE: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+
+// Plain-text formats whose MIME type doesn't start with text/
+const TEXT_LIKE_MIME_TYPES: &[&str] = &[
+    "application/json",
+    "application/xml",
+    "application/javascript",
+    "application/x-sh",
+    "application/toml",
+    "application/yaml",
+    "application/x-yaml",
+];
+
+// Extract searchable text from an attachment file. Returns None for formats we
+// don't know how to read, so callers can skip them without treating it as an error.
+pub fn extract_text(path: &Path, mime_type: &str) -> AppResult<Option<String>> {
+    let text = if mime_type == "application/pdf" {
+        Some(extract_pdf(path)?)
+    } else if mime_type == DOCX_MIME_TYPE {
+        Some(extract_docx(path)?)
+    } else if mime_type.starts_with("text/") || TEXT_LIKE_MIME_TYPES.contains(&mime_type) {
+        let bytes = fs::read(path).context("Failed to read attachment")?;
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    } else {
+        None
+    };
+
+    Ok(text.map(|text| truncate_chars(text.replace('\0', ""), MAX_EXTRACTED_CHARS)))
+}
+
+fn extract_pdf(path: &Path) -> AppResult<String> {
+    let bytes = fs::read(path).context("Failed to read PDF")?;
+
+    // The PDF parser panics on some malformed files instead of returning an error
+    std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes))
+        .map_err(|_| AppError::Validation("PDF text extraction failed on a malformed document".to_string()))?
+        .map_err(|e| AppError::Validation(format!("Failed to extract PDF text: {}", e)))
+}
+
+// DOCX is a zip archive; the body text lives in the <w:t> runs of word/document.xml
+fn extract_docx(path: &Path) -> AppResult<String> {
+    let file = fs::File::open(path).context("Failed to read DOCX")?;
+    let mut archive = zip::ZipArchive::new(file)
+        .context("Failed to open DOCX archive")?;
+
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .context("DOCX has no document body")?
+        .read_to_string(&mut xml)
+        .context("Failed to read DOCX body")?;
+
+    docx_xml_to_text(&xml)
+}
+
+fn docx_xml_to_text(xml: &str) -> AppResult<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut text = String::new();
+    let mut in_text_run = false;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(element)) if element.name().as_ref() == b"w:t" => in_text_run = true,
+            Ok(Event::End(element)) => match element.name().as_ref() {
+                b"w:t" => in_text_run = false,
+                b"w:p" => text.push('\n'),
+                _ => {}
+            },
+            Ok(Event::Empty(element)) => match element.name().as_ref() {
+                b"w:tab" => text.push('\t'),
+                b"w:br" => text.push('\n'),
+                _ => {}
+            },
+            Ok(Event::Text(content)) if in_text_run => {
+                let content = content
+                    .unescape()
+                    .map_err(|e| AppError::Validation(format!("Failed to decode DOCX text: {}", e)))?;
+                text.push_str(&content);
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(AppError::Validation(format!("Failed to parse DOCX body: {}", e))),
+        }
+    }
+
+    Ok(text)
+}
+
+fn truncate_chars(text: String, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => text[..index].to_string(),
+        None => text,
+    }
+}
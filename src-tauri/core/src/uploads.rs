@@ -0,0 +1,137 @@
+use crate::error::{AppError, AppResult};
+use image::ImageFormat;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Formats the webview can render. SVG is deliberately absent: it can't be
+// recognized from magic bytes and may carry script.
+const ALLOWED_IMAGE_FORMATS: &[ImageFormat] = &[
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::WebP,
+    ImageFormat::Bmp,
+    ImageFormat::Ico,
+    ImageFormat::Avif,
+];
+
+pub fn image_mime_type(format: ImageFormat) -> &'static str {
+    format.to_mime_type()
+}
+
+pub fn image_extension(format: ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("bin")
+}
+
+// Check an uploaded blob against the size limit and the caller's claims about
+// it. The format is taken from the content's magic bytes; the declared MIME
+// type and file extension only have to agree with it.
+pub fn validate_image_upload(
+    data: &[u8],
+    original_name: &str,
+    declared_mime_type: &str,
+    max_size: u64,
+) -> AppResult<ImageFormat> {
+    if data.is_empty() {
+        return Err(AppError::Validation("Image file is empty".to_string()));
+    }
+
+    if data.len() as u64 > max_size {
+        return Err(AppError::Validation(format!(
+            "Image is {} bytes, which exceeds the {} byte limit",
+            data.len(),
+            max_size
+        )));
+    }
+
+    let format = image::guess_format(data)
+        .map_err(|_| AppError::Validation("File content is not a recognized image format".to_string()))?;
+
+    if !ALLOWED_IMAGE_FORMATS.contains(&format) {
+        return Err(AppError::Validation(format!("Unsupported image format: {}", image_mime_type(format))));
+    }
+
+    let declared = normalize_mime_type(declared_mime_type);
+    if !declared.is_empty() && declared != "application/octet-stream" && declared != image_mime_type(format) {
+        return Err(AppError::Validation(format!(
+            "File content is {} but was declared as {}",
+            image_mime_type(format),
+            declared
+        )));
+    }
+
+    if let Some(extension) = Path::new(original_name).extension().and_then(|ext| ext.to_str()) {
+        let extension = extension.to_lowercase();
+        if !format.extensions_str().contains(&extension.as_str()) {
+            return Err(AppError::Validation(format!(
+                "File extension .{} does not match its {} content",
+                extension,
+                image_mime_type(format)
+            )));
+        }
+    }
+
+    Ok(format)
+}
+
+fn normalize_mime_type(mime_type: &str) -> String {
+    let essence = mime_type.split(';').next().unwrap_or("").trim().to_lowercase();
+
+    match essence.as_str() {
+        "image/jpg" | "image/pjpeg" => "image/jpeg".to_string(),
+        "image/x-icon" | "image/vnd.microsoft.icon" => "image/x-icon".to_string(),
+        "image/x-ms-bmp" => "image/bmp".to_string(),
+        _ => essence,
+    }
+}
+
+// Attachments accept any content, so the MIME type is informational: magic
+// bytes win, then the file extension, then a generic binary type.
+pub fn detect_mime_type(data: &[u8], original_name: &str) -> String {
+    let from_name = mime_guess::from_path(original_name).first_raw();
+
+    match infer::get(data).map(|kind| kind.mime_type()) {
+        // Office documents, EPUBs and the like are zip containers that aren't
+        // always recognized from the content; the extension names the real format
+        Some("application/zip") => from_name.unwrap_or("application/zip").to_string(),
+        Some(mime_type) => mime_type.to_string(),
+        None => from_name.unwrap_or("application/octet-stream").to_string(),
+    }
+}
+
+// Extension for the stored copy of an upload. The original name's extension
+// is kept when it is short and plain so the system app picks the right handler.
+pub fn storage_extension(data: &[u8], original_name: &str) -> String {
+    let from_name = Path::new(original_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| !ext.is_empty() && ext.len() <= 10 && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    match from_name {
+        Some(ext) => ext.to_lowercase(),
+        None => infer::get(data)
+            .map(|kind| kind.extension().to_string())
+            .unwrap_or_else(|| "bin".to_string()),
+    }
+}
+
+// Write to a temporary file in the target directory and rename it into place,
+// so readers never observe a partially written file.
+pub fn write_file_atomically(dir: &Path, filename: &str, data: &[u8]) -> io::Result<PathBuf> {
+    let final_path = dir.join(filename);
+    let temp_path = dir.join(format!(".{}.tmp", filename));
+
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, &final_path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result.map(|_| final_path)
+}
@@ -0,0 +1,489 @@
+// Opt-in encryption at rest. An encrypted vault's database is a SQLCipher
+// database keyed with the user's passphrase, so titles, content, the search
+// index and settings are unreadable without it, and once it is open
+// everything, search included, works as before. Image files are encrypted
+// with a random key kept inside that database, so changing the passphrase
+// only has to re-encrypt the database. Attachments stay in plain form, as do
+// exports, and the mirror and sync only copy notes out once allowed to.
+use crate::crypto::{self, KEY_LEN};
+use crate::error::{AppError, AppResult, Context};
+use crate::settings::{self, VAULT_IMAGES_PENDING, VAULT_IMAGE_KEY};
+use crate::{backup, recovery, shred, uploads, NoteStore};
+use base64::Engine;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool};
+use sqlx::Connection;
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+// Wrong guesses are slowed down on top of SQLCipher's key derivation
+const FAILED_ATTEMPT_DELAY: Duration = Duration::from_secs(1);
+
+// The key an encrypted vault's image files are encrypted with
+#[derive(Clone)]
+pub struct ImageKey([u8; KEY_LEN]);
+
+impl ImageKey {
+    fn generate() -> ImageKey {
+        let mut key = [0u8; KEY_LEN];
+        crypto::random_bytes(&mut key);
+        ImageKey(key)
+    }
+
+    fn parse(encoded: &str) -> AppResult<ImageKey> {
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|key| <[u8; KEY_LEN]>::try_from(key).ok())
+            .map(ImageKey)
+            .ok_or_else(|| AppError::Internal("The vault's image key is damaged".to_string()))
+    }
+
+    fn encode(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.0)
+    }
+
+    fn seal(&self, data: &[u8]) -> AppResult<Vec<u8>> {
+        let mut sealed = Vec::with_capacity(data.len() + 64);
+        crypto::encrypt_with_key(&mut &data[..], &mut sealed, &self.0)?;
+        Ok(sealed)
+    }
+
+    // Files from before the vault was encrypted are passed through
+    fn open(&self, data: Vec<u8>) -> AppResult<Vec<u8>> {
+        if !crypto::is_key_encrypted(&data) {
+            return Ok(data);
+        }
+        let mut plain = Vec::with_capacity(data.len());
+        crypto::decrypt_with_key(&mut &data[..], &mut plain, &self.0)?;
+        Ok(plain)
+    }
+}
+
+impl NoteStore {
+    // An image file's contents, decrypted in an encrypted vault
+    pub(crate) fn read_image_file(&self, path: &str) -> AppResult<Vec<u8>> {
+        let data = fs::read(path).with_context(|| format!("Failed to read image {}", path))?;
+        match self.image_key() {
+            Some(key) => key.open(data),
+            None => Ok(data),
+        }
+    }
+
+    // What to write to an image file, encrypted in an encrypted vault
+    pub(crate) fn seal_image<'a>(&self, data: &'a [u8]) -> AppResult<Cow<'a, [u8]>> {
+        match self.image_key() {
+            Some(key) => key.seal(data).map(Cow::Owned),
+            None => Ok(Cow::Borrowed(data)),
+        }
+    }
+}
+
+// Written next to the database by `encrypt_vault`, holding the encrypted
+// database's path. A database without the SQLite header is only taken for
+// an encrypted one when this says so; otherwise it is damaged.
+const VAULT_MARKER: &str = "vault-encrypted";
+
+// Whether the vault in `app_dir` has an encrypted database
+pub fn is_encrypted(app_dir: &Path) -> bool {
+    is_marked(app_dir) && is_encrypted_file(&recovery::database_path(app_dir))
+}
+
+// Whether the file could be an encrypted database: it has content but no
+// SQLite header. A damaged database looks the same, see `VAULT_MARKER`.
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| header != recovery::SQLITE_HEADER)
+}
+
+// Whether the marker names the database currently in use
+pub(crate) fn is_marked(app_dir: &Path) -> bool {
+    fs::read_to_string(app_dir.join(VAULT_MARKER))
+        .is_ok_and(|marked| Path::new(marked.trim()) == recovery::database_path(app_dir))
+}
+
+// Record whether the database in use is encrypted
+pub(crate) fn set_marked(app_dir: &Path, encrypted: bool) -> AppResult<()> {
+    let marker = app_dir.join(VAULT_MARKER);
+    if encrypted {
+        let database_path = recovery::database_path(app_dir);
+        fs::write(&marker, database_path.to_string_lossy().as_bytes()).context("Failed to mark the vault as encrypted")
+    } else {
+        match fs::remove_file(&marker) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e).context("Failed to unmark the vault as encrypted"),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Encrypt a plaintext vault with `passphrase`. Backups and the copies a
+// restore set aside are encrypted first, with the same passphrase and image
+// key, so no plain copy of the notes stays behind. The store is then closed,
+// its database replaced by an encrypted copy and the vault opened again,
+// which encrypts the images; if that is cut short it carries on at the next
+// open.
+pub async fn encrypt_vault(store: NoteStore, passphrase: &str) -> AppResult<NoteStore> {
+    if store.is_encrypted() {
+        return Err(AppError::Conflict { message: "The vault is already encrypted".to_string(), id: None });
+    }
+    check_strength(passphrase)?;
+
+    let data_dir = store.data_dir().to_path_buf();
+    let key = ImageKey::generate();
+    encrypt_copies(&data_dir, passphrase, &key).await?;
+    let encoded = key.encode();
+    replace_database(store, passphrase, &[(VAULT_IMAGE_KEY, Some(&encoded)), (VAULT_IMAGES_PENDING, Some("true"))]).await?;
+    NoteStore::open_encrypted(data_dir, passphrase).await
+}
+
+// Re-encrypt the database under a new passphrase; images keep their key, so
+// nothing else is rewritten
+pub async fn change_passphrase(store: NoteStore, current: &str, passphrase: &str) -> AppResult<NoteStore> {
+    require_encrypted(&store)?;
+    check_strength(passphrase)?;
+    verify_passphrase(store.data_dir(), current).await?;
+
+    let data_dir = store.data_dir().to_path_buf();
+    replace_database(store, passphrase, &[]).await?;
+    NoteStore::open_encrypted(data_dir, passphrase).await
+}
+
+// Turn an encrypted vault back into a plaintext one. Images are decrypted
+// first, so the key is still at hand if this is cut short.
+pub async fn decrypt_vault(store: NoteStore, passphrase: &str) -> AppResult<NoteStore> {
+    let key = require_encrypted(&store)?.clone();
+    verify_passphrase(store.data_dir(), passphrase).await?;
+    rewrite_images(&store, &key, false).await?;
+
+    let data_dir = store.data_dir().to_path_buf();
+    replace_database(store, "", &[(VAULT_IMAGE_KEY, None), (VAULT_IMAGES_PENDING, None)]).await?;
+    Ok(NoteStore::open(data_dir).await?)
+}
+
+// The mirror, git and WebDAV sync copy notes out in plain text. From an
+// encrypted vault each only does that once the user allowed it.
+pub(crate) fn check_unencrypted_copy(store: &NoteStore, allowed: bool, what: &str) -> AppResult<()> {
+    if store.is_encrypted() && !allowed {
+        return Err(AppError::Validation(format!(
+            "The vault is encrypted and {} would copy its notes in plain text. Allow unencrypted copies in its settings to go ahead.",
+            what
+        )));
+    }
+    Ok(())
+}
+
+// The image key, made on first use for a vault created encrypted
+pub(crate) async fn load_image_key(pool: &SqlitePool) -> AppResult<ImageKey> {
+    match settings::get_setting(pool, VAULT_IMAGE_KEY).await? {
+        Some(encoded) => ImageKey::parse(&encoded),
+        None => {
+            let key = ImageKey::generate();
+            settings::set_setting(pool, VAULT_IMAGE_KEY, &key.encode()).await?;
+            Ok(key)
+        }
+    }
+}
+
+// Finish encrypting the images of a vault that was just encrypted
+pub(crate) async fn seal_pending_images(store: &NoteStore) -> AppResult<()> {
+    let Some(key) = store.image_key() else { return Ok(()) };
+    if settings::get_setting(store.pool(), VAULT_IMAGES_PENDING).await?.is_none() {
+        return Ok(());
+    }
+    rewrite_images(store, key, true).await?;
+    settings::remove_setting(store.pool(), VAULT_IMAGES_PENDING).await
+}
+
+// The passphrase as a quoted SQL string for `PRAGMA key`; SQLCipher derives
+// the actual key from it with PBKDF2
+pub(crate) fn key_pragma(passphrase: &str) -> String {
+    format!("'{}'", passphrase.replace('\'', "''"))
+}
+
+// SQLITE_NOTADB, which is all SQLCipher says about a wrong key
+pub(crate) fn is_not_a_database(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some("26"))
+}
+
+fn require_encrypted(store: &NoteStore) -> AppResult<&ImageKey> {
+    store
+        .image_key()
+        .ok_or_else(|| AppError::Validation("The vault is not encrypted".to_string()))
+}
+
+fn check_strength(passphrase: &str) -> AppResult<()> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(AppError::Validation(format!(
+            "The vault passphrase needs at least {} characters",
+            MIN_PASSPHRASE_LEN
+        )));
+    }
+    Ok(())
+}
+
+// Try the passphrase on a connection of its own
+async fn verify_passphrase(data_dir: &Path, passphrase: &str) -> AppResult<()> {
+    let options = SqliteConnectOptions::new()
+        .filename(recovery::database_path(data_dir))
+        .read_only(true)
+        .pragma("key", key_pragma(passphrase));
+
+    let result = async {
+        let pool = SqlitePool::connect_with(options).await?;
+        let result = sqlx::query("SELECT COUNT(*) FROM sqlite_master").fetch_one(&pool).await;
+        pool.close().await;
+        result
+    }
+    .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) if is_not_a_database(&e) => {
+            tokio::time::sleep(FAILED_ATTEMPT_DELAY).await;
+            Err(AppError::Unauthorized("Wrong vault passphrase".to_string()))
+        }
+        Err(e) => Err(e).context("Failed to open the vault"),
+    }
+}
+
+// Copy the database into a new file encrypted with `key`, or in plain form
+// for an empty one, with `changes` made to its settings (None removes one).
+// The store is then closed and the copy moved over the original.
+async fn replace_database(store: NoteStore, key: &str, changes: &[(&str, Option<&str>)]) -> AppResult<()> {
+    let database_path = recovery::database_path(store.data_dir());
+    let rewritten = PathBuf::from(format!("{}.rewrite", database_path.display()));
+    let _ = fs::remove_file(&rewritten);
+
+    let result = match store.pool().acquire().await {
+        Ok(mut connection) => export_database(&mut connection, &rewritten, key, changes).await,
+        Err(e) => Err(e).context("Failed to open the database"),
+    };
+    if let Err(e) = result {
+        let _ = fs::remove_file(&rewritten);
+        return Err(e);
+    }
+
+    // Marked before an encrypted file takes the database's place and unmarked
+    // after a plain one did, so a crash in between never leaves an encrypted
+    // database unmarked
+    let encrypted = !key.is_empty();
+    if encrypted {
+        set_marked(store.data_dir(), true)?;
+    }
+    let data_dir = store.data_dir().to_path_buf();
+    store.close().await;
+    // The old file and any WAL left behind are shredded, so a plain database
+    // leaves nothing readable on disk
+    let retired = PathBuf::from(format!("{}.retired", database_path.display()));
+    for suffix in ["-wal", "-shm"] {
+        shred::shred_file(Path::new(&format!("{}{}", database_path.display(), suffix)))
+            .context("Failed to remove the old database journal")?;
+    }
+    fs::rename(&database_path, &retired).context("Failed to move the old database aside")?;
+    fs::rename(&rewritten, &database_path).context("Failed to move the re-encrypted database into place")?;
+    shred::shred_file(&retired).context("Failed to shred the old database")?;
+    if !encrypted {
+        set_marked(&data_dir, false)?;
+    }
+    Ok(())
+}
+
+// ATTACH only lasts for its connection, so everything runs on one
+async fn export_database(
+    connection: &mut SqliteConnection,
+    target: &Path,
+    key: &str,
+    changes: &[(&str, Option<&str>)],
+) -> AppResult<()> {
+    sqlx::query("ATTACH DATABASE ?1 AS rewritten KEY ?2")
+        .bind(target.to_string_lossy().to_string())
+        .bind(key)
+        .execute(&mut *connection)
+        .await
+        .context("Failed to create the re-encrypted database")?;
+
+    let result: AppResult<()> = async {
+        sqlx::query("SELECT sqlcipher_export('rewritten')")
+            .fetch_all(&mut *connection)
+            .await
+            .context("Failed to re-encrypt the database")?;
+
+        for (name, value) in changes {
+            let query = match value {
+                Some(value) => sqlx::query(
+                    r#"
+                    INSERT INTO rewritten.settings (key, value) VALUES (?1, ?2)
+                    ON CONFLICT (key) DO UPDATE SET value = excluded.value
+                    "#,
+                )
+                .bind(*name)
+                .bind(*value),
+                None => sqlx::query("DELETE FROM rewritten.settings WHERE key = ?1").bind(*name),
+            };
+            query
+                .execute(&mut *connection)
+                .await
+                .with_context(|| format!("Failed to save setting {}", name))?;
+        }
+        Ok(())
+    }
+    .await;
+
+    let _ = sqlx::query("DETACH DATABASE rewritten").execute(&mut *connection).await;
+    result
+}
+
+// Encrypt or decrypt every image file in place, skipping those already done
+// and those that are missing
+async fn rewrite_images(store: &NoteStore, key: &ImageKey, seal: bool) -> AppResult<()> {
+    for image in store.get_all_images().await? {
+        let path = Path::new(&image.file_path);
+        rewrite_image(path, key, seal).with_context(|| format!("Failed to rewrite image {}", image.file_path))?;
+    }
+    Ok(())
+}
+
+fn rewrite_image(path: &Path, key: &ImageKey, seal: bool) -> AppResult<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|name| name.to_str())) else {
+        return Ok(());
+    };
+    // Sealed data waits next to the original until that is shredded
+    let sealed_name = format!(".{}.sealed", name);
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return match fs::rename(dir.join(&sealed_name), path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            };
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if crypto::is_key_encrypted(&data) == seal {
+        return Ok(());
+    }
+
+    if seal {
+        let sealed_path = uploads::write_file_atomically(dir, &sealed_name, &key.seal(&data)?)?;
+        shred::shred_file(path)?;
+        fs::rename(sealed_path, path)?;
+    } else {
+        uploads::write_file_atomically(dir, name, &key.open(data)?)?;
+    }
+    Ok(())
+}
+
+// Encrypt the backups and pre-restore copies of a plaintext vault. One that
+// can't be encrypted stops the migration, naming it, rather than staying
+// behind in plain text.
+async fn encrypt_copies(app_dir: &Path, passphrase: &str, key: &ImageKey) -> AppResult<()> {
+    for backup in backup::list_backups(app_dir)? {
+        encrypt_backup(Path::new(&backup.path), passphrase, key)
+            .await
+            .with_context(|| format!("Failed to encrypt backup {}", backup.file_name))?;
+    }
+
+    let entries = fs::read_dir(app_dir).context("Failed to read the data directory")?;
+    for entry in entries {
+        let path = entry.context("Failed to read the data directory")?.path();
+        let is_copy = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(backup::PRE_RESTORE_PREFIX));
+        if is_copy && path.is_dir() {
+            encrypt_copy(&path, passphrase, key)
+                .await
+                .with_context(|| format!("Failed to encrypt {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+// Unpack the backup next to itself, encrypt it there and pack it again; the
+// plain files unpacked are shredded either way
+async fn encrypt_backup(archive_path: &Path, passphrase: &str, key: &ImageKey) -> AppResult<()> {
+    let work_dir = archive_path.with_extension("encrypting");
+    shred::shred_dir(&work_dir).context("Failed to clear the work directory")?;
+    fs::create_dir_all(&work_dir).context("Failed to create the work directory")?;
+
+    let result = async {
+        let manifest = backup::unpack_backup(archive_path, &work_dir).await?;
+        if encrypt_copy(&work_dir, passphrase, key).await? {
+            backup::repack_backup(archive_path, &work_dir, manifest.created_at).await?;
+        }
+        Ok(())
+    }
+    .await;
+
+    shred::shred_dir(&work_dir).context("Failed to shred the unpacked backup")?;
+    result
+}
+
+// Encrypt the plain databases in a copy of the vault, and its images with
+// them. Returns whether there was anything to encrypt.
+async fn encrypt_copy(dir: &Path, passphrase: &str, key: &ImageKey) -> AppResult<bool> {
+    let mut databases = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && is_plain_database(&path) {
+            databases.push(path);
+        }
+    }
+    if databases.is_empty() {
+        return Ok(false);
+    }
+
+    for database in &databases {
+        encrypt_database_file(database, passphrase, key).await?;
+    }
+    if let Ok(entries) = fs::read_dir(dir.join("images")) {
+        for entry in entries {
+            let path = entry?.path();
+            let hidden = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.'));
+            if path.is_file() && !hidden {
+                rewrite_image(&path, key, true)?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+// Replace a plain database file with an encrypted copy that holds the image
+// key, shredding the original and its journal
+async fn encrypt_database_file(path: &Path, passphrase: &str, key: &ImageKey) -> AppResult<()> {
+    let encrypted = PathBuf::from(format!("{}.encrypted", path.display()));
+    let _ = fs::remove_file(&encrypted);
+    let encoded = key.encode();
+    let changes = [(VAULT_IMAGE_KEY, Some(encoded.as_str())), (VAULT_IMAGES_PENDING, None)];
+
+    let result = async {
+        // ATTACH creates the target with the flags the connection was opened with
+        let options = SqliteConnectOptions::new().filename(path).create_if_missing(true);
+        let mut connection = SqliteConnection::connect_with(&options).await.context("Failed to open the database")?;
+        let result = export_database(&mut connection, &encrypted, passphrase, &changes).await;
+        let _ = connection.close().await;
+        result
+    }
+    .await;
+    if let Err(e) = result {
+        let _ = fs::remove_file(&encrypted);
+        return Err(e);
+    }
+
+    for file in recovery::database_files(path) {
+        shred::shred_file(&file).context("Failed to shred the plain database")?;
+    }
+    fs::rename(&encrypted, path).context("Failed to move the encrypted database into place")
+}
+
+fn is_plain_database(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| header == recovery::SQLITE_HEADER)
+}
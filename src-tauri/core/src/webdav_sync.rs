@@ -0,0 +1,1038 @@
+// Syncs the vault between devices through a WebDAV folder (Nextcloud, a NAS,
+// `rclone serve webdav`, ...). The folder holds one file per version of each
+// note, collection and image record, image files named by content hash, and
+// `manifest.json` naming the current version of every record; deleted records
+// stay in the manifest as tombstones. The manifest is only replaced if nobody
+// else replaced it since it was read, so two devices syncing at once cannot
+// lose each other's changes.
+//
+// Each device keeps the version it last agreed on for every record and finds
+// its own changes through the change log. A record changed on both sides is
+// settled the same way whichever device syncs first: an edit beats a delete,
+// otherwise the later `updated_at` wins. For notes the losing version is kept
+// as a conflict copy.
+use crate::error::{AppError, AppResult, Context};
+use crate::settings::{self, WebDavSettings};
+use crate::{count_characters, count_words, secrets, uploads, Collection, ImageMetadata, Note, NoteStore};
+use chrono::{DateTime, Utc};
+use reqwest::{header, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const MANIFEST: &str = "manifest.json";
+const RECORDS_DIR: &str = "records";
+const BLOBS_DIR: &str = "blobs";
+const MANIFEST_FORMAT: u32 = 1;
+// Another device replacing the manifest mid-sync makes us start over
+const MAX_ATTEMPTS: usize = 3;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(60);
+// Local bookkeeping, in the settings table
+const LAST_CHANGE_SEQ: &str = "webdav.last_change_seq";
+const SYNCED_URL: &str = "webdav.synced_url";
+// When the last sync read the change log, in the tombstones' time format
+const SYNCED_AT: &str = "webdav.synced_at";
+
+// Scheduled and on-demand syncs never run at the same time
+static SYNC_LOCK: Mutex<()> = Mutex::const_new(());
+
+// In the order records are applied, so notes find their collection and images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Entity {
+    Collection,
+    Image,
+    Note,
+}
+
+impl Entity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Entity::Collection => "collection",
+            Entity::Image => "image",
+            Entity::Note => "note",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Entity> {
+        match name {
+            "collection" => Some(Entity::Collection),
+            "image" => Some(Entity::Image),
+            "note" => Some(Entity::Note),
+            _ => None,
+        }
+    }
+}
+
+type Key = (Entity, String);
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct WebDavSyncReport {
+    // Records sent to and taken from the folder, deletions included
+    pub pushed: usize,
+    pub pulled: usize,
+    pub conflicts: Vec<WebDavConflict>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebDavConflict {
+    pub entity: Entity,
+    pub id: String,
+    // Whose version the record now has: "local" or "remote"
+    pub kept: String,
+    // The note holding the other version
+    pub copy_id: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    // Keyed by `<entity>/<id>`
+    records: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestEntry {
+    // Hash of the current version, None once the record was deleted
+    hash: Option<String>,
+    updated_at: DateTime<Utc>,
+    // Name of the device that wrote it
+    device: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NoteRecord {
+    id: String,
+    title: String,
+    content: String,
+    collection_id: Option<String>,
+    tags: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    is_archived: bool,
+    // Left out when false, so records of plain notes hash as before
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    is_encrypted: bool,
+    // Ids of the images the note uses, sorted
+    images: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImageRecord {
+    id: String,
+    filename: String,
+    original_name: String,
+    size: i64,
+    mime_type: String,
+    created_at: DateTime<Utc>,
+    // SHA-256 of the image file, which is stored under `blobs/`
+    blob: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "entity", rename_all = "lowercase")]
+enum Record {
+    Collection(Collection),
+    Image(ImageRecord),
+    Note(NoteRecord),
+}
+
+impl Record {
+    fn updated_at(&self) -> DateTime<Utc> {
+        match self {
+            Record::Collection(collection) => collection.updated_at,
+            Record::Image(image) => image.created_at,
+            Record::Note(note) => note.updated_at,
+        }
+    }
+}
+
+// A record as stored in the folder, with the hash naming it
+#[derive(Debug, Clone)]
+struct Version {
+    record: Record,
+    bytes: Vec<u8>,
+    hash: String,
+}
+
+impl Version {
+    fn new(record: Record) -> AppResult<Version> {
+        let bytes = serde_json::to_vec(&record).context("Failed to serialize record")?;
+        let hash = sha256_hex(&bytes);
+        Ok(Version { record, bytes, hash })
+    }
+}
+
+// Sync this vault with the configured WebDAV folder
+pub async fn sync(store: &NoteStore, settings: &WebDavSettings) -> AppResult<WebDavSyncReport> {
+    crate::vault::check_unencrypted_copy(store, settings.allow_unencrypted, "WebDAV sync")?;
+    let _guard = SYNC_LOCK.lock().await;
+    let remote = WebDav::new(settings)?;
+
+    // What this device agreed on with another folder says nothing about this one
+    let url = remote.base.clone();
+    if settings::get_setting(store.pool(), SYNCED_URL).await?.as_deref() != Some(&url) {
+        sqlx::query("DELETE FROM webdav_records")
+            .execute(store.pool())
+            .await
+            .context("Failed to reset sync state")?;
+        sqlx::query("DELETE FROM settings WHERE key IN (?1, ?2)")
+            .bind(LAST_CHANGE_SEQ)
+            .bind(SYNCED_AT)
+            .execute(store.pool())
+            .await
+            .context("Failed to reset sync state")?;
+        settings::set_setting(store.pool(), SYNCED_URL, &url).await?;
+    }
+
+    remote.make_folder("").await?;
+    remote.make_folder(RECORDS_DIR).await?;
+    remote.make_folder(BLOBS_DIR).await?;
+
+    for _ in 0..MAX_ATTEMPTS {
+        if let Some(report) = sync_once(store, &remote, &settings.device()).await? {
+            return Ok(report);
+        }
+    }
+    Err(AppError::Conflict {
+        message: "The WebDAV folder kept changing during the sync, try again in a moment".to_string(),
+        id: None,
+    })
+}
+
+// Sync on the configured interval while enabled
+pub async fn run_webdav_sync_scheduler(store: NoteStore) {
+    loop {
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+
+        if let Err(e) = run_scheduled_sync(&store).await {
+            eprintln!("Scheduled WebDAV sync failed: {}", e);
+        }
+    }
+}
+
+async fn run_scheduled_sync(store: &NoteStore) -> AppResult<()> {
+    let settings = WebDavSettings::load(store.pool()).await?;
+    if !settings.enabled || settings.url.is_empty() {
+        return Ok(());
+    }
+
+    let last_sync = settings::get_setting(store.pool(), settings::WEBDAV_LAST_SYNC)
+        .await?
+        .and_then(|time| DateTime::parse_from_rfc3339(&time).ok());
+    if let Some(last_sync) = last_sync {
+        if Utc::now() - last_sync.with_timezone(&Utc) < chrono::Duration::minutes(settings.interval_minutes as i64) {
+            return Ok(());
+        }
+    }
+
+    let report = sync(store, &settings).await?;
+    settings::set_setting(store.pool(), settings::WEBDAV_LAST_SYNC, &Utc::now().to_rfc3339()).await?;
+    for conflict in report.conflicts.iter().filter(|conflict| conflict.copy_id.is_some()) {
+        eprintln!(
+            "Warning: Note {} changed on two devices, the other version is kept as note {}",
+            conflict.id,
+            conflict.copy_id.as_deref().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+struct Pull {
+    key: Key,
+    // The local hash the decision was based on; the pull is skipped if the
+    // record changes locally before it is applied
+    local_hash: Option<String>,
+    remote: Option<(Version, Option<Vec<u8>>)>,
+    is_copy: bool,
+}
+
+struct Push {
+    key: Key,
+    version: Option<Version>,
+    updated_at: DateTime<Utc>,
+}
+
+// One pass: read the manifest, work out what goes which way, upload, replace
+// the manifest and apply what was pulled. None if the manifest was replaced
+// in the meantime; nothing has changed locally then.
+async fn sync_once(store: &NoteStore, remote: &WebDav, device: &str) -> AppResult<Option<WebDavSyncReport>> {
+    let pool = store.pool();
+    let manifest_exists;
+    let (mut manifest, etag) = match remote.get(MANIFEST).await? {
+        Some((bytes, etag)) => {
+            manifest_exists = true;
+            let manifest: Manifest = serde_json::from_slice(&bytes).context("Failed to read the WebDAV manifest")?;
+            if manifest.format > MANIFEST_FORMAT {
+                return Err(AppError::Validation(
+                    "The WebDAV folder was written by a newer version of Notura".to_string(),
+                ));
+            }
+            (manifest, etag)
+        }
+        None => {
+            manifest_exists = false;
+            (Manifest { format: MANIFEST_FORMAT, ..Default::default() }, None)
+        }
+    };
+
+    let synced_at = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let (max_seq,): (i64,) = sqlx::query_as("SELECT COALESCE(MAX(seq), 0) FROM change_log")
+        .fetch_one(pool)
+        .await
+        .context("Failed to read the change log")?;
+    let last_seq: Option<i64> = settings::get_setting(pool, LAST_CHANGE_SEQ)
+        .await?
+        .and_then(|seq| seq.parse().ok());
+    let base = load_base(store).await?;
+
+    // Records changed here since the last sync, or all of them the first time,
+    // plus those whose remote version is not the one agreed on
+    let mut keys = match last_seq {
+        Some(last_seq) => changed_keys(store, last_seq, max_seq).await?,
+        None => all_local_keys(store).await?,
+    };
+    for (name, entry) in &manifest.records {
+        let Some(key) = parse_key(name) else { continue };
+        if base.get(&key).cloned().flatten() != entry.hash {
+            keys.insert(key);
+        }
+    }
+
+    let mut report = WebDavSyncReport::default();
+    let mut pulls = Vec::new();
+    let mut pushes = Vec::new();
+    let mut settled = Vec::new();
+    let mut copies = Vec::new();
+
+    for key in keys {
+        let local = load_local(store, &key).await?;
+        let local_hash = local.as_ref().map(|version| version.hash.clone());
+        let base_hash = base.get(&key).cloned().flatten();
+        let entry = manifest.records.get(&key_name(&key)).cloned();
+        let remote_hash = entry.as_ref().and_then(|entry| entry.hash.clone());
+
+        if local_hash == remote_hash {
+            if base_hash != local_hash {
+                settled.push((key, local_hash));
+            }
+            continue;
+        }
+
+        let local_wins = if local_hash == base_hash {
+            false
+        } else if remote_hash == base_hash {
+            true
+        } else {
+            // Changed on both sides
+            let local_wins = match (&local, &entry) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                (Some(_), Some(ManifestEntry { hash: None, .. })) => true,
+                // Equal times fall back to the hashes, which every device sees the same
+                (Some(local), Some(entry)) => {
+                    (local.record.updated_at(), Some(&local.hash)) > (entry.updated_at, entry.hash.as_ref())
+                }
+            };
+
+            let mut copy_id = None;
+            if let (Entity::Note, Some(local), Some(remote_hash)) = (key.0, &local, &remote_hash) {
+                let (loser, loser_device) = if local_wins {
+                    let remote_version = fetch_record(remote, &key, remote_hash).await?;
+                    let Some(remote_version) = remote_version else { return Ok(None) };
+                    let device = entry.as_ref().map(|entry| entry.device.clone()).unwrap_or_default();
+                    (remote_version, device)
+                } else {
+                    (local.clone(), device.to_string())
+                };
+                if let Record::Note(note) = loser.record {
+                    let copy = conflict_copy(note, &loser.hash, &loser_device);
+                    copy_id = Some(copy.id.clone());
+                    copies.push(Version::new(Record::Note(copy))?);
+                }
+            }
+            report.conflicts.push(WebDavConflict {
+                entity: key.0,
+                id: key.1.clone(),
+                kept: if local_wins { "local" } else { "remote" }.to_string(),
+                copy_id,
+            });
+            local_wins
+        };
+
+        if local_wins {
+            let updated_at = match &local {
+                Some(version) => version.record.updated_at(),
+                None => deleted_at(store, &key).await?,
+            };
+            pushes.push(Push { key, version: local, updated_at });
+        } else {
+            let fetched = match &remote_hash {
+                Some(remote_hash) => {
+                    let Some(version) = fetch_record(remote, &key, remote_hash).await? else { return Ok(None) };
+                    let blob = match &version.record {
+                        Record::Image(image) => match remote.get(&blob_path(&image.blob)).await? {
+                            Some((blob, _)) if sha256_hex(&blob) == image.blob => Some(blob),
+                            Some(_) => {
+                                return Err(AppError::Validation(format!("The WebDAV copy of image {} is damaged", image.id)));
+                            }
+                            None => return Ok(None),
+                        },
+                        _ => None,
+                    };
+                    Some((version, blob))
+                }
+                None => None,
+            };
+            pulls.push(Pull { key, local_hash, remote: fetched, is_copy: false });
+        }
+    }
+
+    // Notes going up from here are checked for credentials first
+    let mut pushed_notes = Vec::new();
+    for push in &pushes {
+        if let (Entity::Note, Some(_)) = (push.key.0, &push.version) {
+            pushed_notes.push(store.get_note(&push.key.1).await?);
+        }
+    }
+    secrets::check_sync(&pushed_notes, "WebDAV sync")?;
+
+    // Conflict copies are new notes on both sides
+    for copy in copies {
+        let Record::Note(note) = &copy.record else { continue };
+        let key = (Entity::Note, note.id.clone());
+        let updated_at = note.updated_at;
+        let local_hash = load_local(store, &key).await?.map(|version| version.hash);
+        pulls.push(Pull { key: key.clone(), local_hash, remote: Some((copy.clone(), None)), is_copy: true });
+        pushes.push(Push { key, version: Some(copy), updated_at });
+    }
+
+    // Upload new versions, then publish them by replacing the manifest
+    let mut superseded = Vec::new();
+    for push in &pushes {
+        let name = key_name(&push.key);
+        if let Some(version) = &push.version {
+            if let Record::Image(image) = &version.record {
+                let metadata = store.get_image_metadata(&image.id).await?;
+                let data = store.read_image_file(&metadata.file_path)?;
+                remote.put(&blob_path(&image.blob), data, None).await?;
+            }
+            remote.put(&record_path(&push.key, &version.hash), version.bytes.clone(), None).await?;
+        }
+        let hash = push.version.as_ref().map(|version| version.hash.clone());
+        if let Some(old_hash) = manifest.records.get(&name).and_then(|entry| entry.hash.clone()) {
+            if Some(&old_hash) != hash.as_ref() {
+                superseded.push(record_path(&push.key, &old_hash));
+            }
+        }
+        manifest.records.insert(name, ManifestEntry {
+            hash,
+            updated_at: push.updated_at,
+            device: device.to_string(),
+        });
+    }
+
+    if !pushes.is_empty() || !manifest_exists {
+        let bytes = serde_json::to_vec_pretty(&manifest).context("Failed to serialize the WebDAV manifest")?;
+        let condition = match &etag {
+            Some(etag) => Some(Condition::IfMatch(etag)),
+            None if manifest_exists => None,
+            None => Some(Condition::IfNoneMatch),
+        };
+        if !remote.put(MANIFEST, bytes, condition).await? {
+            return Ok(None);
+        }
+    }
+    report.pushed = pushes.len();
+
+    // Older versions are only needed by a device that read the old manifest,
+    // and that one starts over when they are gone
+    for path in superseded {
+        if let Err(e) = remote.delete(&path).await {
+            eprintln!("Warning: Failed to remove {} from the WebDAV folder: {}", path, e);
+        }
+    }
+
+    let mut agreed: Vec<(Key, Option<String>)> = settled;
+    agreed.extend(pushes.iter().map(|push| (push.key.clone(), push.version.as_ref().map(|version| version.hash.clone()))));
+
+    // Creations and updates go collections, images, notes; deletions the
+    // other way round
+    pulls.sort_by_key(|pull| match pull.remote {
+        Some(_) => (false, pull.key.0 as i8),
+        None => (true, -(pull.key.0 as i8)),
+    });
+    order_collections(&mut pulls);
+    for pull in pulls {
+        let current = load_local(store, &pull.key).await?.map(|version| version.hash);
+        if current != pull.local_hash {
+            // Edited while we were syncing; it's a conflict for the next sync
+            continue;
+        }
+        let remote_hash = pull.remote.as_ref().map(|(version, _)| version.hash.clone());
+        apply(store, &pull.key, pull.remote).await?;
+        if !pull.is_copy {
+            report.pulled += 1;
+        }
+        agreed.push((pull.key, remote_hash));
+    }
+
+    save_base(store, &agreed).await?;
+    settings::set_setting(pool, LAST_CHANGE_SEQ, &max_seq.to_string()).await?;
+    settings::set_setting(pool, SYNCED_AT, &synced_at).await?;
+    sqlx::query("DELETE FROM change_log WHERE seq <= ?1")
+        .bind(max_seq)
+        .execute(pool)
+        .await
+        .context("Failed to trim the change log")?;
+
+    Ok(Some(report))
+}
+
+// Drop the bookkeeping no sync will need, so it doesn't grow while WebDAV
+// is off: change log rows up to the last sync, all of them before the first
+// (which compares every record), and the older rows of records changed again
+// since. Tombstones go once a sync passed them on; the manifest keeps its own.
+pub async fn trim_sync_state(pool: &SqlitePool) -> AppResult<()> {
+    let _guard = SYNC_LOCK.lock().await;
+    let Some(last_seq) = settings::get_setting(pool, LAST_CHANGE_SEQ).await?.and_then(|seq| seq.parse::<i64>().ok()) else {
+        sqlx::query("DELETE FROM change_log")
+            .execute(pool)
+            .await
+            .context("Failed to trim the change log")?;
+        return Ok(());
+    };
+
+    sqlx::query(
+        r#"
+        DELETE FROM change_log
+        WHERE seq <= ?1 OR seq NOT IN (SELECT MAX(seq) FROM change_log GROUP BY entity, entity_id)
+        "#,
+    )
+    .bind(last_seq)
+    .execute(pool)
+    .await
+    .context("Failed to trim the change log")?;
+
+    // A deletion still in the change log hasn't been synced yet
+    if let Some(synced_at) = settings::get_setting(pool, SYNCED_AT).await? {
+        sqlx::query(
+            r#"
+            DELETE FROM tombstones
+            WHERE deleted_at < ?1
+              AND NOT EXISTS (
+                  SELECT 1 FROM change_log
+                  WHERE change_log.entity = tombstones.entity AND change_log.entity_id = tombstones.entity_id
+              )
+            "#,
+        )
+        .bind(synced_at)
+        .execute(pool)
+        .await
+        .context("Failed to expire tombstones")?;
+    }
+    Ok(())
+}
+
+fn key_name((entity, id): &Key) -> String {
+    format!("{}/{}", entity.as_str(), id)
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let (entity, id) = name.split_once('/')?;
+    Some((Entity::parse(entity)?, id.to_string()))
+}
+
+// Versions are stored side by side, so writing one never overwrites a
+// version another device may still be reading
+fn record_path((entity, id): &Key, hash: &str) -> String {
+    let id: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}/{}-{}-{}.json", RECORDS_DIR, entity.as_str(), id, hash)
+}
+
+// Image files are named by content and never removed, so a record always
+// finds its file
+fn blob_path(hash: &str) -> String {
+    format!("{}/{}", BLOBS_DIR, hash)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// The losing side of a note edited on two devices, as a new note. The id
+// depends only on the note and the version, so every device makes the same
+// copy and a retried sync doesn't make another.
+fn conflict_copy(note: NoteRecord, hash: &str, device: &str) -> NoteRecord {
+    let digest = Sha256::digest(format!("conflict:{}:{}", note.id, hash).as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    let id = uuid::Builder::from_random_bytes(bytes).into_uuid();
+
+    let device = if device.is_empty() { "another device" } else { device };
+    NoteRecord {
+        id: id.to_string(),
+        title: format!("{} (conflict from {})", note.title, device),
+        ..note
+    }
+}
+
+async fn fetch_record(remote: &WebDav, key: &Key, hash: &str) -> AppResult<Option<Version>> {
+    // Gone means another device replaced it after we read the manifest
+    let Some((bytes, _)) = remote.get(&record_path(key, hash)).await? else { return Ok(None) };
+    if sha256_hex(&bytes) != hash {
+        return Err(AppError::Validation(format!("The WebDAV copy of {} is damaged", key_name(key))));
+    }
+    let record: Record = serde_json::from_slice(&bytes)
+        .with_context(|| format!("Failed to read {} from the WebDAV folder", key_name(key)))?;
+    Ok(Some(Version { record, bytes, hash: hash.to_string() }))
+}
+
+async fn load_base(store: &NoteStore) -> AppResult<HashMap<Key, Option<String>>> {
+    let rows: Vec<(String, String, Option<String>)> = sqlx::query_as("SELECT entity, entity_id, hash FROM webdav_records")
+        .fetch_all(store.pool())
+        .await
+        .context("Failed to read sync state")?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(entity, id, hash)| Some(((Entity::parse(&entity)?, id), hash)))
+        .collect())
+}
+
+async fn save_base(store: &NoteStore, agreed: &[(Key, Option<String>)]) -> AppResult<()> {
+    let mut tx = store.pool().begin().await.context("Failed to start transaction")?;
+    for ((entity, id), hash) in agreed {
+        sqlx::query("INSERT OR REPLACE INTO webdav_records (entity, entity_id, hash) VALUES (?1, ?2, ?3)")
+            .bind(entity.as_str())
+            .bind(id)
+            .bind(hash)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to save sync state")?;
+    }
+    tx.commit().await.context("Failed to save sync state")
+}
+
+async fn changed_keys(store: &NoteStore, after: i64, up_to: i64) -> AppResult<BTreeSet<Key>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT DISTINCT entity, entity_id FROM change_log WHERE seq > ?1 AND seq <= ?2"
+    )
+    .bind(after)
+    .bind(up_to)
+    .fetch_all(store.pool())
+    .await
+    .context("Failed to read the change log")?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(entity, id)| Some((Entity::parse(&entity)?, id)))
+        .collect())
+}
+
+async fn all_local_keys(store: &NoteStore) -> AppResult<BTreeSet<Key>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        r#"
+        SELECT 'note', id FROM notes
+        UNION ALL SELECT 'collection', id FROM collections
+        UNION ALL SELECT 'image', id FROM images
+        UNION ALL SELECT entity, entity_id FROM tombstones
+        "#,
+    )
+    .fetch_all(store.pool())
+    .await
+    .context("Failed to list records")?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(entity, id)| Some((Entity::parse(&entity)?, id)))
+        .collect())
+}
+
+async fn deleted_at(store: &NoteStore, (entity, id): &Key) -> AppResult<DateTime<Utc>> {
+    let deleted_at: Option<(DateTime<Utc>,)> = sqlx::query_as(
+        "SELECT deleted_at FROM tombstones WHERE entity = ?1 AND entity_id = ?2"
+    )
+    .bind(entity.as_str())
+    .bind(id)
+    .fetch_optional(store.pool())
+    .await
+    .context("Failed to read tombstone")?;
+
+    Ok(deleted_at.map(|(deleted_at,)| deleted_at).unwrap_or_else(Utc::now))
+}
+
+// The record as it is here now, None if it doesn't exist (any more)
+async fn load_local(store: &NoteStore, (entity, id): &Key) -> AppResult<Option<Version>> {
+    let pool = store.pool();
+    let record = match entity {
+        Entity::Note => {
+            let Some(note) = sqlx::query_as::<_, Note>("SELECT * FROM notes WHERE id = ?1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to get note")?
+            else {
+                return Ok(None);
+            };
+            let images: Vec<(String,)> = sqlx::query_as("SELECT image_id FROM note_images WHERE note_id = ?1 ORDER BY image_id")
+                .bind(id)
+                .fetch_all(pool)
+                .await
+                .context("Failed to get images for note")?;
+
+            Record::Note(NoteRecord {
+                id: note.id,
+                title: note.title,
+                content: note.content,
+                collection_id: note.collection_id,
+                tags: note.tags,
+                created_at: note.created_at,
+                updated_at: note.updated_at,
+                is_archived: note.is_archived,
+                is_encrypted: note.is_encrypted,
+                images: images.into_iter().map(|(id,)| id).collect(),
+            })
+        }
+        Entity::Collection => {
+            let Some(collection) = sqlx::query_as::<_, Collection>("SELECT * FROM collections WHERE id = ?1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to get collection")?
+            else {
+                return Ok(None);
+            };
+            Record::Collection(collection)
+        }
+        Entity::Image => {
+            let Some(image) = sqlx::query_as::<_, ImageMetadata>("SELECT * FROM images WHERE id = ?1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to get image metadata")?
+            else {
+                return Ok(None);
+            };
+            let data = store.read_image_file(&image.file_path)?;
+
+            Record::Image(ImageRecord {
+                id: image.id,
+                filename: image.filename,
+                original_name: image.original_name,
+                size: image.size,
+                mime_type: image.mime_type,
+                created_at: image.created_at,
+                blob: sha256_hex(&data),
+            })
+        }
+    };
+    Version::new(record).map(Some)
+}
+
+// Collections are created parents first; the sort above keeps them ahead of
+// images and notes
+fn order_collections(pulls: &mut [Pull]) {
+    let parents: HashMap<String, Option<String>> = pulls
+        .iter()
+        .filter_map(|pull| match &pull.remote {
+            Some((Version { record: Record::Collection(collection), .. }, _)) => {
+                Some((collection.id.clone(), collection.parent_id.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    let depth = |id: &str| {
+        let mut depth = 0;
+        let mut current = parents.get(id).cloned().flatten();
+        while let Some(parent) = current {
+            depth += 1;
+            if depth > parents.len() {
+                break;
+            }
+            current = parents.get(&parent).cloned().flatten();
+        }
+        depth
+    };
+
+    let end = pulls.iter().take_while(|pull| pull.remote.is_some() && pull.key.0 == Entity::Collection).count();
+    pulls[..end].sort_by_cached_key(|pull| depth(&pull.key.1));
+}
+
+// Make the local record match the remote version, or delete it
+async fn apply(store: &NoteStore, key: &Key, remote: Option<(Version, Option<Vec<u8>>)>) -> AppResult<()> {
+    let pool = store.pool();
+    let Some((version, blob)) = remote else {
+        return match key.0 {
+            Entity::Note => {
+                sqlx::query("DELETE FROM notes WHERE id = ?1")
+                    .bind(&key.1)
+                    .execute(pool)
+                    .await
+                    .context("Failed to delete note")?;
+                Ok(())
+            }
+            Entity::Image => match store.delete_image(&key.1).await {
+                Err(AppError::NotFound { .. }) => Ok(()),
+                result => result,
+            },
+            Entity::Collection => {
+                let mut tx = pool.begin().await.context("Failed to start transaction")?;
+                sqlx::query("UPDATE notes SET collection_id = NULL WHERE collection_id = ?1")
+                    .bind(&key.1)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to update notes")?;
+                sqlx::query("UPDATE collections SET parent_id = NULL WHERE parent_id = ?1")
+                    .bind(&key.1)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to update collections")?;
+                sqlx::query("DELETE FROM collections WHERE id = ?1")
+                    .bind(&key.1)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to delete collection")?;
+                tx.commit().await.context("Failed to delete collection")
+            }
+        };
+    };
+
+    match version.record {
+        Record::Collection(collection) => {
+            sqlx::query(
+                r#"
+                INSERT INTO collections (id, name, description, parent_id, color, icon, sort_order, created_at, updated_at)
+                VALUES (?1, ?2, ?3, (SELECT id FROM collections WHERE id = ?4), ?5, ?6, ?7, ?8, ?9)
+                ON CONFLICT (id) DO UPDATE SET
+                    name = excluded.name, description = excluded.description, parent_id = excluded.parent_id,
+                    color = excluded.color, icon = excluded.icon, sort_order = excluded.sort_order,
+                    created_at = excluded.created_at, updated_at = excluded.updated_at
+                "#,
+            )
+            .bind(&collection.id)
+            .bind(&collection.name)
+            .bind(&collection.description)
+            .bind(&collection.parent_id)
+            .bind(&collection.color)
+            .bind(&collection.icon)
+            .bind(collection.sort_order)
+            .bind(collection.created_at)
+            .bind(collection.updated_at)
+            .execute(pool)
+            .await
+            .context("Failed to save collection")?;
+        }
+        Record::Image(image) => {
+            let blob = blob.ok_or_else(|| AppError::Internal(format!("Image {} was pulled without its file", image.id)))?;
+            let images_dir = store.data_dir().join("images");
+            fs::create_dir_all(&images_dir).context("Failed to create images directory")?;
+            let file_path = uploads::write_file_atomically(&images_dir, &image.filename, &store.seal_image(&blob)?)
+                .context("Failed to save image file")?;
+            let previous = sqlx::query_as::<_, ImageMetadata>("SELECT * FROM images WHERE id = ?1")
+                .bind(&image.id)
+                .fetch_optional(pool)
+                .await
+                .context("Failed to get image metadata")?;
+
+            sqlx::query(
+                r#"
+                INSERT INTO images (id, filename, original_name, file_path, size, mime_type, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT (id) DO UPDATE SET
+                    filename = excluded.filename, original_name = excluded.original_name, file_path = excluded.file_path,
+                    size = excluded.size, mime_type = excluded.mime_type, created_at = excluded.created_at
+                "#,
+            )
+            .bind(&image.id)
+            .bind(&image.filename)
+            .bind(&image.original_name)
+            .bind(file_path.to_string_lossy().to_string())
+            .bind(image.size)
+            .bind(&image.mime_type)
+            .bind(image.created_at)
+            .execute(pool)
+            .await
+            .context("Failed to save image metadata")?;
+
+            if let Some(previous) = previous.filter(|previous| previous.file_path != file_path.to_string_lossy()) {
+                let _ = fs::remove_file(&previous.file_path);
+            }
+        }
+        Record::Note(note) => {
+            let mut tx = pool.begin().await.context("Failed to start transaction")?;
+            sqlx::query(
+                r#"
+                INSERT INTO notes (id, title, content, collection_id, tags, created_at, updated_at, word_count, character_count, is_archived, is_encrypted)
+                VALUES (?1, ?2, ?3, (SELECT id FROM collections WHERE id = ?4), ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+                ON CONFLICT (id) DO UPDATE SET
+                    title = excluded.title, content = excluded.content, collection_id = excluded.collection_id,
+                    tags = excluded.tags, created_at = excluded.created_at, updated_at = excluded.updated_at,
+                    word_count = excluded.word_count, character_count = excluded.character_count,
+                    is_archived = excluded.is_archived, is_encrypted = excluded.is_encrypted
+                "#,
+            )
+            .bind(&note.id)
+            .bind(&note.title)
+            .bind(&note.content)
+            .bind(&note.collection_id)
+            .bind(&note.tags)
+            .bind(note.created_at)
+            .bind(note.updated_at)
+            .bind(if note.is_encrypted { 0 } else { count_words(&note.content) })
+            .bind(if note.is_encrypted { 0 } else { count_characters(&note.content) })
+            .bind(note.is_archived)
+            .bind(note.is_encrypted)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to save note")?;
+            // An encrypted note must not keep its text in a document
+            if note.is_encrypted {
+                sqlx::query("DELETE FROM note_documents WHERE note_id = ?1")
+                    .bind(&note.id)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to save note")?;
+            }
+
+            sqlx::query("DELETE FROM note_images WHERE note_id = ?1")
+                .bind(&note.id)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to update image associations")?;
+            for image_id in &note.images {
+                sqlx::query(
+                    "INSERT OR IGNORE INTO note_images (note_id, image_id, created_at) SELECT ?1, id, ?3 FROM images WHERE id = ?2"
+                )
+                .bind(&note.id)
+                .bind(image_id)
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await
+                .context("Failed to update image associations")?;
+            }
+            tx.commit().await.context("Failed to save note")?;
+        }
+    }
+    Ok(())
+}
+
+enum Condition<'a> {
+    IfMatch(&'a str),
+    // Only create, never replace
+    IfNoneMatch,
+}
+
+// Just enough WebDAV: GET, PUT, DELETE and MKCOL against one folder
+struct WebDav {
+    client: reqwest::Client,
+    // Ends with a slash
+    base: String,
+    username: String,
+    password: String,
+}
+
+impl WebDav {
+    fn new(settings: &WebDavSettings) -> AppResult<WebDav> {
+        let url = settings.url.trim();
+        if url.is_empty() {
+            return Err(AppError::Validation("No WebDAV folder is configured".to_string()));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to set up the WebDAV client")?;
+
+        Ok(WebDav {
+            client,
+            base: format!("{}/", url.trim_end_matches('/')),
+            username: settings.username.clone(),
+            password: settings.password.clone(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, format!("{}{}", self.base, path));
+        if self.username.is_empty() {
+            request
+        } else {
+            request.basic_auth(&self.username, Some(&self.password))
+        }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> AppResult<reqwest::Response> {
+        let response = request
+            .send()
+            .await
+            .context("Failed to reach the WebDAV server")?;
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(AppError::Unauthorized(
+                "The WebDAV server refused the username or password".to_string(),
+            )),
+            StatusCode::INSUFFICIENT_STORAGE => Err(AppError::StorageFull(
+                "The WebDAV server is out of space".to_string(),
+            )),
+            _ => Ok(response),
+        }
+    }
+
+    async fn make_folder(&self, path: &str) -> AppResult<()> {
+        let method = Method::from_bytes(b"MKCOL").map_err(|e| AppError::Internal(e.to_string()))?;
+        let response = self.send(self.request(method, path)).await?;
+        // 405 means it already exists
+        match response.status() {
+            status if status.is_success() || status == StatusCode::METHOD_NOT_ALLOWED => Ok(()),
+            status => Err(AppError::Unavailable(format!("Failed to create WebDAV folder /{}: HTTP {}", path, status))),
+        }
+    }
+
+    // The file and its ETag, None if it doesn't exist
+    async fn get(&self, path: &str) -> AppResult<Option<(Vec<u8>, Option<String>)>> {
+        let response = self.send(self.request(Method::GET, path)).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let etag = response
+                    .headers()
+                    .get(header::ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(str::to_string);
+                let bytes = response.bytes().await.with_context(|| format!("Failed to download /{}", path))?;
+                Ok(Some((bytes.to_vec(), etag)))
+            }
+            status => Err(AppError::Unavailable(format!("Failed to download /{}: HTTP {}", path, status))),
+        }
+    }
+
+    // False if the condition didn't hold
+    async fn put(&self, path: &str, body: Vec<u8>, condition: Option<Condition<'_>>) -> AppResult<bool> {
+        let request = match condition {
+            Some(Condition::IfMatch(etag)) => self.request(Method::PUT, path).header(header::IF_MATCH, etag),
+            Some(Condition::IfNoneMatch) => self.request(Method::PUT, path).header(header::IF_NONE_MATCH, "*"),
+            None => self.request(Method::PUT, path),
+        };
+        let response = self.send(request.body(body)).await?;
+        match response.status() {
+            StatusCode::PRECONDITION_FAILED => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(AppError::Unavailable(format!("Failed to upload /{}: HTTP {}", path, status))),
+        }
+    }
+
+    async fn delete(&self, path: &str) -> AppResult<()> {
+        let response = self.send(self.request(Method::DELETE, path)).await?;
+        match response.status() {
+            status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            status => Err(AppError::Unavailable(format!("Failed to delete /{}: HTTP {}", path, status))),
+        }
+    }
+}
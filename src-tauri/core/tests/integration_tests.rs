@@ -0,0 +1,961 @@
+use notura_core::NoteStore;
+use notura_core::secrets::SecretPolicy;
+use tempfile::TempDir;
+
+// Opens a real database file the same way the app does
+async fn create_test_store() -> (NoteStore, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let store = NoteStore::open(dir.path()).await.unwrap();
+    (store, dir)
+}
+
+#[tokio::test]
+async fn test_create_note() {
+    let (store, _dir) = create_test_store().await;
+    
+    let title = "Test Note".to_string();
+    let content = "This is a test note with some content.".to_string();
+    let collection_id = None;
+    
+    let note = store.create_note(&title, &content, collection_id).await.unwrap();
+    
+    assert_eq!(note.title, title);
+    assert_eq!(note.content, content);
+    assert_eq!(note.word_count, 8);
+    assert_eq!(note.character_count, 38);
+    assert!(!note.is_archived);
+    
+    store.close().await;
+}
+
+#[tokio::test]
+async fn test_update_note() {
+    let (store, _dir) = create_test_store().await;
+    
+    let note = store.create_note("Original Title", "Original content", None).await.unwrap();
+    
+    let new_content = "Updated content with more words".to_string();
+    let updated_note = store.update_note(&note.id, &new_content).await.unwrap();
+    
+    assert_eq!(updated_note.content, new_content);
+    assert_eq!(updated_note.word_count, 5);
+    assert_eq!(updated_note.character_count, 31);
+    assert!(updated_note.updated_at > note.updated_at);
+    
+    store.close().await;
+}
+
+#[tokio::test]
+async fn test_word_and_character_counting() {
+    let (store, _dir) = create_test_store().await;
+    
+    // Test with markdown content
+    let markdown_content = r#"# Heading
+
+This is a **bold** text with `code` and [link](http://example.com).
+
+- List item 1
+- List item 2
+
+```rust
+fn hello() {
+    println!("Hello, world!");
+}
+```"#;
+    
+    let note = store.create_note("Markdown Test", markdown_content, None).await.unwrap();
+    
+    // Should count all words including markdown syntax
+    assert!(note.word_count > 0);
+    assert!(note.character_count > 0);
+    assert_eq!(note.character_count, markdown_content.chars().count() as i32);
+    
+    store.close().await;
+}
+
+#[tokio::test]
+async fn test_content_sanitization() {
+    let (store, _dir) = create_test_store().await;
+    
+    // Test with content that needs sanitization
+    let dirty_content = "Line 1\r\nLine 2\rLine 3\nLine 4\0Null byte";
+    let expected_clean = "Line 1\nLine 2\nLine 3\nLine 4Null byte";
+    
+    let note = store.create_note("Sanitization Test", dirty_content, None).await.unwrap();
+    
+    assert_eq!(note.content, expected_clean);
+    
+    store.close().await;
+}
+
+#[tokio::test]
+async fn test_export_functionality() {
+    let (store, _dir) = create_test_store().await;
+    
+    // Create test notes
+    let note1 = store.create_note("Note 1", "Content of note 1", None).await.unwrap();
+    let note2 = store.create_note("Note 2", "Content of note 2", None).await.unwrap();
+    
+    // Test markdown export
+    let note_ids = vec![note1.id, note2.id];
+    let markdown = store.export_notes("markdown", &note_ids, SecretPolicy::Block).await.unwrap().output;
+    
+    assert!(markdown.contains("# Note 1"));
+    assert!(markdown.contains("# Note 2"));
+    assert!(markdown.contains("Content of note 1"));
+    assert!(markdown.contains("Content of note 2"));
+    
+    store.close().await;
+}
+#[tokio::test]
+async fn test_two_processes_share_the_database() {
+    // Two stores over the same directory stand in for the app and the CLI
+    let (app, dir) = create_test_store().await;
+    let cli = NoteStore::open(dir.path()).await.unwrap();
+    
+    let write_notes = |store: NoteStore, prefix: &'static str| async move {
+        for i in 0..20 {
+            store.create_note(&format!("{} {}", prefix, i), "Written concurrently", None).await.unwrap();
+        }
+    };
+    tokio::join!(write_notes(app.clone(), "App"), write_notes(cli.clone(), "CLI"));
+    
+    assert_eq!(app.get_all_notes().await.unwrap().len(), 40);
+    assert_eq!(cli.search_notes("concurrently", None).await.unwrap().len(), 40);
+    
+    cli.close().await;
+    app.close().await;
+}
+
+// Minimal HTTP/1.1 client, so the test goes through the real loopback socket
+async fn http(
+    addr: std::net::SocketAddr,
+    request: &str,
+    token: Option<&str>,
+    content_type: &str,
+    body: &[u8],
+) -> (u16, serde_json::Value) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut head = format!(
+        "{} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+        request,
+        addr.port(),
+        content_type,
+        body.len()
+    );
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap_or(serde_json::Value::Null))
+}
+
+#[tokio::test]
+async fn test_http_api_requires_token_and_serves_notes() {
+    use notura_core::app_lock::AppLock;
+    use notura_core::http_api::ApiServer;
+    use notura_core::settings::HttpApiSettings;
+    
+    let (store, _dir) = create_test_store().await;
+    let collection = store.create_collection("Inbox", None, None).await.unwrap();
+    let mut settings = HttpApiSettings::load(store.pool()).await.unwrap();
+    assert!(!settings.enabled);
+    assert_eq!(settings.token.len(), 64);
+    settings.port = 0;
+    
+    let lock = std::sync::Arc::new(AppLock::default());
+    let server = ApiServer::start(store.clone(), lock.clone(), &settings).await.unwrap();
+    let addr = server.addr();
+    assert!(addr.ip().is_loopback());
+    let token = Some(settings.token.as_str());
+    
+    // The description is public, everything else needs the token
+    let (status, openapi) = http(addr, "GET /api/v1/openapi.json", None, "text/plain", b"").await;
+    assert_eq!(status, 200);
+    assert!(openapi["paths"]["/notes/{id}"].is_object());
+    let (status, error) = http(addr, "GET /api/v1/notes", None, "text/plain", b"").await;
+    assert_eq!((status, error["code"].as_str()), (401, Some("unauthorized")));
+    let (status, _) = http(addr, "GET /api/v1/notes", Some("wrong"), "text/plain", b"").await;
+    assert_eq!(status, 401);
+    
+    let body = serde_json::json!({
+        "title": "From a launcher",
+        "content": "quick capture via http",
+        "collection_id": collection.id,
+        "tags": ["inbox"]
+    });
+    let (status, note) = http(addr, "POST /api/v1/notes", token, "application/json", body.to_string().as_bytes()).await;
+    assert_eq!(status, 201);
+    let id = note["id"].as_str().unwrap().to_string();
+    assert_eq!(note["tags"], "[\"inbox\"]");
+    
+    let (status, notes) = http(addr, "GET /api/v1/notes?tag=inbox", token, "text/plain", b"").await;
+    assert_eq!((status, notes.as_array().unwrap().len()), (200, 1));
+    let (_, results) = http(addr, "GET /api/v1/search?q=capture", token, "text/plain", b"").await;
+    assert_eq!(results[0]["note_id"], id.as_str());
+    let (_, collections) = http(addr, "GET /api/v1/collections", token, "text/plain", b"").await;
+    assert_eq!(collections[0]["name"], "Inbox");
+    
+    // A stale `if_updated_at` is refused instead of overwriting
+    let update = serde_json::json!({ "content": "edited", "if_updated_at": note["updated_at"] });
+    let path = format!("PATCH /api/v1/notes/{}", id);
+    let (status, updated) = http(addr, &path, token, "application/json", update.to_string().as_bytes()).await;
+    assert_eq!((status, updated["content"].as_str()), (200, Some("edited")));
+    let (status, error) = http(addr, &path, token, "application/json", update.to_string().as_bytes()).await;
+    assert_eq!((status, error["code"].as_str()), (409, Some("conflict")));
+    let (status, error) = http(addr, &path, token, "application/json", b"{not json").await;
+    assert_eq!((status, error["code"].as_str()), (400, Some("validation")));
+    let (status, error) = http(addr, "GET /api/v1/notes/missing", token, "text/plain", b"").await;
+    assert_eq!((status, error["code"].as_str()), (404, Some("not_found")));
+    
+    let mut png = Vec::new();
+    image::RgbImage::new(2, 2)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    let path = format!("POST /api/v1/images?name=dot.png&note_id={}", id);
+    let (status, image) = http(addr, &path, token, "image/png", &png).await;
+    assert_eq!((status, image["mime_type"].as_str()), (201, Some("image/png")));
+    assert_eq!(store.get_images_for_note(&id).await.unwrap().len(), 1);
+    let (status, _) = http(addr, "POST /api/v1/images?name=fake.png", token, "image/png", b"not an image").await;
+    assert_eq!(status, 400);
+    
+    // A note unlocked in the app stays locked through the API
+    let secret = store.create_note("Secret", "the safe code is 1234", None).await.unwrap();
+    store.encrypt_note(&secret.id, "tin can phone").await.unwrap();
+    assert_eq!(store.unlock_note(&secret.id, "tin can phone").await.unwrap().content, "the safe code is 1234");
+    let path = format!("GET /api/v1/notes/{}", secret.id);
+    let (status, served) = http(addr, &path, token, "text/plain", b"").await;
+    assert_eq!((status, served["is_encrypted"].as_bool()), (200, Some(true)));
+    assert!(!served["content"].as_str().unwrap().contains("safe code"));
+    let path = format!("PATCH /api/v1/notes/{}", secret.id);
+    let update = serde_json::json!({ "content": "overwritten over http" });
+    let (status, error) = http(addr, &path, token, "application/json", update.to_string().as_bytes()).await;
+    assert_eq!((status, error["code"].as_str()), (423, Some("locked")));
+    assert_eq!(store.read_note(&secret.id).await.unwrap().content, "the safe code is 1234");
+    
+    // Nothing is served while the app is locked
+    lock.set_password(store.pool(), None, "correct horse").await.unwrap();
+    lock.lock().unwrap();
+    let (status, error) = http(addr, "GET /api/v1/notes", token, "text/plain", b"").await;
+    assert_eq!((status, error["code"].as_str()), (423, Some("locked")));
+    let (status, _) = http(addr, "GET /api/v1/search?q=capture", token, "text/plain", b"").await;
+    assert_eq!(status, 423);
+    lock.unlock(store.pool(), "correct horse").await.unwrap();
+    let (status, _) = http(addr, "GET /api/v1/notes", token, "text/plain", b"").await;
+    assert_eq!(status, 200);
+    
+    server.stop().await;
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    store.close().await;
+}
+
+#[tokio::test]
+async fn test_mirror_picks_up_external_edits() {
+    use notura_core::mirror::MirrorHandle;
+    
+    let (store, dir) = create_test_store().await;
+    let note = store.create_note("Journal", "Day one", None).await.unwrap();
+    let root = dir.path().join("mirror");
+    let mirror = MirrorHandle::start(store.clone(), root.clone()).await.unwrap();
+    
+    let file = root.join("Journal.md");
+    let text = std::fs::read_to_string(&file).unwrap();
+    std::fs::write(&file, text.replace("Day one", "Day one, edited in vim")).unwrap();
+    
+    // The watcher syncs on its own; give it a few seconds
+    let mut content = String::new();
+    for _ in 0..50 {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        content = store.get_note(&note.id).await.unwrap().content;
+        if content.contains("edited") {
+            break;
+        }
+    }
+    assert_eq!(content.trim_end(), "Day one, edited in vim");
+    
+    // And changes made in the app show up on disk
+    store.update_note(&note.id, "Day two").await.unwrap();
+    let mirrored = mirror.sync_now().await.unwrap();
+    assert!(mirrored.conflicts.is_empty());
+    assert!(std::fs::read_to_string(&file).unwrap().contains("Day two"));
+    
+    mirror.stop().await;
+    store.close().await;
+}
+
+#[tokio::test]
+async fn test_git_sync_between_two_vaults() {
+    use notura_core::git_sync;
+    use notura_core::settings::GitSyncSettings;
+    
+    let remote = TempDir::new().unwrap();
+    let status = std::process::Command::new("git")
+        .args(["init", "-q", "--bare"])
+        .arg(remote.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let settings = GitSyncSettings {
+        enabled: false,
+        path: String::new(),
+        remote: remote.path().to_string_lossy().to_string(),
+        branch: "main".to_string(),
+        interval_minutes: 60,
+        allow_unencrypted: false,
+    };
+    
+    let (laptop, _laptop_dir) = create_test_store().await;
+    let (desktop, _desktop_dir) = create_test_store().await;
+    
+    let work = laptop.create_collection("Work", None, None).await.unwrap();
+    let plan = laptop.create_note("Plan", "Draft", Some(&work.id)).await.unwrap();
+    let report = git_sync::sync(&laptop, &settings).await.unwrap();
+    assert_eq!(report.commits[0].message, "Add Plan");
+    assert!(report.pushed);
+    
+    // The other vault gets the note, under the same id and collection
+    let report = git_sync::sync(&desktop, &settings).await.unwrap();
+    assert_eq!(report.created, 1);
+    let pulled = desktop.get_note(&plan.id).await.unwrap();
+    assert_eq!(pulled.content.trim_end(), "Draft");
+    assert_eq!(desktop.get_collection(pulled.collection_id.as_deref().unwrap()).await.unwrap().name, "Work");
+    
+    // Syncing again with nothing new is quiet
+    let report = git_sync::sync(&laptop, &settings).await.unwrap();
+    assert!(report.commits.is_empty());
+    assert!(report.conflicts.is_empty());
+    
+    // Edits on one side arrive on the other
+    laptop.update_note(&plan.id, "Final").await.unwrap();
+    git_sync::sync(&laptop, &settings).await.unwrap();
+    let report = git_sync::sync(&desktop, &settings).await.unwrap();
+    assert_eq!(report.imported, 1);
+    assert_eq!(desktop.get_note(&plan.id).await.unwrap().content.trim_end(), "Final");
+    
+    // Both sides edit the same note: each keeps its own text, with the other
+    // side's version saved as a conflict copy
+    laptop.update_note(&plan.id, "Laptop wins").await.unwrap();
+    desktop.update_note(&plan.id, "Desktop wins").await.unwrap();
+    git_sync::sync(&laptop, &settings).await.unwrap();
+    let report = git_sync::sync(&desktop, &settings).await.unwrap();
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].note_id, plan.id);
+    assert_eq!(desktop.get_note(&plan.id).await.unwrap().content.trim_end(), "Desktop wins");
+    let copy = desktop.data_dir().join("git/notes").join(&report.conflicts[0].copy_path);
+    assert!(std::fs::read_to_string(copy).unwrap().contains("Laptop wins"));
+    
+    // Deleting a note on one side deletes it on the other
+    git_sync::sync(&laptop, &settings).await.unwrap();
+    laptop.delete_note(&plan.id).await.unwrap();
+    let report = git_sync::sync(&laptop, &settings).await.unwrap();
+    assert_eq!(report.commits[0].message, "Delete Plan");
+    let report = git_sync::sync(&desktop, &settings).await.unwrap();
+    assert_eq!(report.deleted, 1);
+    assert!(desktop.get_note(&plan.id).await.is_err());
+    
+    // Notes with credentials are not committed until they are cleaned up
+    let keys = laptop.create_note("Keys", "api_key = abcdefghijklmnop1234", None).await.unwrap();
+    let error = git_sync::sync(&laptop, &settings).await.unwrap_err();
+    assert_eq!(error.code(), "validation");
+    assert!(error.to_string().contains("Keys"));
+    laptop.update_note(&keys.id, "in the password manager").await.unwrap();
+    let report = git_sync::sync(&laptop, &settings).await.unwrap();
+    assert_eq!(report.commits[0].message, "Add Keys");
+    
+    laptop.close().await;
+    desktop.close().await;
+}
+
+// In-memory WebDAV server with just what sync uses: GET, PUT (with If-Match
+// and If-None-Match), DELETE and MKCOL, behind basic auth
+async fn start_webdav_server(username: &str, password: &str) -> std::net::SocketAddr {
+    use axum::body::Bytes;
+    use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+    use axum::response::IntoResponse;
+    use base64::Engine;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    
+    #[derive(Default)]
+    struct Files {
+        files: HashMap<String, (Vec<u8>, u64)>,
+        folders: Vec<String>,
+        version: u64,
+    }
+    
+    let files = Arc::new(Mutex::new(Files::default()));
+    let credentials = format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password))
+    );
+    
+    let handler = move |method: Method, uri: Uri, headers: HeaderMap, body: Bytes| {
+        let files = files.clone();
+        let credentials = credentials.clone();
+        async move {
+            if headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()) != Some(credentials.as_str()) {
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            let path = uri.path().trim_end_matches('/').to_string();
+            let mut files = files.lock().unwrap();
+            let etag = |version: u64| format!("\"{}\"", version);
+            
+            match method.as_str() {
+                "MKCOL" if files.folders.contains(&path) => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+                "MKCOL" => {
+                    files.folders.push(path);
+                    StatusCode::CREATED.into_response()
+                }
+                "GET" => match files.files.get(&path) {
+                    Some((data, version)) => ([(header::ETAG, etag(*version))], data.clone()).into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                },
+                "PUT" => {
+                    let current = files.files.get(&path).map(|(_, version)| etag(*version));
+                    let if_match = headers.get(header::IF_MATCH).and_then(|value| value.to_str().ok());
+                    let if_none_match = headers.get(header::IF_NONE_MATCH).is_some();
+                    if if_match.is_some_and(|expected| current.as_deref() != Some(expected))
+                        || (if_none_match && current.is_some())
+                    {
+                        return StatusCode::PRECONDITION_FAILED.into_response();
+                    }
+                    files.version += 1;
+                    let version = files.version;
+                    files.files.insert(path, (body.to_vec(), version));
+                    StatusCode::CREATED.into_response()
+                }
+                "DELETE" => match files.files.remove(&path) {
+                    Some(_) => StatusCode::NO_CONTENT.into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                },
+                _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+            }
+        }
+    };
+    
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, axum::Router::new().fallback(handler)).await.unwrap();
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_webdav_sync_between_two_vaults() {
+    use notura_core::settings::WebDavSettings;
+    use notura_core::webdav_sync;
+    
+    let addr = start_webdav_server("sam", "secret").await;
+    let settings = |device: &str| WebDavSettings {
+        enabled: false,
+        url: format!("http://{}/dav/notura/", addr),
+        username: "sam".to_string(),
+        password: "secret".to_string(),
+        interval_minutes: 15,
+        device_name: device.to_string(),
+        allow_unencrypted: false,
+    };
+    let (laptop, _laptop_dir) = create_test_store().await;
+    let (desktop, _desktop_dir) = create_test_store().await;
+    
+    let work = laptop.create_collection("Work", None, None).await.unwrap();
+    let plan = laptop.create_note("Plan", "Draft", Some(&work.id)).await.unwrap();
+    laptop.set_tags(&plan.id, &["q3".to_string()]).await.unwrap();
+    let image = {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::new(4, 4));
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut buffer, image::ImageFormat::Png).unwrap();
+        laptop.save_image(&buffer.into_inner(), "map.png", "image/png", Some(&plan.id)).await.unwrap()
+    };
+    let report = webdav_sync::sync(&laptop, &settings("laptop")).await.unwrap();
+    assert_eq!(report.pushed, 3);
+    
+    // The other vault gets the note with its collection, tags and image
+    let report = webdav_sync::sync(&desktop, &settings("desktop")).await.unwrap();
+    assert_eq!(report.pulled, 3);
+    let pulled = desktop.get_note(&plan.id).await.unwrap();
+    assert_eq!(pulled.content, "Draft");
+    assert_eq!(pulled.collection_id.as_deref(), Some(work.id.as_str()));
+    assert_eq!(pulled.tags, r#"["q3"]"#);
+    assert_eq!(desktop.get_images_for_note(&plan.id).await.unwrap()[0].id, image.id);
+    assert_eq!(
+        desktop.get_image(&image.id).await.unwrap().data_url,
+        laptop.get_image(&image.id).await.unwrap().data_url
+    );
+    
+    // Nothing changed, nothing moves
+    let report = webdav_sync::sync(&laptop, &settings("laptop")).await.unwrap();
+    assert_eq!((report.pushed, report.pulled), (0, 0));
+    
+    // Edited on both: the later edit wins everywhere and the other is kept as a copy
+    laptop.update_note(&plan.id, "Laptop version").await.unwrap();
+    desktop.update_note(&plan.id, "Desktop version").await.unwrap();
+    webdav_sync::sync(&laptop, &settings("laptop")).await.unwrap();
+    let report = webdav_sync::sync(&desktop, &settings("desktop")).await.unwrap();
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].kept, "local");
+    let copy_id = report.conflicts[0].copy_id.clone().unwrap();
+    webdav_sync::sync(&laptop, &settings("laptop")).await.unwrap();
+    for store in [&laptop, &desktop] {
+        assert_eq!(store.get_note(&plan.id).await.unwrap().content, "Desktop version");
+        let copy = store.get_note(&copy_id).await.unwrap();
+        assert_eq!(copy.title, "Plan (conflict from laptop)");
+        assert_eq!(copy.content, "Laptop version");
+    }
+    
+    // Deletions travel as tombstones
+    desktop.delete_note(&copy_id).await.unwrap();
+    webdav_sync::sync(&desktop, &settings("desktop")).await.unwrap();
+    webdav_sync::sync(&laptop, &settings("laptop")).await.unwrap();
+    assert!(laptop.get_note(&copy_id).await.is_err());
+    
+    // An edit beats a deletion made elsewhere
+    laptop.delete_note(&plan.id).await.unwrap();
+    desktop.update_note(&plan.id, "Still needed").await.unwrap();
+    webdav_sync::sync(&laptop, &settings("laptop")).await.unwrap();
+    webdav_sync::sync(&desktop, &settings("desktop")).await.unwrap();
+    webdav_sync::sync(&laptop, &settings("laptop")).await.unwrap();
+    assert_eq!(laptop.get_note(&plan.id).await.unwrap().content, "Still needed");
+    
+    // Notes with credentials stay here until they are cleaned up
+    let keys = desktop.create_note("Keys", "api_key = abcdefghijklmnop1234", None).await.unwrap();
+    let error = webdav_sync::sync(&desktop, &settings("desktop")).await.unwrap_err();
+    assert_eq!(error.code(), "validation");
+    desktop.delete_note(&keys.id).await.unwrap();
+    webdav_sync::sync(&desktop, &settings("desktop")).await.unwrap();
+    
+    // Once synced, the change log and tombstones are only dead weight
+    let count = async |table: &str| {
+        let query = format!("SELECT COUNT(*) FROM {}", table);
+        sqlx::query_as::<_, (i64,)>(&query).fetch_one(desktop.pool()).await.unwrap().0
+    };
+    assert_eq!(count("tombstones").await, 2);
+    webdav_sync::trim_sync_state(desktop.pool()).await.unwrap();
+    assert_eq!((count("change_log").await, count("tombstones").await), (0, 0));
+    let report = webdav_sync::sync(&desktop, &settings("desktop")).await.unwrap();
+    assert_eq!((report.pushed, report.pulled), (0, 0));
+    assert!(desktop.get_note(&copy_id).await.is_err());
+    
+    let mut wrong = settings("laptop");
+    wrong.password = "guess".to_string();
+    let error = webdav_sync::sync(&laptop, &wrong).await.unwrap_err();
+    assert_eq!(error.code(), "unauthorized");
+    
+    laptop.close().await;
+    desktop.close().await;
+}
+
+// Wait for a note to reach the expected text, as collaboration is asynchronous
+async fn wait_for_content(store: &NoteStore, id: &str, expected: &str) {
+    for _ in 0..100 {
+        if store.get_note(id).await.map(|note| note.content == expected).unwrap_or(false) {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("note {} never became {:?}, it is {:?}", id, expected, store.get_note(id).await.map(|note| note.content));
+}
+
+#[tokio::test]
+async fn test_collaborators_co_edit_a_note() {
+    use notura_core::app_lock::AppLock;
+    use notura_core::collab::{CollabServer, CollabSession};
+    use notura_core::documents::TextEdit;
+    use notura_core::settings::CollabSettings;
+    
+    let (host, _host_dir) = create_test_store().await;
+    let (guest, _guest_dir) = create_test_store().await;
+    let note = host.create_note("Meeting", "Agenda\n", None).await.unwrap();
+    
+    let mut settings = CollabSettings::load(host.pool()).await.unwrap();
+    settings.port = 0;
+    let lock = std::sync::Arc::new(AppLock::default());
+    let server = CollabServer::start(host.clone(), lock.clone(), &settings).await.unwrap();
+    let address = format!("127.0.0.1:{}", server.addr().port());
+    
+    let error = CollabSession::join(guest.clone(), &address, "guess", &note.id).await.err().unwrap();
+    assert_eq!(error.code(), "unauthorized");
+    
+    // The guest does not have the note yet and gets it from the host
+    let session = CollabSession::join(guest.clone(), &address, &settings.token, &note.id).await.unwrap();
+    wait_for_content(&guest, &note.id, "Agenda\n").await;
+    assert_eq!(guest.get_note(&note.id).await.unwrap().title, "Meeting");
+    
+    // Edits on either side show up on the other as they are made
+    let edits = [TextEdit { index: 0, delete: 0, insert: "Budget review\n".to_string() }];
+    host.edit_note(&note.id, &edits).await.unwrap();
+    wait_for_content(&guest, &note.id, "Budget review\nAgenda\n").await;
+    guest.update_note(&note.id, "Budget review\nAgenda\n- hiring\n").await.unwrap();
+    wait_for_content(&host, &note.id, "Budget review\nAgenda\n- hiring\n").await;
+    
+    assert!(session.is_connected());
+    session.leave().await.unwrap();
+    
+    // Edits made apart from each other merge when the guest joins again
+    host.update_note(&note.id, "Budget review\nAgenda (Friday)\n- hiring\n").await.unwrap();
+    guest.update_note(&note.id, "Budget review\nAgenda\n- hiring\n- offsite\n").await.unwrap();
+    let session = CollabSession::join(guest.clone(), &address, &settings.token, &note.id).await.unwrap();
+    let merged = "Budget review\nAgenda (Friday)\n- hiring\n- offsite\n";
+    wait_for_content(&host, &note.id, merged).await;
+    wait_for_content(&guest, &note.id, merged).await;
+    session.leave().await.unwrap();
+    
+    let missing = CollabSession::join(host.clone(), &address, &settings.token, "no-such-note").await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(missing.leave().await.unwrap_err().code(), "not_found");
+    
+    // Locking the host closes its connections and refuses new ones
+    let session = CollabSession::join(guest.clone(), &address, &settings.token, &note.id).await.unwrap();
+    lock.set_password(host.pool(), None, "correct horse").await.unwrap();
+    lock.lock().unwrap();
+    for _ in 0..50 {
+        if !session.is_connected() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(!session.is_connected());
+    let error = CollabSession::join(guest.clone(), &address, &settings.token, &note.id).await.err().unwrap();
+    assert_eq!(error.code(), "locked");
+    
+    server.stop().await;
+    host.close().await;
+    guest.close().await;
+}
+
+// In-memory S3 bucket `backups` with just what remote backups use: multipart
+// uploads, listing, GET and DELETE. It checks the access key but not the
+// signature, and can be told to fail one part upload.
+#[derive(Default)]
+struct S3Bucket {
+    objects: std::collections::HashMap<String, Vec<u8>>,
+    uploads: std::collections::HashMap<String, std::collections::BTreeMap<u32, Vec<u8>>>,
+    next_upload: u32,
+    // Part numbers in the order their uploads arrived, failed ones included
+    part_uploads: Vec<u32>,
+    fail_part: Option<u32>,
+}
+
+async fn start_s3_server(access_key_id: &str) -> (std::net::SocketAddr, std::sync::Arc<std::sync::Mutex<S3Bucket>>) {
+    use axum::body::Bytes;
+    use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+    use axum::response::IntoResponse;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    
+    let bucket = Arc::new(Mutex::new(S3Bucket::default()));
+    let credential = format!("AWS4-HMAC-SHA256 Credential={}/", access_key_id);
+    let state = bucket.clone();
+    
+    let handler = move |method: Method, uri: Uri, headers: HeaderMap, body: Bytes| {
+        let bucket = state.clone();
+        let credential = credential.clone();
+        async move {
+            let authorization = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()).unwrap_or_default();
+            if !authorization.starts_with(&credential) {
+                return (StatusCode::FORBIDDEN, "<Error><Code>InvalidAccessKeyId</Code></Error>").into_response();
+            }
+            let query: HashMap<String, String> = uri
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(name, value)| (name.to_string(), value.replace("%2F", "/")))
+                .collect();
+            let Some(key) = uri.path().strip_prefix("/backups").map(|key| key.trim_start_matches('/').to_string()) else {
+                return (StatusCode::NOT_FOUND, "<Error><Code>NoSuchBucket</Code></Error>").into_response();
+            };
+            let mut bucket = bucket.lock().unwrap();
+            
+            match (method.as_str(), query.get("uploadId")) {
+                ("POST", None) if query.contains_key("uploads") => {
+                    bucket.next_upload += 1;
+                    let upload_id = format!("upload{}", bucket.next_upload);
+                    bucket.uploads.insert(upload_id.clone(), Default::default());
+                    format!("<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>", upload_id).into_response()
+                }
+                ("PUT", Some(upload_id)) => {
+                    let number: u32 = query["partNumber"].parse().unwrap();
+                    bucket.part_uploads.push(number);
+                    if bucket.fail_part == Some(number) {
+                        bucket.fail_part = None;
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                    let etag = format!("\"{}-{}\"", number, body.len());
+                    match bucket.uploads.get_mut(upload_id) {
+                        Some(parts) => {
+                            parts.insert(number, body.to_vec());
+                            [(header::ETAG, etag)].into_response()
+                        }
+                        None => (StatusCode::NOT_FOUND, "<Error><Code>NoSuchUpload</Code></Error>").into_response(),
+                    }
+                }
+                ("GET", Some(upload_id)) => match bucket.uploads.get(upload_id) {
+                    Some(parts) => {
+                        let parts: String = parts
+                            .iter()
+                            .map(|(number, data)| {
+                                format!(
+                                    "<Part><PartNumber>{0}</PartNumber><ETag>&quot;{0}-{1}&quot;</ETag><Size>{1}</Size></Part>",
+                                    number,
+                                    data.len()
+                                )
+                            })
+                            .collect();
+                        format!("<ListPartsResult><IsTruncated>false</IsTruncated>{}</ListPartsResult>", parts).into_response()
+                    }
+                    None => (StatusCode::NOT_FOUND, "<Error><Code>NoSuchUpload</Code></Error>").into_response(),
+                },
+                ("POST", Some(upload_id)) => {
+                    let parts = bucket.uploads.remove(upload_id).unwrap();
+                    let listed = String::from_utf8_lossy(&body).matches("<Part>").count();
+                    assert_eq!(listed, parts.len());
+                    bucket.objects.insert(key, parts.into_values().flatten().collect());
+                    "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".into_response()
+                }
+                ("GET", None) if query.contains_key("list-type") => {
+                    let prefix = query.get("prefix").cloned().unwrap_or_default();
+                    let contents: String = bucket
+                        .objects
+                        .iter()
+                        .filter(|(key, _)| key.starts_with(&prefix))
+                        .map(|(key, data)| format!("<Contents><Key>{}</Key><Size>{}</Size></Contents>", key, data.len()))
+                        .collect();
+                    format!("<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>", contents).into_response()
+                }
+                ("GET", None) => match bucket.objects.get(&key) {
+                    Some(data) => data.clone().into_response(),
+                    None => (StatusCode::NOT_FOUND, "<Error><Code>NoSuchKey</Code></Error>").into_response(),
+                },
+                ("DELETE", None) => {
+                    bucket.objects.remove(&key);
+                    StatusCode::NO_CONTENT.into_response()
+                }
+                _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+            }
+        }
+    };
+    
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let router = axum::Router::new().fallback(handler).layer(axum::extract::DefaultBodyLimit::disable());
+        axum::serve(listener, router).await.unwrap();
+    });
+    (addr, bucket)
+}
+
+#[tokio::test]
+async fn test_s3_backup_resumes_upload_and_restores_elsewhere() {
+    use notura_core::backup;
+    use notura_core::s3_backup;
+    use notura_core::settings::S3BackupSettings;
+    
+    let (addr, bucket) = start_s3_server("minio").await;
+    let settings = S3BackupSettings {
+        enabled: true,
+        endpoint: format!("http://{}", addr),
+        region: "us-east-1".to_string(),
+        bucket: "backups".to_string(),
+        prefix: "laptop".to_string(),
+        path_style: true,
+        access_key_id: "minio".to_string(),
+        secret_access_key: "minio-secret".to_string(),
+        passphrase: "correct horse battery staple".to_string(),
+        keep_daily: 7,
+        keep_weekly: 4,
+        keep_monthly: 12,
+    };
+    
+    let (laptop, laptop_dir) = create_test_store().await;
+    let note = laptop.create_note("Payroll", "Salaries for March", None).await.unwrap();
+    // Noise doesn't compress, so the archive takes two parts
+    let images = laptop_dir.path().join("images");
+    std::fs::create_dir_all(&images).unwrap();
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let noise: Vec<u8> = (0..6 * 1024 * 1024)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        })
+        .collect();
+    std::fs::write(images.join("noise.png"), &noise).unwrap();
+    let local = backup::create_backup(laptop.pool(), laptop_dir.path()).await.unwrap();
+    
+    // The second part fails; the retry only sends that one
+    bucket.lock().unwrap().fail_part = Some(2);
+    let error = s3_backup::upload_backup(laptop_dir.path(), &settings, &local.file_name).await.unwrap_err();
+    assert_eq!(error.code(), "unavailable");
+    let uploaded = s3_backup::upload_backup(laptop_dir.path(), &settings, &local.file_name).await.unwrap();
+    assert_eq!(uploaded.key, format!("laptop/{}.enc", local.file_name));
+    assert_eq!(bucket.lock().unwrap().part_uploads, vec![1, 2, 2]);
+    
+    // Only ciphertext reaches the bucket
+    let stored = bucket.lock().unwrap().objects[&uploaded.key].clone();
+    assert!(stored.starts_with(b"NTRENC01"));
+    assert!(!stored.windows(64).any(|window| window == &noise[..64]));
+    
+    let remote = s3_backup::list_remote_backups(&settings).await.unwrap();
+    assert_eq!(remote.len(), 1);
+    assert_eq!(remote[0].file_name, local.file_name);
+    assert_eq!(remote[0].size, stored.len() as u64);
+    
+    let wrong_key = S3BackupSettings { access_key_id: "someone".to_string(), ..settings.clone() };
+    let error = s3_backup::list_remote_backups(&wrong_key).await.unwrap_err();
+    assert_eq!(error.code(), "unauthorized");
+    
+    // Restore on another machine, which needs the passphrase
+    let (desktop, desktop_dir) = create_test_store().await;
+    let wrong_passphrase = S3BackupSettings { passphrase: "wrong".to_string(), ..settings.clone() };
+    let error = s3_backup::download_backup(desktop_dir.path(), &wrong_passphrase, &local.file_name).await.unwrap_err();
+    assert_eq!(error.code(), "unauthorized");
+    assert!(backup::list_backups(desktop_dir.path()).unwrap().is_empty());
+    
+    let downloaded = s3_backup::download_backup(desktop_dir.path(), &settings, &local.file_name).await.unwrap();
+    assert_eq!(downloaded.file_name, local.file_name);
+    backup::stage_restore(desktop_dir.path(), &downloaded.file_name, false).await.unwrap();
+    desktop.close().await;
+    assert!(backup::apply_pending_restore(desktop_dir.path()).unwrap());
+    let desktop = NoteStore::open(desktop_dir.path()).await.unwrap();
+    assert_eq!(desktop.get_note(&note.id).await.unwrap().content, "Salaries for March");
+    assert_eq!(std::fs::read(desktop_dir.path().join("images/noise.png")).unwrap(), noise);
+    
+    s3_backup::delete_remote_backup(&settings, &local.file_name).await.unwrap();
+    assert!(s3_backup::list_remote_backups(&settings).await.unwrap().is_empty());
+    
+    laptop.close().await;
+    desktop.close().await;
+}
+
+#[tokio::test]
+async fn test_encrypted_vault_migrates_and_changes_passphrase() {
+    use base64::Engine;
+    use notura_core::mirror::MirrorHandle;
+    use notura_core::settings::{GitSyncSettings, MirrorSettings};
+    use notura_core::{backup, git_sync, vault, AppError};
+    
+    let (store, dir) = create_test_store().await;
+    let note = store.create_note("Launch plan", "Codename falcon ships in May", None).await.unwrap();
+    let png = {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::new(4, 4));
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut buffer, image::ImageFormat::Png).unwrap();
+        buffer.into_inner()
+    };
+    let image = store.save_image(&png, "map.png", "image/png", Some(&note.id)).await.unwrap();
+    let data_url = format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png));
+    
+    // A backup and the copy a restore set aside hold the notes in plain text
+    let plain_backup = backup::create_backup(store.pool(), dir.path()).await.unwrap();
+    let pre_restore_dir = dir.path().join("pre-restore-20260101-000000");
+    std::fs::create_dir_all(pre_restore_dir.join("images")).unwrap();
+    sqlx::query("VACUUM INTO ?1")
+        .bind(pre_restore_dir.join("notura.db").to_string_lossy().to_string())
+        .execute(store.pool())
+        .await
+        .unwrap();
+    std::fs::copy(&image.file_path, pre_restore_dir.join("images/map.png")).unwrap();
+    
+    let error = vault::encrypt_vault(store.clone(), "short").await.err().unwrap();
+    assert_eq!(error.code(), "validation");
+    let store = vault::encrypt_vault(store, "correct horse").await.unwrap();
+    assert!(store.is_encrypted());
+    assert!(vault::is_encrypted(dir.path()));
+    
+    // Neither the text nor the image is readable on disk, in the vault or in
+    // the copies
+    let is_plain = |data: &[u8]| data.windows(6).any(|window| window == b"falcon") || data == &png[..];
+    assert!(!is_plain(&std::fs::read(dir.path().join("notura.db")).unwrap()));
+    assert!(!is_plain(&std::fs::read(&image.file_path).unwrap()));
+    for entry in std::fs::read_dir(backup::backups_dir(dir.path())).unwrap() {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(entry.unwrap().path()).unwrap()).unwrap();
+        for index in 0..archive.len() {
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut archive.by_index(index).unwrap(), &mut data).unwrap();
+            assert!(!is_plain(&data));
+        }
+    }
+    backup::verify_backup(std::path::Path::new(&plain_backup.path)).await.unwrap();
+    for file in ["notura.db", "images/map.png"] {
+        assert!(!is_plain(&std::fs::read(pre_restore_dir.join(file)).unwrap()));
+    }
+    
+    // While it is open, search and images work as before
+    let results = store.search_notes("falcon", None).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].note_id, note.id);
+    assert_eq!(store.get_image(&image.id).await.unwrap().data_url, data_url);
+    
+    // Plain copies of the notes are only written once allowed
+    let git = GitSyncSettings::load(store.pool()).await.unwrap();
+    let error = git_sync::sync(&store, &git).await.err().unwrap();
+    assert_eq!(error.code(), "validation");
+    let mirror_dir = dir.path().join("mirror");
+    let error = MirrorHandle::start(store.clone(), mirror_dir.clone()).await.err().unwrap();
+    assert!(error.to_string().contains("plain text"));
+    assert!(!mirror_dir.exists());
+    let mirror = MirrorSettings { allow_unencrypted: true, ..MirrorSettings::load(store.pool()).await.unwrap() };
+    mirror.save(store.pool()).await.unwrap();
+    MirrorHandle::start(store.clone(), mirror_dir.clone()).await.unwrap().stop().await;
+    assert!(mirror_dir.join("Launch plan.md").exists());
+    store.close().await;
+    
+    let error = AppError::from(NoteStore::open(dir.path()).await.err().unwrap());
+    assert_eq!(error.code(), "locked");
+    let error = NoteStore::open_encrypted(dir.path(), "wrong horse").await.err().unwrap();
+    assert_eq!(error.code(), "unauthorized");
+    
+    // A new passphrase re-encrypts the database but leaves the images be
+    let store = NoteStore::open_encrypted(dir.path(), "correct horse").await.unwrap();
+    let sealed_image = std::fs::read(&image.file_path).unwrap();
+    let error = vault::change_passphrase(store.clone(), "wrong horse", "battery staple").await.err().unwrap();
+    assert_eq!(error.code(), "unauthorized");
+    let store = vault::change_passphrase(store, "correct horse", "battery staple").await.unwrap();
+    assert_eq!(std::fs::read(&image.file_path).unwrap(), sealed_image);
+    store.close().await;
+    assert!(NoteStore::open_encrypted(dir.path(), "correct horse").await.is_err());
+    let store = NoteStore::open_encrypted(dir.path(), "battery staple").await.unwrap();
+    assert_eq!(store.get_note(&note.id).await.unwrap().content, "Codename falcon ships in May");
+    
+    // Backups hold the encrypted database and still verify
+    let archive = backup::create_backup(store.pool(), dir.path()).await.unwrap();
+    backup::verify_backup(std::path::Path::new(&archive.path)).await.unwrap();
+    
+    // Decrypting brings back the plain vault
+    let store = vault::decrypt_vault(store, "battery staple").await.unwrap();
+    assert!(!store.is_encrypted());
+    assert!(!vault::is_encrypted(dir.path()));
+    assert_eq!(std::fs::read(&image.file_path).unwrap(), png);
+    assert_eq!(store.search_notes("falcon", None).await.unwrap().len(), 1);
+    
+    store.close().await;
+}
+
+#[tokio::test]
+async fn test_damaged_database_is_not_taken_for_an_encrypted_vault() {
+    use notura_core::AppError;
+    
+    // A database whose header was overwritten goes to recovery, not to the
+    // passphrase prompt. Written directly: a closed pool may still checkpoint
+    // a real header back over a damaged one.
+    let dir = TempDir::new().unwrap();
+    let mut damaged = vec![0u8; 8192];
+    damaged[..16].copy_from_slice(b"garbage garbage!");
+    std::fs::write(dir.path().join("notura.db"), &damaged).unwrap();
+    let error = AppError::from(NoteStore::open(dir.path()).await.err().unwrap());
+    assert_eq!(error.code(), "database");
+    assert!(error.to_string().contains("damaged"));
+}
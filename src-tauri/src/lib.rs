@@ -1,33 +1,208 @@
-mod database;
-#[cfg(test)]
-mod test_utils;
-#[cfg(test)]
-mod api_tests;
-
-use database::Database;
+use notura_core::attachments::AttachmentMetadata;
+use notura_core::app_lock::{AppLock, LockStatus};
+use notura_core::backup::{self, BackupInfo, BackupManifest};
+use notura_core::collab::{CollabServer, CollabSession};
+use notura_core::documents::TextEdit;
+use notura_core::error::{AppError, AppResult, Context};
+use notura_core::file_grants::{FileGrant, FileGrants};
+use notura_core::git_sync::{self, GitSyncReport};
+use notura_core::http_api::ApiServer;
+use notura_core::image_editing::ImageEditRequest;
+use notura_core::maintenance::{self, MaintenanceOptions, MaintenanceReport};
+use notura_core::mirror::{MirrorHandle, MirrorReport};
+use notura_core::recovery::{self, RepairReport};
+use notura_core::s3_backup::{self, RemoteBackup};
+use notura_core::secrets::{CheckedExport, NoteFindings, SecretPolicy};
+use notura_core::vault;
+use notura_core::settings::{BackupSettings, CollabSettings, GitSyncSettings, HttpApiSettings, LockSettings, MirrorSettings, S3BackupSettings, UploadSettings, WebDavSettings};
+use notura_core::webdav_sync::{self, WebDavSyncReport};
+use notura_core::{Collection, ImageMetadata, ImageWithData, Note, NoteStore, SearchFilters, SearchResult, ShredResult, StorageInfo};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tauri::{AppHandle, Manager, State};
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_dialog::DialogExt;
 
-// Application state to hold database connection
-pub struct AppState {
-    pub db: Arc<Database>,
+// How long commands wait for the database on startup before giving up
+const DB_READY_TIMEOUT: Duration = Duration::from_secs(30);
+// How often the idle lock is checked
+const LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+// Lifecycle of the database connection
+enum DbState {
+    Initializing,
+    Ready(Arc<NoteStore>),
+    // The vault is encrypted and waits for its passphrase
+    Encrypted,
+    // Opening failed, the app is in recovery mode
+    Failed(String),
 }
 
-// Utility functions
-fn count_words(text: &str) -> i32 {
-    text.split_whitespace().count() as i32
+#[derive(Clone, serde::Serialize)]
+struct AppStatus {
+    status: &'static str,
+    error: Option<String>,
 }
 
-fn count_characters(text: &str) -> i32 {
-    text.chars().count() as i32
+// Application state to hold database connection. It is managed from the start
+// so commands never hit an unmanaged state; they wait in `store()` instead.
+pub struct AppState {
+    db: watch::Sender<DbState>,
+    // The local HTTP API, while it is enabled
+    http_api: Mutex<Option<ApiServer>>,
+    // The Markdown mirror, while it is enabled
+    mirror: Mutex<Option<MirrorHandle>>,
+    // The collaboration server, while it is enabled
+    collab: Mutex<Option<CollabServer>>,
+    // Notes being co-edited on other devices' servers, by note id
+    collab_sessions: Mutex<HashMap<String, CollabSession>>,
+    // The master password lock; commands get no store while it is locked
+    lock: Arc<AppLock>,
+    // Files picked for import, redeemed by token
+    import_grants: FileGrants,
+    // Databases picked in recovery mode, kept apart so an import token can't
+    // open a database
+    database_grants: FileGrants,
+    // Schedulers and other tasks holding the open store
+    tasks: std::sync::Mutex<Vec<tauri::async_runtime::JoinHandle<()>>>,
+}
+
+impl AppState {
+    fn new() -> Self {
+        AppState {
+            db: watch::Sender::new(DbState::Initializing),
+            http_api: Mutex::new(None),
+            mirror: Mutex::new(None),
+            collab: Mutex::new(None),
+            collab_sessions: Mutex::new(HashMap::new()),
+            lock: Arc::new(AppLock::default()),
+            import_grants: FileGrants::default(),
+            database_grants: FileGrants::default(),
+            tasks: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+    
+    // The open note store, waiting for it during startup. Rejects with the
+    // failure while the app is in recovery mode, and while it is locked.
+    pub async fn store(&self) -> AppResult<Arc<NoteStore>> {
+        let store = self.open_store().await?;
+        self.lock.check()?;
+        Ok(store)
+    }
+    
+    // The store even while the app is locked, for unlocking it
+    async fn open_store(&self) -> AppResult<Arc<NoteStore>> {
+        let mut receiver = self.db.subscribe();
+        let state = tokio::time::timeout(
+            DB_READY_TIMEOUT,
+            receiver.wait_for(|state| !matches!(state, DbState::Initializing)),
+        )
+        .await
+        .map_err(|_| AppError::Unavailable("The database is still starting, please try again".to_string()))?
+        .map_err(|_| AppError::Unavailable("The database is unavailable".to_string()))?;
+        
+        match &*state {
+            DbState::Ready(store) => Ok(store.clone()),
+            DbState::Encrypted => Err(AppError::Locked("The vault is encrypted, enter its passphrase".to_string())),
+            DbState::Failed(error) => Err(AppError::Unavailable(format!("The database is unavailable: {}", error))),
+            DbState::Initializing => unreachable!(),
+        }
+    }
+    
+    fn status(&self) -> AppStatus {
+        match &*self.db.borrow() {
+            DbState::Initializing => AppStatus { status: "initializing", error: None },
+            DbState::Ready(_) => AppStatus { status: "ready", error: None },
+            DbState::Encrypted => AppStatus { status: "encrypted", error: None },
+            DbState::Failed(error) => AppStatus { status: "failed", error: Some(error.clone()) },
+        }
+    }
+    
+    // Notes unlocked with their own password are locked with the app
+    fn lock_encrypted_notes(&self) {
+        if let DbState::Ready(store) = &*self.db.borrow() {
+            store.lock_notes();
+        }
+    }
+    
+    fn failure(&self) -> Option<String> {
+        match &*self.db.borrow() {
+            DbState::Failed(error) => Some(error.clone()),
+            _ => None,
+        }
+    }
+    
+    // Claim the failed state for a recovery action so two actions never run
+    // at once. Returns the original failure to fall back to.
+    fn begin_recovery(&self) -> AppResult<String> {
+        let mut previous = Err(AppError::Conflict {
+            message: "The database is not in recovery mode".to_string(),
+            id: None,
+        });
+        self.db.send_if_modified(|state| {
+            if let DbState::Failed(error) = state {
+                previous = Ok(error.clone());
+                *state = DbState::Initializing;
+                true
+            } else {
+                false
+            }
+        });
+        previous
+    }
+    
+    // Claim the encrypted state for an unlock attempt, like `begin_recovery`
+    fn begin_unlock(&self) -> AppResult<()> {
+        let claimed = self.db.send_if_modified(|state| {
+            if matches!(state, DbState::Encrypted) {
+                *state = DbState::Initializing;
+                true
+            } else {
+                false
+            }
+        });
+        if !claimed {
+            return Err(AppError::Conflict { message: "The vault is not waiting for its passphrase".to_string(), id: None });
+        }
+        Ok(())
+    }
+    
+    fn set(&self, state: DbState) {
+        self.db.send_replace(state);
+    }
+    
+    fn spawn_task(&self, task: impl std::future::Future<Output = ()> + Send + 'static) {
+        self.tasks.lock().unwrap().push(tauri::async_runtime::spawn(task));
+    }
+    
+    // Stop everything that holds the store, before its database is swapped:
+    // schedulers, servers, the mirror and collaboration sessions. Commands
+    // wait until the database is started again.
+    async fn stop_store_tasks(&self) {
+        self.set(DbState::Initializing);
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        if let Some(server) = self.http_api.lock().await.take() {
+            server.stop().await;
+        }
+        if let Some(server) = self.collab.lock().await.take() {
+            server.stop().await;
+        }
+        if let Some(mirror) = self.mirror.lock().await.take() {
+            mirror.stop().await;
+        }
+        for (_, session) in self.collab_sessions.lock().await.drain() {
+            let _ = session.leave().await;
+        }
+    }
 }
 
-fn sanitize_content(content: &str) -> String {
-    // Basic sanitization - remove null bytes and normalize line endings
-    content.replace('\0', "").replace("\r\n", "\n").replace('\r', "\n")
+// Utility functions
+fn app_data_dir(app_handle: &AppHandle) -> AppResult<std::path::PathBuf> {
+    app_handle.path().app_data_dir()
+        .map_err(|e| AppError::Io(format!("Failed to get app data dir: {}", e)))
 }
 
 // Note management commands
@@ -37,111 +212,45 @@ async fn create_note(
     content: String,
     collection_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<Note, String> {
-    let pool = state.db.pool();
-    let id = Uuid::new_v4().to_string();
-    let sanitized_content = sanitize_content(&content);
-    let word_count = count_words(&sanitized_content);
-    let character_count = count_characters(&sanitized_content);
-    let now = Utc::now();
-    let tags = "[]"; // Empty JSON array for tags
-    
-    let note = sqlx::query_as::<_, Note>(
-        r#"
-        INSERT INTO notes (id, title, content, collection_id, tags, created_at, updated_at, word_count, character_count, is_archived)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
-        RETURNING *
-        "#,
-    )
-    .bind(&id)
-    .bind(&title)
-    .bind(&sanitized_content)
-    .bind(&collection_id)
-    .bind(tags)
-    .bind(&now)
-    .bind(&now)
-    .bind(word_count)
-    .bind(character_count)
-    .bind(false)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| format!("Failed to create note: {}", e))?;
-    
-    Ok(note)
+) -> AppResult<Note> {
+    state.store().await?.create_note(&title, &content, collection_id.as_deref()).await
 }
 
+// With `edits`, only those are applied and `content` is ignored, so text a
+// collaborator added meanwhile is kept
 #[tauri::command]
 async fn update_note(
     id: String,
     content: String,
+    edits: Option<Vec<TextEdit>>,
     state: State<'_, AppState>,
-) -> Result<Note, String> {
-    let pool = state.db.pool();
-    let sanitized_content = sanitize_content(&content);
-    let word_count = count_words(&sanitized_content);
-    let character_count = count_characters(&sanitized_content);
-    let now = Utc::now();
-    
-    let note = sqlx::query_as::<_, Note>(
-        r#"
-        UPDATE notes 
-        SET content = ?1, updated_at = ?2, word_count = ?3, character_count = ?4
-        WHERE id = ?5
-        RETURNING *
-        "#,
-    )
-    .bind(&sanitized_content)
-    .bind(&now)
-    .bind(word_count)
-    .bind(character_count)
-    .bind(&id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| format!("Failed to update note: {}", e))?;
-    
-    Ok(note)
+) -> AppResult<Note> {
+    let store = state.store().await?;
+    match edits {
+        Some(edits) => store.edit_note(&id, &edits).await,
+        None => store.update_note(&id, &content).await,
+    }
 }
 
 #[tauri::command]
-async fn delete_note(id: String, state: State<'_, AppState>) -> Result<(), String> {
-    let pool = state.db.pool();
-    
-    let result = sqlx::query("DELETE FROM notes WHERE id = ?1")
-        .bind(&id)
-        .execute(pool)
-        .await
-        .map_err(|e| format!("Failed to delete note: {}", e))?;
-    
-    if result.rows_affected() == 0 {
-        return Err(format!("Note with id {} not found", id));
-    }
-    
-    Ok(())
+async fn delete_note(id: String, state: State<'_, AppState>) -> AppResult<()> {
+    state.store().await?.delete_note(&id).await
 }
 
+// Like `delete_note`, but also scrubs the text out of the database files
 #[tauri::command]
-async fn get_note(id: String, state: State<'_, AppState>) -> Result<Note, String> {
-    let pool = state.db.pool();
-    
-    let note = sqlx::query_as::<_, Note>("SELECT * FROM notes WHERE id = ?1")
-        .bind(&id)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to get note: {}", e))?;
-    
-    Ok(note)
+async fn shred_note(id: String, state: State<'_, AppState>) -> AppResult<ShredResult> {
+    state.store().await?.shred_note(&id).await
 }
 
 #[tauri::command]
-async fn get_all_notes(state: State<'_, AppState>) -> Result<Vec<Note>, String> {
-    let pool = state.db.pool();
-    
-    let notes = sqlx::query_as::<_, Note>("SELECT * FROM notes WHERE is_archived = FALSE ORDER BY updated_at DESC")
-        .fetch_all(pool)
-        .await
-        .map_err(|e| format!("Failed to get notes: {}", e))?;
-    
-    Ok(notes)
+async fn get_note(id: String, state: State<'_, AppState>) -> AppResult<Note> {
+    state.store().await?.read_note(&id).await
+}
+
+#[tauri::command]
+async fn get_all_notes(state: State<'_, AppState>) -> AppResult<Vec<Note>> {
+    state.store().await?.get_all_notes().await
 }
 
 // Collection management commands
@@ -151,52 +260,10 @@ async fn create_collection(
     description: Option<String>,
     parent_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<Collection, String> {
-    let pool = state.db.pool();
-    let id = Uuid::new_v4().to_string();
-    let now = Utc::now();
-    
-    // Get the next sort order for this parent
-    let sort_order = if let Some(ref parent) = parent_id {
-        let (max_order,): (Option<i32>,) = sqlx::query_as(
-            "SELECT MAX(sort_order) FROM collections WHERE parent_id = ?1"
-        )
-        .bind(parent)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to get sort order: {}", e))?;
-        
-        max_order.unwrap_or(0) + 1
-    } else {
-        let (max_order,): (Option<i32>,) = sqlx::query_as(
-            "SELECT MAX(sort_order) FROM collections WHERE parent_id IS NULL"
-        )
-        .fetch_one(pool)
+) -> AppResult<Collection> {
+    state.store().await?
+        .create_collection(&name, description.as_deref(), parent_id.as_deref())
         .await
-        .map_err(|e| format!("Failed to get sort order: {}", e))?;
-        
-        max_order.unwrap_or(0) + 1
-    };
-    
-    let collection = sqlx::query_as::<_, Collection>(
-        r#"
-        INSERT INTO collections (id, name, description, parent_id, sort_order, created_at, updated_at)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-        RETURNING *
-        "#,
-    )
-    .bind(&id)
-    .bind(&name)
-    .bind(&description)
-    .bind(&parent_id)
-    .bind(sort_order)
-    .bind(&now)
-    .bind(&now)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| format!("Failed to create collection: {}", e))?;
-    
-    Ok(collection)
 }
 
 #[tauri::command]
@@ -205,89 +272,23 @@ async fn update_collection(
     name: String,
     description: Option<String>,
     state: State<'_, AppState>,
-) -> Result<Collection, String> {
-    let pool = state.db.pool();
-    let now = Utc::now();
-    
-    let collection = sqlx::query_as::<_, Collection>(
-        r#"
-        UPDATE collections 
-        SET name = ?1, description = ?2, updated_at = ?3
-        WHERE id = ?4
-        RETURNING *
-        "#,
-    )
-    .bind(&name)
-    .bind(&description)
-    .bind(&now)
-    .bind(&id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| format!("Failed to update collection: {}", e))?;
-    
-    Ok(collection)
+) -> AppResult<Collection> {
+    state.store().await?.update_collection(&id, &name, description.as_deref()).await
 }
 
 #[tauri::command]
-async fn delete_collection(id: String, state: State<'_, AppState>) -> Result<(), String> {
-    let pool = state.db.pool();
-    
-    // Check if collection has child collections
-    let (child_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM collections WHERE parent_id = ?1")
-        .bind(&id)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to check child collections: {}", e))?;
-    
-    if child_count > 0 {
-        return Err("Cannot delete collection with child collections".to_string());
-    }
-    
-    // Move notes in this collection to no collection
-    sqlx::query("UPDATE notes SET collection_id = NULL WHERE collection_id = ?1")
-        .bind(&id)
-        .execute(pool)
-        .await
-        .map_err(|e| format!("Failed to update notes: {}", e))?;
-    
-    let result = sqlx::query("DELETE FROM collections WHERE id = ?1")
-        .bind(&id)
-        .execute(pool)
-        .await
-        .map_err(|e| format!("Failed to delete collection: {}", e))?;
-    
-    if result.rows_affected() == 0 {
-        return Err(format!("Collection with id {} not found", id));
-    }
-    
-    Ok(())
+async fn delete_collection(id: String, state: State<'_, AppState>) -> AppResult<()> {
+    state.store().await?.delete_collection(&id).await
 }
 
 #[tauri::command]
-async fn get_collection(id: String, state: State<'_, AppState>) -> Result<Collection, String> {
-    let pool = state.db.pool();
-    
-    let collection = sqlx::query_as::<_, Collection>("SELECT * FROM collections WHERE id = ?1")
-        .bind(&id)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to get collection: {}", e))?;
-    
-    Ok(collection)
+async fn get_collection(id: String, state: State<'_, AppState>) -> AppResult<Collection> {
+    state.store().await?.get_collection(&id).await
 }
 
 #[tauri::command]
-async fn get_all_collections(state: State<'_, AppState>) -> Result<Vec<Collection>, String> {
-    let pool = state.db.pool();
-    
-    let collections = sqlx::query_as::<_, Collection>(
-        "SELECT * FROM collections ORDER BY parent_id, sort_order"
-    )
-    .fetch_all(pool)
-    .await
-    .map_err(|e| format!("Failed to get collections: {}", e))?;
-    
-    Ok(collections)
+async fn get_all_collections(state: State<'_, AppState>) -> AppResult<Vec<Collection>> {
+    state.store().await?.get_all_collections().await
 }
 
 #[tauri::command]
@@ -295,286 +296,98 @@ async fn move_note_to_collection(
     note_id: String,
     collection_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let pool = state.db.pool();
-    let now = Utc::now();
-    
-    let result = sqlx::query(
-        "UPDATE notes SET collection_id = ?1, updated_at = ?2 WHERE id = ?3"
-    )
-    .bind(&collection_id)
-    .bind(&now)
-    .bind(&note_id)
-    .execute(pool)
-    .await
-    .map_err(|e| format!("Failed to move note: {}", e))?;
-    
-    if result.rows_affected() == 0 {
-        return Err(format!("Note with id {} not found", note_id));
-    }
-    
-    Ok(())
+) -> AppResult<()> {
+    state.store().await?.move_note_to_collection(&note_id, collection_id.as_deref()).await
 }
 
 // Search and filtering commands
 #[tauri::command]
 async fn search_notes(
     query: String,
-    _filters: Option<SearchFilters>,
+    filters: Option<SearchFilters>,
     state: State<'_, AppState>,
-) -> Result<Vec<SearchResult>, String> {
-    let pool = state.db.pool();
-    
-    if query.trim().is_empty() {
-        return Ok(vec![]);
-    }
-    
-    // For now, use a simpler approach with basic search (without complex filtering)
-    let search_results = sqlx::query_as::<_, (String, String, String, DateTime<Utc>, String, f64)>(
-        r#"
-        SELECT n.id, n.title, n.content, n.updated_at,
-               snippet(notes_fts, 1, '<mark>', '</mark>', '...', 32) as excerpt,
-               rank as relevance_score
-        FROM notes_fts 
-        JOIN notes n ON notes_fts.rowid = n.rowid
-        WHERE notes_fts MATCH ?1 AND n.is_archived = FALSE
-        ORDER BY rank
-        LIMIT 50
-        "#
-    )
-    .bind(&query)
-    .fetch_all(pool)
-    .await
-    .map_err(|e| format!("Failed to search notes: {}", e))?;
-    
-    let results = search_results
-        .into_iter()
-        .map(|(id, title, content, updated_at, excerpt, relevance_score)| {
-            // Extract highlights from the content
-            let highlights = extract_highlights(&content, &query);
-            
-            SearchResult {
-                note_id: id,
-                title: title.clone(),
-                excerpt: excerpt.clone(),
-                highlights,
-                relevance_score,
-                last_modified: updated_at,
-            }
-        })
-        .collect();
-    
-    Ok(results)
-}
-
-fn extract_highlights(content: &str, query: &str) -> Vec<String> {
-    let query_terms: Vec<&str> = query.split_whitespace().collect();
-    let mut highlights = Vec::new();
-    
-    for term in query_terms {
-        if content.to_lowercase().contains(&term.to_lowercase()) {
-            // Find context around the term
-            if let Some(pos) = content.to_lowercase().find(&term.to_lowercase()) {
-                let start = pos.saturating_sub(30);
-                let end = (pos + term.len() + 30).min(content.len());
-                let context = &content[start..end];
-                highlights.push(format!("...{}...", context));
-            }
-        }
-    }
-    
-    highlights
+) -> AppResult<Vec<SearchResult>> {
+    state.store().await?.search_notes(&query, filters.as_ref()).await
 }
 
 #[tauri::command]
-async fn get_recent_searches(_state: State<'_, AppState>) -> Result<Vec<String>, String> {
+async fn get_recent_searches(_state: State<'_, AppState>) -> AppResult<Vec<String>> {
     // For now, return empty array. In a full implementation, 
     // you'd store recent searches in a separate table
     Ok(vec![])
 }
 
 #[tauri::command]
-async fn save_recent_search(_query: String, _state: State<'_, AppState>) -> Result<(), String> {
+async fn save_recent_search(_query: String, _state: State<'_, AppState>) -> AppResult<()> {
     // For now, do nothing. In a full implementation,
     // you'd save the search query to a recent_searches table
     Ok(())
 }
 
 // File operations and storage management
+// Exports are scanned for secrets; without `secrets` one that has any is
+// refused
 #[tauri::command]
 async fn export_notes(
     format: String,
     note_ids: Vec<String>,
+    secrets: Option<SecretPolicy>,
     state: State<'_, AppState>,
-) -> Result<String, String> {
-    let pool = state.db.pool();
-    
-    if note_ids.is_empty() {
-        return Err("No notes selected for export".to_string());
-    }
-    
-    // Get the notes to export
-    let placeholders = note_ids.iter()
-        .enumerate()
-        .map(|(i, _)| format!("?{}", i + 1))
-        .collect::<Vec<_>>()
-        .join(", ");
-    
-    let query_str = format!("SELECT * FROM notes WHERE id IN ({}) ORDER BY created_at", placeholders);
-    let mut query = sqlx::query_as::<_, Note>(&query_str);
-    
-    for note_id in &note_ids {
-        query = query.bind(note_id);
-    }
-    
-    let notes = query
-        .fetch_all(pool)
-        .await
-        .map_err(|e| format!("Failed to fetch notes for export: {}", e))?;
-    
-    match format.as_str() {
-        "markdown" => export_as_markdown(notes),
-        "json" => export_as_json(notes),
-        _ => Err(format!("Unsupported export format: {}", format)),
-    }
+) -> AppResult<CheckedExport<String>> {
+    state.store().await?.export_notes(&format, &note_ids, secrets.unwrap_or_default()).await
 }
 
-fn export_as_markdown(notes: Vec<Note>) -> Result<String, String> {
-    let mut markdown = String::new();
-    
-    for note in notes {
-        markdown.push_str(&format!("# {}\n\n", note.title));
-        markdown.push_str(&format!("*Created: {}*\n", note.created_at.format("%Y-%m-%d %H:%M:%S")));
-        markdown.push_str(&format!("*Updated: {}*\n\n", note.updated_at.format("%Y-%m-%d %H:%M:%S")));
-        
-        // Parse tags from JSON
-        if let Ok(tags) = serde_json::from_str::<Vec<String>>(&note.tags) {
-            if !tags.is_empty() {
-                markdown.push_str(&format!("*Tags: {}*\n\n", tags.join(", ")));
-            }
-        }
-        
-        markdown.push_str(&note.content);
-        markdown.push_str("\n\n---\n\n");
-    }
-    
-    Ok(markdown)
+// Export notes together with their attachments and images as a zip archive in
+// the app's exports directory, returning the archive path
+#[tauri::command]
+async fn export_notes_archive(
+    note_ids: Vec<String>,
+    secrets: Option<SecretPolicy>,
+    state: State<'_, AppState>,
+) -> AppResult<CheckedExport<String>> {
+    let export = state.store().await?.export_notes_archive(&note_ids, secrets.unwrap_or_default()).await?;
+    Ok(CheckedExport { output: export.output.to_string_lossy().to_string(), findings: export.findings })
 }
 
-fn export_as_json(notes: Vec<Note>) -> Result<String, String> {
-    serde_json::to_string_pretty(&notes)
-        .map_err(|e| format!("Failed to serialize notes to JSON: {}", e))
+// Secrets in the given notes, or in every note when `note_ids` is empty
+#[tauri::command]
+async fn scan_notes(note_ids: Vec<String>, state: State<'_, AppState>) -> AppResult<Vec<NoteFindings>> {
+    state.store().await?.scan_notes_for_secrets(&note_ids).await
 }
 
+// Let the user pick a file to import in a native dialog. The webview only
+// gets a token for it, so it can never name a path to read itself.
 #[tauri::command]
-async fn import_notes(
-    file_path: String,
-    state: State<'_, AppState>,
-) -> Result<Vec<Note>, String> {
-    use std::fs;
-    
-    let content = fs::read_to_string(&file_path)
-        .map_err(|e| format!("Failed to read file: {}", e))?;
-    
-    // Try to parse as JSON first
-    if let Ok(notes) = serde_json::from_str::<Vec<Note>>(&content) {
-        return import_json_notes(notes, state).await;
-    }
-    
-    // Otherwise, treat as markdown
-    import_markdown_content(content, state).await
-}
+async fn pick_import_file(app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<Option<FileGrant>> {
+    state.lock.check()?;
+    let picked = tauri::async_runtime::spawn_blocking(move || {
+        app_handle
+            .dialog()
+            .file()
+            .set_title("Import notes")
+            .add_filter("Notes", &["json", "md", "markdown", "txt"])
+            .blocking_pick_file()
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("The file dialog failed: {}", e)))?;
 
-async fn import_json_notes(
-    notes: Vec<Note>,
-    state: State<'_, AppState>,
-) -> Result<Vec<Note>, String> {
-    let pool = state.db.pool();
-    let mut imported_notes = Vec::new();
-    
-    for note in notes {
-        let new_id = Uuid::new_v4().to_string();
-        let now = Utc::now();
-        
-        let imported_note = sqlx::query_as::<_, Note>(
-            r#"
-            INSERT INTO notes (id, title, content, collection_id, tags, created_at, updated_at, word_count, character_count, is_archived)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
-            RETURNING *
-            "#,
-        )
-        .bind(&new_id)
-        .bind(&note.title)
-        .bind(&note.content)
-        .bind(&note.collection_id)
-        .bind(&note.tags)
-        .bind(&now)
-        .bind(&now)
-        .bind(count_words(&note.content))
-        .bind(count_characters(&note.content))
-        .bind(false)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to import note: {}", e))?;
-        
-        imported_notes.push(imported_note);
+    match picked {
+        Some(picked) => {
+            let path = picked.into_path().map_err(|e| AppError::Validation(format!("Cannot import this file: {}", e)))?;
+            state.import_grants.grant(&path).map(Some)
+        }
+        None => Ok(None),
     }
-    
-    Ok(imported_notes)
 }
 
-async fn import_markdown_content(
-    content: String,
+#[tauri::command]
+async fn import_notes(
+    token: String,
     state: State<'_, AppState>,
-) -> Result<Vec<Note>, String> {
-    let pool = state.db.pool();
-    let mut imported_notes = Vec::new();
-    
-    // Simple markdown parsing - split by horizontal rules
-    let sections: Vec<&str> = content.split("\n---\n").collect();
-    
-    for (i, section) in sections.iter().enumerate() {
-        if section.trim().is_empty() {
-            continue;
-        }
-        
-        let lines: Vec<&str> = section.lines().collect();
-        let title = if let Some(first_line) = lines.first() {
-            first_line.trim_start_matches('#').trim().to_string()
-        } else {
-            format!("Imported Note {}", i + 1)
-        };
-        
-        let content = section.to_string();
-        let id = Uuid::new_v4().to_string();
-        let now = Utc::now();
-        let tags = "[]";
-        
-        let imported_note = sqlx::query_as::<_, Note>(
-            r#"
-            INSERT INTO notes (id, title, content, collection_id, tags, created_at, updated_at, word_count, character_count, is_archived)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
-            RETURNING *
-            "#,
-        )
-        .bind(&id)
-        .bind(&title)
-        .bind(&content)
-        .bind::<Option<String>>(None)
-        .bind(tags)
-        .bind(&now)
-        .bind(&now)
-        .bind(count_words(&content))
-        .bind(count_characters(&content))
-        .bind(false)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to import markdown note: {}", e))?;
-        
-        imported_notes.push(imported_note);
-    }
-    
-    Ok(imported_notes)
+) -> AppResult<Vec<Note>> {
+    let store = state.store().await?;
+    let path = state.import_grants.redeem(&token)?;
+    store.import_notes(&path).await
 }
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
@@ -584,42 +397,8 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
-async fn get_storage_info(state: State<'_, AppState>, app_handle: AppHandle) -> Result<StorageInfo, String> {
-    let pool = state.db.pool();
-    
-    let notes_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM notes")
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to count notes: {}", e))?;
-    
-    let collections_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM collections")
-        .fetch_one(pool)
-        .await
-        .map_err(|e| format!("Failed to count collections: {}", e))?;
-    
-    // Get database file size
-    let db_size = get_database_size(&app_handle).unwrap_or(0);
-    
-    Ok(StorageInfo {
-        total_notes: notes_count.0 as u32,
-        total_collections: collections_count.0 as u32,
-        database_size: db_size,
-        last_backup: None,
-    })
-}
-
-fn get_database_size(app_handle: &AppHandle) -> Result<u64, Box<dyn std::error::Error>> {
-    use std::fs;
-    
-    let app_dir = app_handle.path().app_data_dir()?;
-    let database_path = app_dir.join("notura.db");
-    
-    if database_path.exists() {
-        let metadata = fs::metadata(database_path)?;
-        Ok(metadata.len())
-    } else {
-        Ok(0)
-    }
+async fn get_storage_info(state: State<'_, AppState>) -> AppResult<StorageInfo> {
+    state.store().await?.storage_info().await
 }
 
 // Image management commands
@@ -630,326 +409,886 @@ async fn save_image(
     original_name: String,
     mime_type: String,
     note_id: Option<String>,
-    app_handle: AppHandle,
     state: State<'_, AppState>,
-) -> Result<ImageMetadata, String> {
-    use std::fs;
-    use std::path::Path;
-    
-    let pool = state.db.pool();
-    let id = Uuid::new_v4().to_string();
-    let now = Utc::now();
-    
-    // Create images directory if it doesn't exist
-    let app_dir = app_handle.path().app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
-    let images_dir = app_dir.join("images");
-    fs::create_dir_all(&images_dir)
-        .map_err(|e| format!("Failed to create images directory: {}", e))?;
-    
-    // Generate unique filename
-    let extension = Path::new(&original_name)
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("png");
-    let unique_filename = format!("{}_{}.{}", id, chrono::Utc::now().timestamp(), extension);
-    let file_path = images_dir.join(&unique_filename);
-    
-    // Save file to disk
-    fs::write(&file_path, &file_data)
-        .map_err(|e| format!("Failed to save image file: {}", e))?;
-    
-    // Save metadata to database
-    let image_metadata = sqlx::query_as::<_, ImageMetadata>(
-        r#"
-        INSERT INTO images (id, filename, original_name, file_path, size, mime_type, created_at)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-        RETURNING *
-        "#,
-    )
-    .bind(&id)
-    .bind(&unique_filename)
-    .bind(&original_name)
-    .bind(file_path.to_string_lossy().to_string())
-    .bind(file_data.len() as i64)
-    .bind(&mime_type)
-    .bind(&now)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| format!("Failed to save image metadata: {}", e))?;
-    
-    // If note_id is provided, create the association
-    if let Some(note_id) = note_id {
-        sqlx::query(
-            "INSERT INTO note_images (note_id, image_id, created_at) VALUES (?1, ?2, ?3)"
-        )
-        .bind(&note_id)
-        .bind(&id)
-        .bind(&now)
-        .execute(pool)
+) -> AppResult<ImageMetadata> {
+    state.store().await?
+        .save_image(&file_data, &original_name, &mime_type, note_id.as_deref())
         .await
-        .map_err(|e| format!("Failed to associate image with note: {}", e))?;
-    }
-    
-    Ok(image_metadata)
 }
 
 #[tauri::command]
-async fn get_image(
-    id: String,
-    app_handle: AppHandle,
+async fn get_upload_settings(state: State<'_, AppState>) -> AppResult<UploadSettings> {
+    UploadSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_upload_settings(
+    settings: UploadSettings,
     state: State<'_, AppState>,
-) -> Result<ImageWithData, String> {
-    use std::fs;
-    
-    let pool = state.db.pool();
-    
-    let image_metadata = sqlx::query_as::<_, ImageMetadata>(
-        "SELECT * FROM images WHERE id = ?1"
-    )
-    .bind(&id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| format!("Failed to get image metadata: {}", e))?;
-    
-    // Read file data
-    let file_data = fs::read(&image_metadata.file_path)
-        .map_err(|e| format!("Failed to read image file: {}", e))?;
-    
-    // Convert to base64 for frontend
-    let base64_data = base64::encode(&file_data);
-    let data_url = format!("data:{};base64,{}", image_metadata.mime_type, base64_data);
-    
-    Ok(ImageWithData {
-        id: image_metadata.id,
-        filename: image_metadata.filename,
-        original_name: image_metadata.original_name,
-        file_path: image_metadata.file_path,
-        size: image_metadata.size,
-        mime_type: image_metadata.mime_type,
-        created_at: image_metadata.created_at,
-        data_url,
-    })
+) -> AppResult<UploadSettings> {
+    settings.save(state.store().await?.pool()).await?;
+    Ok(settings)
 }
 
+// Local HTTP API commands
 #[tauri::command]
-async fn get_all_images(state: State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
-    let pool = state.db.pool();
-    
-    let images = sqlx::query_as::<_, ImageMetadata>(
-        "SELECT * FROM images ORDER BY created_at DESC"
-    )
-    .fetch_all(pool)
-    .await
-    .map_err(|e| format!("Failed to get images: {}", e))?;
-    
-    Ok(images)
+async fn get_http_api_settings(state: State<'_, AppState>) -> AppResult<HttpApiSettings> {
+    HttpApiSettings::load(state.store().await?.pool()).await
 }
 
 #[tauri::command]
-async fn get_images_for_note(
-    note_id: String,
+async fn update_http_api_settings(
+    settings: HttpApiSettings,
     state: State<'_, AppState>,
-) -> Result<Vec<ImageMetadata>, String> {
-    let pool = state.db.pool();
-    
-    let images = sqlx::query_as::<_, ImageMetadata>(
-        r#"
-        SELECT i.* FROM images i
-        JOIN note_images ni ON i.id = ni.image_id
-        WHERE ni.note_id = ?1
-        ORDER BY i.created_at DESC
-        "#
-    )
-    .bind(&note_id)
-    .fetch_all(pool)
-    .await
-    .map_err(|e| format!("Failed to get images for note: {}", e))?;
-    
-    Ok(images)
+) -> AppResult<HttpApiSettings> {
+    let store = state.store().await?;
+    settings.save(store.pool()).await?;
+    restart_http_api(&state, &store).await
 }
 
+// Issue a new token; clients using the old one are refused from now on
 #[tauri::command]
-async fn delete_image(
-    id: String,
-    app_handle: AppHandle,
-    state: State<'_, AppState>,
-) -> Result<(), String> {
-    use std::fs;
-    
-    let pool = state.db.pool();
-    
-    // Get image metadata first
-    let image_metadata = sqlx::query_as::<_, ImageMetadata>(
-        "SELECT * FROM images WHERE id = ?1"
-    )
-    .bind(&id)
-    .fetch_one(pool)
-    .await
-    .map_err(|e| format!("Failed to get image metadata: {}", e))?;
-    
-    // Delete file from disk
-    if let Err(e) = fs::remove_file(&image_metadata.file_path) {
-        eprintln!("Warning: Failed to delete image file {}: {}", image_metadata.file_path, e);
+async fn regenerate_http_api_token(state: State<'_, AppState>) -> AppResult<HttpApiSettings> {
+    let store = state.store().await?;
+    HttpApiSettings::regenerate_token(store.pool()).await?;
+    restart_http_api(&state, &store).await
+}
+
+// Stop the running server, if any, and start it again with the saved settings
+// when it is enabled
+async fn restart_http_api(state: &AppState, store: &NoteStore) -> AppResult<HttpApiSettings> {
+    let mut server = state.http_api.lock().await;
+    if let Some(running) = server.take() {
+        running.stop().await;
     }
-    
-    // Delete from database (this will cascade to note_images due to foreign key)
-    let result = sqlx::query("DELETE FROM images WHERE id = ?1")
-        .bind(&id)
-        .execute(pool)
-        .await
-        .map_err(|e| format!("Failed to delete image from database: {}", e))?;
-    
-    if result.rows_affected() == 0 {
-        return Err(format!("Image with id {} not found", id));
+
+    let settings = HttpApiSettings::load(store.pool()).await?;
+    if settings.enabled {
+        *server = Some(ApiServer::start(store.clone(), state.lock.clone(), &settings).await?);
     }
-    
-    Ok(())
+    Ok(settings)
 }
 
+// Markdown mirror commands
 #[tauri::command]
-async fn update_image_note_association(
-    image_id: String,
-    note_id: String,
-    is_used: bool,
+async fn get_mirror_settings(state: State<'_, AppState>) -> AppResult<MirrorSettings> {
+    MirrorSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_mirror_settings(
+    settings: MirrorSettings,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let pool = state.db.pool();
-    let now = Utc::now();
-    
-    if is_used {
-        // Add association if it doesn't exist
-        sqlx::query(
-            r#"
-            INSERT INTO note_images (note_id, image_id, created_at)
-            VALUES (?1, ?2, ?3)
-            ON CONFLICT (note_id, image_id) DO NOTHING
-            "#
-        )
-        .bind(&note_id)
-        .bind(&image_id)
-        .bind(&now)
-        .execute(pool)
-        .await
-        .map_err(|e| format!("Failed to add image association: {}", e))?;
-    } else {
-        // Remove association
-        sqlx::query("DELETE FROM note_images WHERE note_id = ?1 AND image_id = ?2")
-            .bind(&note_id)
-            .bind(&image_id)
-            .execute(pool)
-            .await
-            .map_err(|e| format!("Failed to remove image association: {}", e))?;
+) -> AppResult<MirrorSettings> {
+    let store = state.store().await?;
+    settings.save(store.pool()).await?;
+    restart_mirror(&state, &store).await
+}
+
+#[tauri::command]
+async fn sync_mirror(state: State<'_, AppState>) -> AppResult<MirrorReport> {
+    match &*state.mirror.lock().await {
+        Some(mirror) => mirror.sync_now().await,
+        None => Err(AppError::Validation("The Markdown mirror is not enabled".to_string())),
     }
-    
-    Ok(())
 }
 
-// Data models
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-pub struct ImageMetadata {
-    pub id: String,
-    pub filename: String,
-    pub original_name: String,
-    pub file_path: String,
-    pub size: i64,
-    pub mime_type: String,
-    pub created_at: DateTime<Utc>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ImageWithData {
-    pub id: String,
-    pub filename: String,
-    pub original_name: String,
-    pub file_path: String,
-    pub size: i64,
-    pub mime_type: String,
-    pub created_at: DateTime<Utc>,
-    pub data_url: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-pub struct Note {
-    pub id: String,
-    pub title: String,
-    pub content: String,
-    pub collection_id: Option<String>,
-    pub tags: String, // JSON string
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-    pub word_count: i32,
-    pub character_count: i32,
-    pub is_archived: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-pub struct Collection {
-    pub id: String,
-    pub name: String,
-    pub description: Option<String>,
-    pub parent_id: Option<String>,
-    pub color: Option<String>,
-    pub icon: Option<String>,
-    pub sort_order: i32,
-    pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SearchResult {
-    pub note_id: String,
-    pub title: String,
-    pub excerpt: String,
-    pub highlights: Vec<String>,
-    pub relevance_score: f64,
-    pub last_modified: DateTime<Utc>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SearchFilters {
-    pub collections: Option<Vec<String>>,
-    pub tags: Option<Vec<String>>,
-    pub date_range: Option<DateRange>,
-    pub content_type: Option<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DateRange {
-    pub start: DateTime<Utc>,
-    pub end: DateTime<Utc>,
-}
-
-#[derive(serde::Serialize)]
-struct StorageInfo {
-    total_notes: u32,
-    total_collections: u32,
-    database_size: u64,
-    last_backup: Option<String>,
+async fn restart_mirror(state: &AppState, store: &NoteStore) -> AppResult<MirrorSettings> {
+    let mut mirror = state.mirror.lock().await;
+    if let Some(running) = mirror.take() {
+        running.stop().await;
+    }
+
+    let settings = MirrorSettings::load(store.pool()).await?;
+    if settings.enabled {
+        *mirror = Some(MirrorHandle::start(store.clone(), settings.folder(store.data_dir())).await?);
+    }
+    Ok(settings)
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_sql::Builder::default().build())
-        .setup(|app| {
-            let app_handle = app.handle().clone();
-            
-            tauri::async_runtime::spawn(async move {
-                match Database::new(&app_handle).await {
-                    Ok(db) => {
-                        let state = AppState {
-                            db: Arc::new(db),
-                        };
-                        app_handle.manage(state);
-                        println!("Database initialized successfully");
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to initialize database: {}", e);
-                        std::process::exit(1);
+// Git history and sync commands
+#[tauri::command]
+async fn get_git_sync_settings(state: State<'_, AppState>) -> AppResult<GitSyncSettings> {
+    GitSyncSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_git_sync_settings(
+    settings: GitSyncSettings,
+    state: State<'_, AppState>,
+) -> AppResult<GitSyncSettings> {
+    settings.save(state.store().await?.pool()).await?;
+    Ok(settings)
+}
+
+// Commit the vault to its git repository without pulling or pushing
+#[tauri::command]
+async fn git_commit(state: State<'_, AppState>) -> AppResult<GitSyncReport> {
+    let store = state.store().await?;
+    git_sync::commit(&store, &GitSyncSettings::load(store.pool()).await?).await
+}
+
+#[tauri::command]
+async fn git_sync_now(state: State<'_, AppState>) -> AppResult<GitSyncReport> {
+    let store = state.store().await?;
+    git_sync::sync(&store, &GitSyncSettings::load(store.pool()).await?).await
+}
+
+// WebDAV sync commands
+#[tauri::command]
+async fn get_webdav_settings(state: State<'_, AppState>) -> AppResult<WebDavSettings> {
+    WebDavSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_webdav_settings(
+    settings: WebDavSettings,
+    state: State<'_, AppState>,
+) -> AppResult<WebDavSettings> {
+    settings.save(state.store().await?.pool()).await?;
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn webdav_sync_now(state: State<'_, AppState>) -> AppResult<WebDavSyncReport> {
+    let store = state.store().await?;
+    webdav_sync::sync(&store, &WebDavSettings::load(store.pool()).await?).await
+}
+
+// Collaborative editing commands
+#[tauri::command]
+async fn get_collab_settings(state: State<'_, AppState>) -> AppResult<CollabSettings> {
+    CollabSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_collab_settings(
+    settings: CollabSettings,
+    state: State<'_, AppState>,
+) -> AppResult<CollabSettings> {
+    let store = state.store().await?;
+    settings.save(store.pool()).await?;
+    restart_collab(&state, &store).await
+}
+
+// Issue a new token; collaborators using the old one are disconnected
+#[tauri::command]
+async fn regenerate_collab_token(state: State<'_, AppState>) -> AppResult<CollabSettings> {
+    let store = state.store().await?;
+    CollabSettings::regenerate_token(store.pool()).await?;
+    restart_collab(&state, &store).await
+}
+
+async fn restart_collab(state: &AppState, store: &NoteStore) -> AppResult<CollabSettings> {
+    let mut server = state.collab.lock().await;
+    if let Some(running) = server.take() {
+        running.stop().await;
+    }
+
+    let settings = CollabSettings::load(store.pool()).await?;
+    if settings.enabled {
+        *server = Some(CollabServer::start(store.clone(), state.lock.clone(), &settings).await?);
+    }
+    Ok(settings)
+}
+
+// Co-edit a note on another device's collaboration server; `address` is its
+// `host:port`. The note is created here if this vault does not have it.
+#[tauri::command]
+async fn join_collab_session(
+    address: String,
+    token: String,
+    note_id: String,
+    state: State<'_, AppState>,
+) -> AppResult<()> {
+    let store = state.store().await?;
+    let mut sessions = state.collab_sessions.lock().await;
+    if let Some(previous) = sessions.remove(&note_id) {
+        let _ = previous.leave().await;
+    }
+
+    let session = CollabSession::join((*store).clone(), &address, &token, &note_id).await?;
+    sessions.insert(note_id, session);
+    Ok(())
+}
+
+#[tauri::command]
+async fn leave_collab_session(note_id: String, state: State<'_, AppState>) -> AppResult<()> {
+    match state.collab_sessions.lock().await.remove(&note_id) {
+        Some(session) => session.leave().await,
+        None => Ok(()),
+    }
+}
+
+// Ids of the notes still connected to another device; sessions whose
+// connection ended are dropped
+#[tauri::command]
+async fn get_collab_sessions(state: State<'_, AppState>) -> AppResult<Vec<String>> {
+    let mut sessions = state.collab_sessions.lock().await;
+    sessions.retain(|_, session| session.is_connected());
+    Ok(sessions.keys().cloned().collect())
+}
+
+// Notes changed by collaborators reach the editor as `note-updated` events
+async fn forward_collab_updates(app_handle: AppHandle, store: Arc<NoteStore>) {
+    let mut updates = store.subscribe_document_updates();
+    loop {
+        match updates.recv().await {
+            // The edit is picked up on unlocking
+            Ok(update) if update.origin.is_some() && app_handle.state::<AppState>().lock.status().locked => {}
+            Ok(update) if update.origin.is_some() => match store.get_note(&update.note_id).await {
+                Ok(note) => {
+                    if let Err(e) = app_handle.emit("note-updated", note) {
+                        eprintln!("Warning: Failed to notify frontend of a collaborator's edit: {}", e);
                     }
                 }
+                Err(e) => eprintln!("Warning: Failed to read note {} after a collaborator's edit: {}", update.note_id, e),
+            },
+            Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+// Notes encrypted with their own password. An encrypted note's content is
+// ciphertext until `unlock_note`; after that `get_note` returns its text and
+// `update_note` saves it encrypted, until the note or the app is locked.
+#[tauri::command]
+async fn encrypt_note(id: String, password: String, state: State<'_, AppState>) -> AppResult<Note> {
+    state.store().await?.encrypt_note(&id, &password).await
+}
+
+#[tauri::command]
+async fn unlock_note(id: String, password: String, state: State<'_, AppState>) -> AppResult<Note> {
+    state.store().await?.unlock_note(&id, &password).await
+}
+
+#[tauri::command]
+async fn lock_note(id: String, state: State<'_, AppState>) -> AppResult<()> {
+    state.store().await?.lock_note(&id);
+    Ok(())
+}
+
+#[tauri::command]
+async fn decrypt_note(id: String, password: String, state: State<'_, AppState>) -> AppResult<Note> {
+    state.store().await?.decrypt_note(&id, &password).await
+}
+
+// Master password commands; they work while the app is locked
+#[tauri::command]
+fn get_lock_status(state: State<'_, AppState>) -> LockStatus {
+    state.lock.status()
+}
+
+#[tauri::command]
+fn lock_app(app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    state.lock.lock()?;
+    state.lock_encrypted_notes();
+    notify_locked(&app_handle);
+    Ok(())
+}
+
+#[tauri::command]
+async fn unlock_app(password: String, state: State<'_, AppState>) -> AppResult<()> {
+    let store = state.open_store().await?;
+    state.lock.unlock(store.pool(), &password).await
+}
+
+// Returns the new recovery key, which is only ever shown this once
+#[tauri::command]
+async fn set_master_password(
+    current_password: Option<String>,
+    new_password: String,
+    state: State<'_, AppState>,
+) -> AppResult<String> {
+    let store = state.open_store().await?;
+    let recovery_key = state.lock.set_password(store.pool(), current_password.as_deref(), &new_password).await?;
+    state.lock.set_timeout(LockSettings::load(store.pool()).await?.timeout());
+    Ok(recovery_key)
+}
+
+#[tauri::command]
+async fn remove_master_password(password: String, state: State<'_, AppState>) -> AppResult<()> {
+    let store = state.open_store().await?;
+    state.lock.remove_password(store.pool(), &password).await
+}
+
+// Sets a new password with the recovery key and unlocks; returns the next
+// recovery key
+#[tauri::command]
+async fn recover_master_password(
+    recovery_key: String,
+    new_password: String,
+    state: State<'_, AppState>,
+) -> AppResult<String> {
+    let store = state.open_store().await?;
+    state.lock.recover(store.pool(), &recovery_key, &new_password).await
+}
+
+#[tauri::command]
+async fn get_lock_settings(state: State<'_, AppState>) -> AppResult<LockSettings> {
+    LockSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_lock_settings(settings: LockSettings, state: State<'_, AppState>) -> AppResult<LockSettings> {
+    settings.save(state.store().await?.pool()).await?;
+    state.lock.set_timeout(settings.timeout());
+    Ok(settings)
+}
+
+// Lock after the configured time without activity and tell the frontend, so
+// it hides notes even if the user never touches it again
+async fn lock_when_idle(app_handle: AppHandle) {
+    let mut interval = tokio::time::interval(LOCK_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = app_handle.state::<AppState>();
+        if state.lock.lock_if_idle() {
+            state.lock_encrypted_notes();
+            notify_locked(&app_handle);
+        }
+    }
+}
+
+fn notify_locked(app_handle: &AppHandle) {
+    if let Err(e) = app_handle.emit("app-locked", ()) {
+        eprintln!("Warning: Failed to notify frontend that the app locked: {}", e);
+    }
+}
+
+// Encrypted vault commands. Opening it with the passphrase starts the app as
+// usual; the others rewrite the database and restart, after which it asks
+// for the (new) passphrase.
+#[tauri::command]
+async fn unlock_vault(passphrase: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    let app_dir = app_data_dir(&app_handle)?;
+    state.begin_unlock()?;
+    
+    match NoteStore::open_encrypted(app_dir, &passphrase).await {
+        Err(e @ AppError::Unauthorized(_)) => {
+            state.set(DbState::Encrypted);
+            Err(e)
+        }
+        opened => start_database(&app_handle, opened).await,
+    }
+}
+
+#[tauri::command]
+async fn is_vault_encrypted(state: State<'_, AppState>) -> AppResult<bool> {
+    Ok(state.store().await?.is_encrypted())
+}
+
+// The background tasks are stopped first so nothing writes to the database
+// while it is rewritten. On failure the vault is started again as it is on disk.
+#[tauri::command]
+async fn encrypt_vault(passphrase: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    let store = state.store().await?;
+    state.stop_store_tasks().await;
+    let rewritten = vault::encrypt_vault((*store).clone(), &passphrase).await;
+    finish_vault_rewrite(&app_handle, &store, rewritten).await
+}
+
+#[tauri::command]
+async fn change_vault_passphrase(
+    current_passphrase: String,
+    new_passphrase: String,
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> AppResult<()> {
+    let store = state.store().await?;
+    state.stop_store_tasks().await;
+    let rewritten = vault::change_passphrase((*store).clone(), &current_passphrase, &new_passphrase).await;
+    finish_vault_rewrite(&app_handle, &store, rewritten).await
+}
+
+#[tauri::command]
+async fn decrypt_vault(passphrase: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    let store = state.store().await?;
+    state.stop_store_tasks().await;
+    let rewritten = vault::decrypt_vault((*store).clone(), &passphrase).await;
+    finish_vault_rewrite(&app_handle, &store, rewritten).await
+}
+
+async fn finish_vault_rewrite(app_handle: &AppHandle, previous: &NoteStore, rewritten: AppResult<NoteStore>) -> AppResult<()> {
+    match rewritten {
+        Ok(store) => {
+            store.close().await;
+            app_handle.restart()
+        }
+        Err(e) => {
+            previous.close().await;
+            initialize_database(app_handle).await?;
+            Err(e)
+        }
+    }
+}
+
+// Maintenance commands
+#[tauri::command]
+async fn run_database_maintenance(
+    options: Option<MaintenanceOptions>,
+    state: State<'_, AppState>,
+) -> AppResult<MaintenanceReport> {
+    let options = options.unwrap_or_else(MaintenanceOptions::full);
+    maintenance::run_maintenance(state.store().await?.pool(), &options).await
+}
+
+#[tauri::command]
+async fn rebuild_search_index(state: State<'_, AppState>) -> AppResult<()> {
+    maintenance::rebuild_search_index(state.store().await?.pool()).await
+}
+
+// Backup commands
+#[tauri::command]
+async fn create_backup(state: State<'_, AppState>) -> AppResult<BackupInfo> {
+    let store = state.store().await?;
+    
+    let backup = backup::create_backup(store.pool(), store.data_dir()).await?;
+    
+    let settings = BackupSettings::load(store.pool()).await?;
+    if let Err(e) = backup::prune_backups(store.data_dir(), &settings) {
+        eprintln!("Warning: Failed to remove old backups: {}", e);
+    }
+    
+    Ok(backup)
+}
+
+#[tauri::command]
+async fn list_backups(app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<Vec<BackupInfo>> {
+    state.lock.check()?;
+    let app_dir = app_data_dir(&app_handle)?;
+    backup::list_backups(&app_dir)
+}
+
+#[tauri::command]
+async fn verify_backup(file_name: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<BackupManifest> {
+    state.lock.check()?;
+    let app_dir = app_data_dir(&app_handle)?;
+    let path = backup::resolve_backup(&app_dir, &file_name)?;
+    backup::verify_backup(&path).await
+}
+
+#[tauri::command]
+async fn delete_backup(file_name: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    state.lock.check()?;
+    let app_dir = app_data_dir(&app_handle)?;
+    let path = backup::resolve_backup(&app_dir, &file_name)?;
+    std::fs::remove_file(path)
+        .context("Failed to delete backup")
+}
+
+// Verifies the backup, stages it and restarts; the staged copy replaces the
+// live data on startup, before the database is opened
+#[tauri::command]
+async fn restore_backup(file_name: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    state.lock.check()?;
+    let app_dir = app_data_dir(&app_handle)?;
+    
+    backup::stage_restore(&app_dir, &file_name, false).await?;
+    
+    state.store().await?.close().await;
+    app_handle.restart()
+}
+
+#[tauri::command]
+async fn get_backup_settings(state: State<'_, AppState>) -> AppResult<BackupSettings> {
+    BackupSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_backup_settings(
+    settings: BackupSettings,
+    state: State<'_, AppState>,
+) -> AppResult<BackupSettings> {
+    settings.save(state.store().await?.pool()).await?;
+    Ok(settings)
+}
+
+// Remote backups in an S3-compatible bucket
+#[tauri::command]
+async fn get_s3_backup_settings(state: State<'_, AppState>) -> AppResult<S3BackupSettings> {
+    S3BackupSettings::load(state.store().await?.pool()).await
+}
+
+#[tauri::command]
+async fn update_s3_backup_settings(
+    settings: S3BackupSettings,
+    state: State<'_, AppState>,
+) -> AppResult<S3BackupSettings> {
+    settings.save(state.store().await?.pool()).await?;
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn upload_backup(file_name: String, state: State<'_, AppState>) -> AppResult<RemoteBackup> {
+    let store = state.store().await?;
+    let settings = S3BackupSettings::load(store.pool()).await?;
+    s3_backup::upload_backup(store.data_dir(), &settings, &file_name).await
+}
+
+#[tauri::command]
+async fn list_remote_backups(state: State<'_, AppState>) -> AppResult<Vec<RemoteBackup>> {
+    s3_backup::list_remote_backups(&S3BackupSettings::load(state.store().await?.pool()).await?).await
+}
+
+#[tauri::command]
+async fn delete_remote_backup(file_name: String, state: State<'_, AppState>) -> AppResult<()> {
+    let settings = S3BackupSettings::load(state.store().await?.pool()).await?;
+    s3_backup::delete_remote_backup(&settings, &file_name).await
+}
+
+// Downloads the backup into the local backups, then restores it like
+// `restore_backup`
+#[tauri::command]
+async fn restore_remote_backup(file_name: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    let app_dir = app_data_dir(&app_handle)?;
+    let store = state.store().await?;
+    let settings = S3BackupSettings::load(store.pool()).await?;
+    
+    let backup = s3_backup::download_backup(&app_dir, &settings, &file_name).await?;
+    backup::stage_restore(&app_dir, &backup.file_name, false).await?;
+    
+    store.close().await;
+    app_handle.restart()
+}
+
+#[tauri::command]
+async fn get_image(id: String, state: State<'_, AppState>) -> AppResult<ImageWithData> {
+    state.store().await?.get_image(&id).await
+}
+
+#[tauri::command]
+async fn get_all_images(state: State<'_, AppState>) -> AppResult<Vec<ImageMetadata>> {
+    state.store().await?.get_all_images().await
+}
+
+#[tauri::command]
+async fn get_images_for_note(
+    note_id: String,
+    state: State<'_, AppState>,
+) -> AppResult<Vec<ImageMetadata>> {
+    state.store().await?.get_images_for_note(&note_id).await
+}
+
+#[tauri::command]
+async fn delete_image(id: String, state: State<'_, AppState>) -> AppResult<()> {
+    state.store().await?.delete_image(&id).await
+}
+
+// Overwrites the file before deleting it
+#[tauri::command]
+async fn shred_image(id: String, state: State<'_, AppState>) -> AppResult<ShredResult> {
+    state.store().await?.shred_image(&id).await
+}
+
+#[tauri::command]
+async fn update_image_note_association(
+    image_id: String,
+    note_id: String,
+    is_used: bool,
+    state: State<'_, AppState>,
+) -> AppResult<()> {
+    state.store().await?
+        .update_image_note_association(&image_id, &note_id, is_used)
+        .await
+}
+
+#[tauri::command]
+async fn edit_image(
+    id: String,
+    edits: ImageEditRequest,
+    state: State<'_, AppState>,
+) -> AppResult<ImageMetadata> {
+    state.store().await?.edit_image(&id, edits).await
+}
+
+// Attachment management commands
+#[tauri::command]
+async fn save_attachment(
+    file_data: Vec<u8>,
+    original_name: String,
+    note_id: Option<String>,
+    state: State<'_, AppState>,
+) -> AppResult<AttachmentMetadata> {
+    state.store().await?
+        .save_attachment(&file_data, &original_name, note_id.as_deref())
+        .await
+}
+
+// Re-extract and index the text of every attachment, returning how many had text
+#[tauri::command]
+async fn reindex_attachments(state: State<'_, AppState>) -> AppResult<usize> {
+    state.store().await?.reindex_attachments().await
+}
+
+#[tauri::command]
+async fn get_all_attachments(state: State<'_, AppState>) -> AppResult<Vec<AttachmentMetadata>> {
+    state.store().await?.get_all_attachments().await
+}
+
+#[tauri::command]
+async fn get_attachments_for_note(
+    note_id: String,
+    state: State<'_, AppState>,
+) -> AppResult<Vec<AttachmentMetadata>> {
+    state.store().await?.get_attachments_for_note(&note_id).await
+}
+
+#[tauri::command]
+async fn delete_attachment(id: String, state: State<'_, AppState>) -> AppResult<()> {
+    state.store().await?.delete_attachment(&id).await
+}
+
+#[tauri::command]
+async fn update_attachment_note_association(
+    attachment_id: String,
+    note_id: String,
+    is_used: bool,
+    state: State<'_, AppState>,
+) -> AppResult<()> {
+    state.store().await?.update_attachment_note_association(&attachment_id, &note_id, is_used).await
+}
+
+// Open an attachment with the system's default application for its type
+#[tauri::command]
+async fn open_attachment(
+    id: String,
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> AppResult<()> {
+    use tauri_plugin_opener::OpenerExt;
+    
+    let attachment = state.store().await?.get_attachment(&id).await?;
+    
+    app_handle
+        .opener()
+        .open_path(attachment.file_path, None::<&str>)
+        .map_err(|e| AppError::Io(format!("Failed to open attachment: {}", e)))
+}
+
+// Open the database and start the background jobs, then tell the frontend
+// with a `db-ready` event. On failure the app stays up in recovery mode and a
+// `db-failed` event carries the recovery options. An encrypted vault sends
+// `vault-locked` instead and waits for `unlock_vault`.
+async fn initialize_database(app_handle: &AppHandle) -> AppResult<()> {
+    let opened = match app_data_dir(app_handle) {
+        Ok(app_dir) => NoteStore::open(app_dir).await.map_err(AppError::from),
+        Err(e) => Err(e),
+    };
+    start_database(app_handle, opened).await
+}
+
+async fn start_database(app_handle: &AppHandle, opened: AppResult<NoteStore>) -> AppResult<()> {
+    let state = app_handle.state::<AppState>();
+    
+    // The lock state is read before anything can serve notes. If it can't be
+    // read the app stays locked and goes to recovery mode.
+    let opened = match opened {
+        Ok(store) => match state.lock.load(store.pool()).await {
+            Ok(()) => Ok(store),
+            Err(e) => {
+                store.close().await;
+                Err(AppError::Database(format!("Failed to read the master password settings: {}", e)))
+            }
+        },
+        Err(e) => Err(e),
+    };
+    
+    match opened {
+        Ok(store) => {
+            state.spawn_task(backup::run_backup_scheduler(store.pool().clone(), store.data_dir().to_path_buf()));
+            state.spawn_task(maintenance::run_idle_maintenance_scheduler(store.pool().clone()));
+            state.spawn_task(git_sync::run_git_sync_scheduler(store.clone()));
+            state.spawn_task(webdav_sync::run_webdav_sync_scheduler(store.clone()));
+            
+            if let Err(e) = restart_http_api(&state, &store).await {
+                eprintln!("Warning: Failed to start the local API: {}", e);
+            }
+            if let Err(e) = restart_collab(&state, &store).await {
+                eprintln!("Warning: Failed to start the collaboration server: {}", e);
+            }
+            
+            state.spawn_task(lock_when_idle(app_handle.clone()));
+            
+            let store = Arc::new(store);
+            state.spawn_task(forward_collab_updates(app_handle.clone(), store.clone()));
+            state.set(DbState::Ready(store.clone()));
+            println!("Database initialized successfully");
+            
+            // The first mirror sync can take a while, so it runs after startup
+            let mirror_handle = app_handle.clone();
+            state.spawn_task(async move {
+                if let Err(e) = restart_mirror(&mirror_handle.state::<AppState>(), &store).await {
+                    eprintln!("Warning: Failed to start the Markdown mirror: {}", e);
+                }
+            });
+            if let Err(e) = app_handle.emit("db-ready", state.status()) {
+                eprintln!("Warning: Failed to notify frontend that the database is ready: {}", e);
+            }
+            Ok(())
+        }
+        Err(AppError::Locked(_)) => {
+            state.set(DbState::Encrypted);
+            if let Err(e) = app_handle.emit("vault-locked", state.status()) {
+                eprintln!("Warning: Failed to notify frontend that the vault is encrypted: {}", e);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            let message = e.to_string();
+            state.set(DbState::Failed(message));
+            if let Err(e) = app_handle.emit("db-failed", get_recovery_info(app_handle.clone(), state)?) {
+                eprintln!("Warning: Failed to notify frontend of recovery mode: {}", e);
+            }
+            Err(e)
+        }
+    }
+}
+
+// Lets the frontend catch up on the lifecycle if it missed the events
+#[tauri::command]
+fn get_app_status(state: State<'_, AppState>) -> AppStatus {
+    state.status()
+}
+
+#[derive(Clone, serde::Serialize)]
+struct RecoveryInfo {
+    in_recovery: bool,
+    error: Option<String>,
+    database_path: String,
+    backups: Vec<BackupInfo>,
+}
+
+// Recovery commands, usable only while the database failed to open
+#[tauri::command]
+fn get_recovery_info(app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<RecoveryInfo> {
+    let app_dir = app_data_dir(&app_handle)?;
+    let error = state.failure();
+    
+    Ok(RecoveryInfo {
+        in_recovery: error.is_some(),
+        error,
+        database_path: recovery::database_path(&app_dir).to_string_lossy().to_string(),
+        backups: backup::list_backups(&app_dir).unwrap_or_default(),
+    })
+}
+
+// Run a recovery action and reopen the database. If the action itself fails
+// the app stays in recovery mode with the original error.
+async fn recover_with<T>(
+    app_handle: &AppHandle,
+    action: impl std::future::Future<Output = AppResult<T>>,
+) -> AppResult<T> {
+    let state = app_handle.state::<AppState>();
+    let original_error = state.begin_recovery()?;
+    
+    match action.await {
+        Ok(value) => {
+            initialize_database(app_handle).await?;
+            Ok(value)
+        }
+        Err(e) => {
+            state.set(DbState::Failed(original_error));
+            Err(e)
+        }
+    }
+}
+
+#[tauri::command]
+// With `reset_location` the backup goes to the default location instead of
+// where the database was opened from
+async fn recovery_restore_backup(file_name: String, reset_location: Option<bool>, app_handle: AppHandle) -> AppResult<()> {
+    let app_dir = app_data_dir(&app_handle)?;
+    
+    // Opening the database applies the staged restore first
+    recover_with(&app_handle, async {
+        backup::stage_restore(&app_dir, &file_name, reset_location.unwrap_or(false)).await.map(|_| ())
+    })
+    .await
+}
+
+// Let the user pick another database file in a native dialog, in recovery
+// mode only. Like imports, the webview gets a token rather than a path.
+#[tauri::command]
+async fn recovery_pick_database(app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<Option<FileGrant>> {
+    if state.failure().is_none() {
+        return Err(AppError::Conflict { message: "The database is not in recovery mode".to_string(), id: None });
+    }
+    let picked = tauri::async_runtime::spawn_blocking(move || {
+        app_handle
+            .dialog()
+            .file()
+            .set_title("Open database")
+            .add_filter("Databases", &["db", "sqlite", "sqlite3"])
+            .blocking_pick_file()
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("The file dialog failed: {}", e)))?;
+
+    match picked {
+        Some(picked) => {
+            let path = picked.into_path().map_err(|e| AppError::Validation(format!("Cannot open this file: {}", e)))?;
+            state.database_grants.grant(&path).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+// Open the database picked with `recovery_pick_database`
+#[tauri::command]
+async fn recovery_open_database(token: String, app_handle: AppHandle, state: State<'_, AppState>) -> AppResult<()> {
+    let app_dir = app_data_dir(&app_handle)?;
+    let path = state.database_grants.redeem(&token)?;
+    let previous = recovery::database_path(&app_dir);
+    
+    let result = recover_with(&app_handle, async {
+        recovery::validate_database_file(&path)?;
+        recovery::set_database_location(&app_dir, Some(&path))
+    })
+    .await;
+    
+    if result.is_err() && recovery::database_path(&app_dir) == path {
+        // Keep pointing at the original database rather than the one that failed
+        recovery::set_database_location(&app_dir, Some(&previous))?;
+    }
+    result
+}
+
+#[tauri::command]
+async fn recovery_repair_database(app_handle: AppHandle) -> AppResult<RepairReport> {
+    let app_dir = app_data_dir(&app_handle)?;
+    
+    recover_with(&app_handle, recovery::repair_database(&recovery::database_path(&app_dir))).await
+}
+
+// Moves the broken database aside and starts with an empty one; returns where
+// the old files went
+#[tauri::command]
+async fn recovery_start_fresh(app_handle: AppHandle) -> AppResult<String> {
+    let app_dir = app_data_dir(&app_handle)?;
+    
+    recover_with(&app_handle, async {
+        let aside_dir = recovery::move_database_aside(&recovery::database_path(&app_dir))?;
+        Ok(aside_dir.to_string_lossy().to_string())
+    })
+    .await
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_sql::Builder::default().build())
+        .setup(|app| {
+            app.manage(AppState::new());
+            let app_handle = app.handle().clone();
+            
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = initialize_database(&app_handle).await {
+                    eprintln!("Failed to initialize database, starting in recovery mode: {}", e);
+                }
             });
             
             Ok(())
@@ -960,7 +1299,12 @@ pub fn run() {
             create_note,
             update_note,
             delete_note,
+            shred_note,
             get_note,
+            encrypt_note,
+            unlock_note,
+            lock_note,
+            decrypt_note,
             get_all_notes,
             create_collection,
             update_collection,
@@ -972,13 +1316,81 @@ pub fn run() {
             get_recent_searches,
             save_recent_search,
             export_notes,
+            scan_notes,
+            export_notes_archive,
+            pick_import_file,
             import_notes,
             save_image,
             get_image,
             get_all_images,
             get_images_for_note,
             delete_image,
-            update_image_note_association
+            shred_image,
+            update_image_note_association,
+            edit_image,
+            get_upload_settings,
+            update_upload_settings,
+            get_http_api_settings,
+            update_http_api_settings,
+            regenerate_http_api_token,
+            get_mirror_settings,
+            update_mirror_settings,
+            sync_mirror,
+            get_git_sync_settings,
+            update_git_sync_settings,
+            git_commit,
+            git_sync_now,
+            get_webdav_settings,
+            update_webdav_settings,
+            webdav_sync_now,
+            get_collab_settings,
+            update_collab_settings,
+            regenerate_collab_token,
+            join_collab_session,
+            leave_collab_session,
+            get_collab_sessions,
+            save_attachment,
+            get_all_attachments,
+            get_attachments_for_note,
+            delete_attachment,
+            update_attachment_note_association,
+            open_attachment,
+            reindex_attachments,
+            create_backup,
+            list_backups,
+            verify_backup,
+            delete_backup,
+            restore_backup,
+            get_backup_settings,
+            update_backup_settings,
+            get_lock_status,
+            lock_app,
+            unlock_app,
+            set_master_password,
+            remove_master_password,
+            recover_master_password,
+            get_lock_settings,
+            update_lock_settings,
+            unlock_vault,
+            is_vault_encrypted,
+            encrypt_vault,
+            change_vault_passphrase,
+            decrypt_vault,
+            get_s3_backup_settings,
+            update_s3_backup_settings,
+            upload_backup,
+            list_remote_backups,
+            delete_remote_backup,
+            restore_remote_backup,
+            run_database_maintenance,
+            rebuild_search_index,
+            get_app_status,
+            get_recovery_info,
+            recovery_restore_backup,
+            recovery_pick_database,
+            recovery_open_database,
+            recovery_repair_database,
+            recovery_start_fresh
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
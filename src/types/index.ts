@@ -10,6 +10,8 @@ export interface Note {
   wordCount: number;
   characterCount: number;
   isArchived: boolean;
+  // Content is ciphertext until the note is unlocked with its password
+  isEncrypted?: boolean;
 }
 
 export interface Collection {
@@ -28,10 +30,13 @@ export interface Collection {
 export interface SearchResult {
   noteId: string;
   title: string;
+  // HTML: escaped note text with matches in <mark>, and no other tags
   excerpt: string;
+  // Plain text, never render as HTML
   highlights: string[];
   relevanceScore: number;
   lastModified: Date;
+  matchedAttachment?: string;
 }
 
 export interface SearchFilters {
@@ -52,6 +57,177 @@ export interface StorageInfo {
   lastBackup?: Date;
 }
 
+// The item is deleted either way; warnings say what is still on disk for now
+export interface ShredResult {
+  warnings: string[];
+}
+
+// Secrets found in notes; `preview` is masked, never the secret itself
+export type SecretKind = 'api_key' | 'private_key' | 'password' | 'email' | 'card_number';
+
+export interface NoteFindings {
+  note_id: string;
+  // Redacted when the title holds findings
+  title: string;
+  findings: { kind: SecretKind; in_title: boolean; line: number; preview: string }[];
+}
+
+// What an export does when it finds secrets; `block` is the default and
+// rejects with code `validation`
+export type SecretPolicy = 'block' | 'warn' | 'redact';
+
+export interface CheckedExport {
+  output: string;
+  findings: NoteFindings[];
+}
+
+// A file picked for import; only the token reaches the backend
+export interface FileGrant {
+  token: string;
+  file_name: string;
+  size: number;
+}
+
+// Local HTTP API; the token is generated on first load
+export interface HttpApiSettings {
+  enabled: boolean;
+  port: number;
+  token: string;
+}
+
+// Markdown mirror; an empty path means `mirror/` in the app data directory
+export interface MirrorSettings {
+  enabled: boolean;
+  path: string;
+  // Copy notes out in plain text even from an encrypted vault
+  allow_unencrypted?: boolean;
+}
+
+export interface MirrorReport {
+  written: number;
+  imported: number;
+  created: number;
+  removed: number;
+  conflicts: { note_id: string; path: string; copy_path: string }[];
+}
+
+// Git history and sync; an empty path means `git/` in the app data directory
+export interface GitSyncSettings {
+  enabled: boolean;
+  path: string;
+  remote: string;
+  branch: string;
+  interval_minutes: number;
+  // Copy notes out in plain text even from an encrypted vault
+  allow_unencrypted?: boolean;
+}
+
+export interface GitSyncReport {
+  commits: { id: string; message: string }[];
+  pulled: boolean;
+  pushed: boolean;
+  imported: number;
+  created: number;
+  deleted: number;
+  conflicts: { note_id: string; path: string; copy_path: string }[];
+}
+
+// Sync with other devices through a WebDAV folder; an empty device name
+// means the host name
+export interface WebDavSettings {
+  enabled: boolean;
+  url: string;
+  username: string;
+  password: string;
+  interval_minutes: number;
+  device_name: string;
+  // Copy notes out in plain text even from an encrypted vault
+  allow_unencrypted?: boolean;
+}
+
+export interface WebDavSyncReport {
+  pushed: number;
+  pulled: number;
+  conflicts: {
+    entity: 'note' | 'collection' | 'image';
+    id: string;
+    kept: 'local' | 'remote';
+    copy_id: string | null;
+  }[];
+}
+
+// Co-editing notes with other devices on the network. Collaborators join
+// at `host:port` with the token.
+export interface CollabSettings {
+  enabled: boolean;
+  port: number;
+  token: string;
+}
+
+// A change to a note's text; offsets are JavaScript string indices and each
+// edit applies to the text left by the one before
+export interface TextEdit {
+  index: number;
+  delete?: number;
+  insert?: string;
+}
+
+// Encrypted backups in an S3-compatible bucket; `passphrase` is needed to
+// restore them
+export interface S3BackupSettings {
+  enabled: boolean;
+  endpoint: string;
+  region: string;
+  bucket: string;
+  prefix: string;
+  path_style: boolean;
+  access_key_id: string;
+  secret_access_key: string;
+  passphrase: string;
+  keep_daily: number;
+  keep_weekly: number;
+  keep_monthly: number;
+}
+
+export interface RemoteBackup {
+  file_name: string;
+  key: string;
+  created_at: string;
+  size: number;
+}
+
+// Master password lock. While locked, commands reject with code `locked`
+// and an `app-locked` event is sent when the app locks by itself.
+export interface LockStatus {
+  enabled: boolean;
+  locked: boolean;
+}
+
+// 0 locks only on startup
+export interface LockSettings {
+  timeout_minutes: number;
+}
+
+// Error rejected by backend commands; match on `code`, show `message`
+export type CommandErrorCode =
+  | 'not_found'
+  | 'conflict'
+  | 'validation'
+  | 'storage_full'
+  | 'io'
+  | 'database'
+  | 'unavailable'
+  | 'unauthorized'
+  | 'locked'
+  | 'internal';
+
+export interface CommandError {
+  code: CommandErrorCode;
+  message: string;
+  entity?: string;
+  id?: string;
+}
+
 // Theme configuration types
 export type ThemeName = 'cyber-amber' | 'zen-paper';
 
@@ -134,10 +310,21 @@ export interface ApiResponse<T> {
 export interface TauriCommands {
   // Note operations
   create_note: (title: string, content: string, collectionId?: string) => Promise<Note>;
-  update_note: (id: string, content: string) => Promise<Note>;
+  update_note: (id: string, content: string, edits?: TextEdit[]) => Promise<Note>;
   delete_note: (id: string) => Promise<void>;
+  // Deletes and also scrubs the text out of the database files
+  shred_note: (id: string) => Promise<ShredResult>;
+  shred_image: (id: string) => Promise<ShredResult>;
   get_note: (id: string) => Promise<Note>;
   get_all_notes: () => Promise<Note[]>;
+
+  // Notes with their own password. Unlocked notes come from `get_note` with
+  // their text and are saved with `update_note` without `edits`, until
+  // `lock_note` or the app locks.
+  encrypt_note: (id: string, password: string) => Promise<Note>;
+  unlock_note: (id: string, password: string) => Promise<Note>;
+  lock_note: (id: string) => Promise<void>;
+  decrypt_note: (id: string, password: string) => Promise<Note>;
   
   // Collection operations
   create_collection: (name: string, description?: string, parentId?: string) => Promise<Collection>;
@@ -150,11 +337,74 @@ export interface TauriCommands {
   search_notes: (query: string, filters: SearchFilters) => Promise<SearchResult[]>;
   
   // File operations
-  export_notes: (format: ExportFormat, noteIds: string[]) => Promise<string>;
-  import_notes: (filePath: string) => Promise<Note[]>;
+  export_notes: (format: ExportFormat, noteIds: string[], secrets?: SecretPolicy) => Promise<CheckedExport>;
+  export_notes_archive: (noteIds: string[], secrets?: SecretPolicy) => Promise<CheckedExport>;
+  // An empty list scans every note
+  scan_notes: (noteIds: string[]) => Promise<NoteFindings[]>;
+  // Opens a native file dialog; null when it was cancelled. The token imports
+  // the picked file once, within a few minutes.
+  pick_import_file: () => Promise<FileGrant | null>;
+  import_notes: (token: string) => Promise<Note[]>;
   
   // Storage operations
   get_storage_info: () => Promise<StorageInfo>;
+
+  // Local HTTP API
+  get_http_api_settings: () => Promise<HttpApiSettings>;
+  update_http_api_settings: (settings: HttpApiSettings) => Promise<HttpApiSettings>;
+  regenerate_http_api_token: () => Promise<HttpApiSettings>;
+
+  // Markdown mirror
+  get_mirror_settings: () => Promise<MirrorSettings>;
+  update_mirror_settings: (settings: MirrorSettings) => Promise<MirrorSettings>;
+  sync_mirror: () => Promise<MirrorReport>;
+
+  // Git history and sync
+  get_git_sync_settings: () => Promise<GitSyncSettings>;
+  update_git_sync_settings: (settings: GitSyncSettings) => Promise<GitSyncSettings>;
+  git_commit: () => Promise<GitSyncReport>;
+  git_sync_now: () => Promise<GitSyncReport>;
+  get_webdav_settings: () => Promise<WebDavSettings>;
+  update_webdav_settings: (settings: WebDavSettings) => Promise<WebDavSettings>;
+  webdav_sync_now: () => Promise<WebDavSyncReport>;
+
+  // Collaborative editing; notes changed by collaborators arrive as
+  // `note-updated` events
+  get_collab_settings: () => Promise<CollabSettings>;
+  update_collab_settings: (settings: CollabSettings) => Promise<CollabSettings>;
+  regenerate_collab_token: () => Promise<CollabSettings>;
+  join_collab_session: (address: string, token: string, noteId: string) => Promise<void>;
+  leave_collab_session: (noteId: string) => Promise<void>;
+  get_collab_sessions: () => Promise<string[]>;
+
+  // Master password; the set and recover commands return the new recovery
+  // key, shown to the user once
+  get_lock_status: () => Promise<LockStatus>;
+  lock_app: () => Promise<void>;
+  unlock_app: (password: string) => Promise<void>;
+  set_master_password: (currentPassword: string | null, newPassword: string) => Promise<string>;
+  remove_master_password: (password: string) => Promise<void>;
+  recover_master_password: (recoveryKey: string, newPassword: string) => Promise<string>;
+  get_lock_settings: () => Promise<LockSettings>;
+  update_lock_settings: (settings: LockSettings) => Promise<LockSettings>;
+
+  // Encrypted vault. An encrypted vault starts with app status `encrypted`
+  // and a `vault-locked` event, and opens with `unlock_vault`; the other
+  // commands restart the app once the database is rewritten.
+  unlock_vault: (passphrase: string) => Promise<void>;
+  is_vault_encrypted: () => Promise<boolean>;
+  encrypt_vault: (passphrase: string) => Promise<void>;
+  change_vault_passphrase: (currentPassphrase: string, newPassphrase: string) => Promise<void>;
+  decrypt_vault: (passphrase: string) => Promise<void>;
+
+  // Remote backups; `upload_backup` and `restore_remote_backup` take a
+  // backup's file name
+  get_s3_backup_settings: () => Promise<S3BackupSettings>;
+  update_s3_backup_settings: (settings: S3BackupSettings) => Promise<S3BackupSettings>;
+  upload_backup: (fileName: string) => Promise<RemoteBackup>;
+  list_remote_backups: () => Promise<RemoteBackup[]>;
+  delete_remote_backup: (fileName: string) => Promise<void>;
+  restore_remote_backup: (fileName: string) => Promise<void>;
 }
 
 // Utility types